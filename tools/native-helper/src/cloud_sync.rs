@@ -0,0 +1,434 @@
+//! Project sync through WebDAV
+//!
+//! `sync_project` keeps a project file in step with a copy on a WebDAV
+//! server (Nextcloud, ownCloud, most NAS boxes), so a project can move
+//! between a laptop and a desktop without copying `.msp` files by hand.
+//! Only the project file is synced, never its media. The server is set in
+//! `[sync] webdav_url` (a collection URL) and `username`; the password,
+//! ideally an app password, comes from `MASTERSELECTS_WEBDAV_PASSWORD`.
+//!
+//! For every project the SHA-256 of the local file and the server's ETag as
+//! of the last sync are kept in
+//! ```text
+//! {data_local_dir}/MasterSelects/sync-state.json
+//! ```
+//! Comparing them with the current values tells which side changed since.
+//! When both did, nothing is overwritten: the result is `conflict` until the
+//! editor syncs again with `resolve: "local"` or `"remote"`. Taking the
+//! remote copy keeps the local file as `<name>.conflict-<time>.<ext>`.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::config;
+
+const PASSWORD_ENV: &str = "MASTERSELECTS_WEBDAV_PASSWORD";
+const USER_AGENT: &str = "MasterSelects-Helper";
+/// Projects are small; anything bigger is not a project file
+const MAX_PROJECT_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Which side wins a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolve {
+    Local,
+    Remote,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SyncError {
+    NotConfigured,
+    /// `remote_name` would leave the configured collection
+    InvalidName(String),
+    Failed(String),
+}
+
+impl From<String> for SyncError {
+    fn from(message: String) -> Self {
+        SyncError::Failed(message)
+    }
+}
+
+/// Outcome of one `sync_project`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SyncResult {
+    /// `up_to_date`, `uploaded`, `downloaded` or `conflict`
+    pub status: &'static str,
+    pub path: PathBuf,
+    pub remote_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// Where the local file was kept when the remote copy replaced it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+}
+
+/// State of a project after its last sync
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SyncRecord {
+    remote_url: String,
+    etag: String,
+    sha256: String,
+    synced_at: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    UpToDate,
+    Upload,
+    Download,
+    Conflict,
+}
+
+/// What to do given the last synced state and the current hash and ETag.
+fn decide(
+    base: Option<&SyncRecord>,
+    local: Option<&str>,
+    remote: Option<&str>,
+    resolve: Option<Resolve>,
+) -> Action {
+    let (local, remote) = match (local, remote) {
+        (Some(local), Some(remote)) => (local, remote),
+        (Some(_), None) => return Action::Upload,
+        _ => return Action::Download,
+    };
+    let local_changed = base.is_none_or(|b| b.sha256 != local);
+    let remote_changed = base.is_none_or(|b| b.etag != remote);
+    match (local_changed, remote_changed, resolve) {
+        (false, false, _) => Action::UpToDate,
+        (true, false, _) | (true, true, Some(Resolve::Local)) => Action::Upload,
+        (false, true, _) | (true, true, Some(Resolve::Remote)) => Action::Download,
+        (true, true, None) => Action::Conflict,
+    }
+}
+
+struct Remote {
+    url: String,
+    authorization: String,
+    agent: ureq::Agent,
+}
+
+/// Percent-encode one path segment.
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// URL of `name` (a `/`-separated path) inside the collection at `base`.
+/// `.` and `..` segments are refused so a name can't reach outside it.
+fn remote_url(base: &str, name: &str) -> Result<String, SyncError> {
+    let segments: Vec<&str> = name.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() || segments.iter().any(|s| *s == "." || *s == "..") {
+        return Err(SyncError::InvalidName(format!("Invalid remote name: {}", name)));
+    }
+    let path: Vec<String> = segments.into_iter().map(encode_segment).collect();
+    Ok(format!("{}/{}", base.trim_end_matches('/'), path.join("/")))
+}
+
+fn etag_of(resp: &ureq::Response) -> Option<String> {
+    resp.header("ETag").map(str::to_string)
+}
+
+impl Remote {
+    fn new(name: &str) -> Result<Self, SyncError> {
+        let settings = config::current().sync;
+        let base = settings.webdav_url.filter(|u| !u.is_empty()).ok_or(SyncError::NotConfigured)?;
+        // Basic auth sends the password as-is, so never over plain HTTP
+        if !base.to_ascii_lowercase().starts_with("https://") {
+            return Err(SyncError::Failed(format!(
+                "[sync] webdav_url must be an https:// URL: {}",
+                base
+            )));
+        }
+        let password = std::env::var(PASSWORD_ENV).unwrap_or_default();
+        let credentials = format!("{}:{}", settings.username.unwrap_or_default(), password);
+        Ok(Self {
+            url: remote_url(&base, name)?,
+            authorization: format!("Basic {}", BASE64.encode(credentials)),
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(15))
+                .timeout_read(Duration::from_secs(120))
+                .user_agent(USER_AGENT)
+                .build(),
+        })
+    }
+
+    fn error(&self, error: ureq::Error) -> SyncError {
+        SyncError::Failed(match error {
+            ureq::Error::Status(401, _) | ureq::Error::Status(403, _) => {
+                format!("The WebDAV server refused the credentials for {}", self.url)
+            }
+            ureq::Error::Status(code, _) => format!("WebDAV request to {} failed with HTTP {}", self.url, code),
+            e => format!("WebDAV request to {} failed: {}", self.url, e),
+        })
+    }
+
+    /// ETag of the remote file, `None` when it does not exist.
+    fn etag(&self) -> Result<Option<String>, SyncError> {
+        match self.agent.head(&self.url).set("Authorization", &self.authorization).call() {
+            Ok(resp) => etag_of(&resp)
+                .map(Some)
+                .ok_or_else(|| SyncError::Failed(format!("The server sent no ETag for {}", self.url))),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+
+    fn download(&self) -> Result<(Vec<u8>, String), SyncError> {
+        let resp = self
+            .agent
+            .get(&self.url)
+            .set("Authorization", &self.authorization)
+            .call()
+            .map_err(|e| self.error(e))?;
+        let etag = etag_of(&resp);
+        let mut body = Vec::new();
+        resp.into_reader()
+            .take(MAX_PROJECT_BYTES + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| format!("Cannot read {}: {}", self.url, e))?;
+        if body.len() as u64 > MAX_PROJECT_BYTES {
+            return Err(SyncError::Failed(format!("{} is too large for a project file", self.url)));
+        }
+        let etag = match etag {
+            Some(etag) => etag,
+            None => self.etag()?.unwrap_or_default(),
+        };
+        Ok((body, etag))
+    }
+
+    /// Upload `data` if the remote file is still at `expected` (or absent
+    /// when `None`). `Ok(None)` means it changed in the meantime.
+    fn upload(&self, data: &[u8], expected: Option<&str>) -> Result<Option<String>, SyncError> {
+        let request = self.agent.put(&self.url).set("Authorization", &self.authorization);
+        let request = match expected {
+            Some(etag) => request.set("If-Match", etag),
+            None => request.set("If-None-Match", "*"),
+        };
+        match request.send_bytes(data) {
+            Ok(resp) => match etag_of(&resp) {
+                Some(etag) => Ok(Some(etag)),
+                None => self.etag(),
+            },
+            Err(ureq::Error::Status(412, _)) => Ok(None),
+            Err(e) => Err(self.error(e)),
+        }
+    }
+}
+
+fn state_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("sync-state.json")
+}
+
+fn load_state() -> HashMap<PathBuf, SyncRecord> {
+    std::fs::read_to_string(state_path())
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn save_record(project: &Path, record: SyncRecord) -> Result<(), String> {
+    let mut state = load_state();
+    state.insert(project.to_path_buf(), record);
+    let path = state_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(&state).map_err(|e| e.to_string())?;
+    std::fs::write(&path, json).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `Film.msp` -> `Film.conflict-1760000000.msp`
+fn backup_path(project: &Path) -> PathBuf {
+    let stem = project.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match project.extension() {
+        Some(ext) => format!("{}.conflict-{}.{}", stem, now_secs(), ext.to_string_lossy()),
+        None => format!("{}.conflict-{}", stem, now_secs()),
+    };
+    project.with_file_name(name)
+}
+
+fn write_local(project: &Path, data: &[u8]) -> Result<(), String> {
+    let tmp = project.with_extension("sync.tmp");
+    std::fs::write(&tmp, data).map_err(|e| format!("Cannot write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, project).map_err(|e| {
+        let _ = std::fs::remove_file(&tmp);
+        format!("Cannot replace {}: {}", project.display(), e)
+    })
+}
+
+/// Sync `project` with `remote_name` (default: its file name) in the
+/// configured WebDAV collection. Blocking.
+pub fn sync_project(
+    project: &Path,
+    remote_name: Option<&str>,
+    resolve: Option<Resolve>,
+) -> Result<SyncResult, SyncError> {
+    let default_name = project.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let remote = Remote::new(remote_name.unwrap_or(&default_name))?;
+
+    let local = match std::fs::read(project) {
+        Ok(data) => Some(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(SyncError::Failed(format!("Cannot read {}: {}", project.display(), e))),
+    };
+    let local_hash = local.as_deref().map(sha256);
+    let remote_etag = remote.etag()?;
+    if local.is_none() && remote_etag.is_none() {
+        return Err(SyncError::Failed(format!(
+            "Neither {} nor {} exists",
+            project.display(),
+            remote.url
+        )));
+    }
+
+    // A record for another remote file says nothing about this one
+    let base = load_state()
+        .remove(project)
+        .filter(|record| record.remote_url == remote.url);
+    let mut action = decide(base.as_ref(), local_hash.as_deref(), remote_etag.as_deref(), resolve);
+
+    let mut downloaded = None;
+    if action == Action::Conflict && base.is_none() {
+        // First sync with both sides present: identical content is no conflict
+        let (data, etag) = remote.download()?;
+        if Some(sha256(&data)) == local_hash {
+            action = Action::UpToDate;
+        }
+        downloaded = Some((data, etag));
+    }
+
+    let result = |status, etag: Option<String>, backup| SyncResult {
+        status,
+        path: project.to_path_buf(),
+        remote_url: remote.url.clone(),
+        etag,
+        backup,
+    };
+    let record = |etag: &str, sha256: String| SyncRecord {
+        remote_url: remote.url.clone(),
+        etag: etag.to_string(),
+        sha256,
+        synced_at: now_secs(),
+    };
+
+    match action {
+        Action::UpToDate => {
+            let etag = remote_etag.unwrap_or_default();
+            save_record(project, record(&etag, local_hash.unwrap_or_default()))?;
+            Ok(result("up_to_date", Some(etag), None))
+        }
+        Action::Conflict => Ok(result("conflict", remote_etag, None)),
+        Action::Upload => {
+            let data = local.unwrap_or_default();
+            match remote.upload(&data, remote_etag.as_deref())? {
+                Some(etag) => {
+                    save_record(project, record(&etag, sha256(&data)))?;
+                    info!("Uploaded {} to {}", project.display(), remote.url);
+                    Ok(result("uploaded", Some(etag), None))
+                }
+                // Someone uploaded between our check and the upload
+                None => Ok(result("conflict", remote.etag()?, None)),
+            }
+        }
+        Action::Download => {
+            let (data, etag) = match downloaded {
+                Some(downloaded) => downloaded,
+                None => remote.download()?,
+            };
+            let local_changed = base
+                .as_ref()
+                .is_none_or(|b| Some(&b.sha256) != local_hash.as_ref());
+            let backup = if local.is_some() && local_changed {
+                let backup = backup_path(project);
+                std::fs::copy(project, &backup)
+                    .map_err(|e| format!("Cannot keep {}: {}", backup.display(), e))?;
+                Some(backup)
+            } else {
+                None
+            };
+            write_local(project, &data)?;
+            save_record(project, record(&etag, sha256(&data)))?;
+            info!("Downloaded {} from {}", project.display(), remote.url);
+            Ok(result("downloaded", Some(etag), backup))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> SyncRecord {
+        SyncRecord {
+            remote_url: "https://dav/Film.msp".to_string(),
+            etag: "\"e1\"".to_string(),
+            sha256: "h1".to_string(),
+            synced_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let base = base();
+        let b = Some(&base);
+        assert_eq!(decide(b, Some("h1"), Some("\"e1\""), None), Action::UpToDate);
+        assert_eq!(decide(b, Some("h2"), Some("\"e1\""), None), Action::Upload);
+        assert_eq!(decide(b, Some("h1"), Some("\"e2\""), None), Action::Download);
+        assert_eq!(decide(b, Some("h2"), Some("\"e2\""), None), Action::Conflict);
+        assert_eq!(decide(b, Some("h2"), Some("\"e2\""), Some(Resolve::Local)), Action::Upload);
+        assert_eq!(decide(b, Some("h2"), Some("\"e2\""), Some(Resolve::Remote)), Action::Download);
+        assert_eq!(decide(None, Some("h1"), None, None), Action::Upload);
+        assert_eq!(decide(None, None, Some("\"e1\""), None), Action::Download);
+        assert_eq!(decide(None, Some("h1"), Some("\"e1\""), None), Action::Conflict);
+    }
+
+    #[test]
+    fn test_remote_url() {
+        let base = "https://cloud.example.com/dav/Projects/";
+        assert_eq!(
+            remote_url(base, "Client A/Film #2.msp").unwrap(),
+            "https://cloud.example.com/dav/Projects/Client%20A/Film%20%232.msp"
+        );
+        assert_eq!(
+            remote_url(base, "v1.2/Film..msp").unwrap(),
+            "https://cloud.example.com/dav/Projects/v1.2/Film..msp"
+        );
+        for name in ["../../other/file", "a/./b.msp", "a/..", "", "/"] {
+            assert!(matches!(remote_url(base, name), Err(SyncError::InvalidName(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_backup_path() {
+        let backup = backup_path(Path::new("/p/Film.msp"));
+        let name = backup.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.starts_with("Film.conflict-") && name.ends_with(".msp"), "{}", name);
+    }
+}
@@ -0,0 +1,250 @@
+//! Persistent preferences
+//!
+//! One TOML file shared by the helper and the editor's Preferences dialog:
+//! ```text
+//! {config_dir}/MasterSelects/config.toml
+//! ```
+//!
+//! The helper reads it once at startup; command-line flags override the
+//! `[helper]` section. The editor reads and writes the whole file through
+//! `get_settings` / `set_settings`, so sections the helper does not use
+//! itself (cache size, autosave, project defaults) live here too.
+//! Missing keys fall back to their defaults, so older files keep working.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use crate::media::EncoderPreference;
+
+pub const DEFAULT_PORT: u16 = 9876;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HelperSettings {
+    /// WebSocket port; the HTTP file server uses the next one
+    pub port: u16,
+    /// Listen address; anything but loopback serves other machines
+    pub bind: IpAddr,
+    /// Replaces the built-in origin list when set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_origins: Option<Vec<String>>,
+    pub log_level: String,
+    /// Advertise the helper over mDNS (`_masterselects._tcp`)
+    pub mdns: bool,
+    /// Serve Prometheus metrics at `/metrics`
+    pub metrics: bool,
+}
+
+impl Default for HelperSettings {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            allowed_origins: None,
+            log_level: "info".to_string(),
+            mdns: false,
+            metrics: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PathSettings {
+    /// Where downloads and exports go (default: temp dir)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<PathBuf>,
+    /// Default project root (default: Documents/MasterSelects)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub projects: Option<PathBuf>,
+    /// Scratch disks for proxies, renders, and caches, in order of preference
+    pub scratch: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    /// Frame cache budget in the editor
+    pub ram_mb: u32,
+    /// Proxy/render cache budget on the scratch disks
+    pub disk_gb: u32,
+    /// Downloads dir budget; least recently used downloads are deleted
+    /// beyond it (0 = no limit)
+    pub downloads_gb: u32,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            ram_mb: 2048,
+            disk_gb: 50,
+            downloads_gb: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectDefaults {
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub sample_rate: u32,
+    /// 0 disables autosave
+    pub autosave_interval_secs: u32,
+}
+
+impl Default for ProjectDefaults {
+    fn default() -> Self {
+        Self {
+            width: 1920,
+            height: 1080,
+            fps: 30.0,
+            sample_rate: 48000,
+            autosave_interval_secs: 120,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaSettings {
+    /// Use hardware video decoding in the editor
+    pub hardware_decode: bool,
+    /// Encoder family used when a command does not ask for one
+    pub encoder_preference: EncoderPreference,
+    /// UUID of the GPU for decoding and encoding; the default device when
+    /// unset or not present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<String>,
+}
+
+impl Default for MediaSettings {
+    fn default() -> Self {
+        Self {
+            hardware_decode: true,
+            encoder_preference: EncoderPreference::Auto,
+            gpu: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SyncSettings {
+    /// WebDAV collection projects are synced to; sync is off when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webdav_url: Option<String>,
+    /// The password is read from `MASTERSELECTS_WEBDAV_PASSWORD`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub helper: HelperSettings,
+    pub paths: PathSettings,
+    pub cache: CacheSettings,
+    pub project: ProjectDefaults,
+    pub media: MediaSettings,
+    pub sync: SyncSettings,
+}
+
+impl Settings {
+    /// Every directory the settings point at.
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.paths
+            .downloads
+            .iter()
+            .chain(self.paths.projects.iter())
+            .chain(self.paths.scratch.iter())
+    }
+}
+
+/// Return the path of the config file.
+pub fn get_config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("config.toml")
+}
+
+/// Read settings from `path`; a missing file gives the defaults.
+pub fn load_from(path: &Path) -> Result<Settings> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).with_context(|| format!("Invalid {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(e) => Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    }
+}
+
+static SETTINGS: OnceLock<RwLock<Settings>> = OnceLock::new();
+
+fn store() -> &'static RwLock<Settings> {
+    SETTINGS.get_or_init(|| RwLock::new(Settings::default()))
+}
+
+/// Load the config file at startup. An unreadable or invalid file is
+/// reported and the defaults are used instead.
+pub fn init() -> Result<()> {
+    let loaded = load_from(&get_config_path());
+    let settings = loaded.as_ref().cloned().unwrap_or_default();
+    *store().write().unwrap_or_else(|e| e.into_inner()) = settings;
+    loaded.map(|_| ())
+}
+
+/// The settings loaded at startup, including changes saved since.
+pub fn current() -> Settings {
+    store().read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Write `settings` to the config file and make them current.
+pub fn save(settings: &Settings) -> Result<()> {
+    let path = get_config_path();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Cannot create {}", dir.display()))?;
+    }
+    let text = toml::to_string_pretty(settings)?;
+    std::fs::write(&path, text).with_context(|| format!("Cannot write {}", path.display()))?;
+    *store().write().unwrap_or_else(|e| e.into_inner()) = settings.clone();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_file_uses_defaults() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [helper]
+            port = 9900
+
+            [paths]
+            scratch = ["/mnt/fast"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.helper.port, 9900);
+        assert_eq!(settings.helper.log_level, "info");
+        assert!(settings.helper.bind.is_loopback());
+        assert_eq!(settings.paths.scratch, vec![PathBuf::from("/mnt/fast")]);
+        assert_eq!(settings.project, ProjectDefaults::default());
+        assert!(settings.media.hardware_decode);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut settings = Settings::default();
+        settings.paths.downloads = Some(PathBuf::from("/data/downloads"));
+        settings.media.encoder_preference = EncoderPreference::Software;
+        let text = toml::to_string_pretty(&settings).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&text).unwrap(), settings);
+    }
+}
@@ -0,0 +1,204 @@
+//! Log files, crash reports, and diagnostic bundles
+//!
+//! The helper always writes a daily-rotated log next to its console output,
+//! and a panic hook records crashes with a backtrace. `export_diagnostics`
+//! zips both together with system information (and, when the web app sends
+//! it, the project structure without media) for attaching to bug reports.
+//!
+//! Storage layout:
+//! ```text
+//! {config_dir}/MasterSelects/logs/
+//! ├── helper.2026-10-17.log   (last 7 days)
+//! └── crash-1760659200.log
+//! ```
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tracing::error;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+const LOG_PREFIX: &str = "helper";
+const LOG_SUFFIX: &str = "log";
+const MAX_LOG_FILES: usize = 7;
+const CRASH_PREFIX: &str = "crash-";
+/// Only the tail of each log goes into a bundle
+const MAX_BUNDLED_LOG_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Return the directory holding logs and crash reports.
+pub fn get_logs_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("logs")
+}
+
+/// Daily-rotated log file writer, or `None` if the logs dir is unusable.
+pub fn log_file_appender() -> Option<RollingFileAppender> {
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_PREFIX)
+        .filename_suffix(LOG_SUFFIX)
+        .max_log_files(MAX_LOG_FILES)
+        .build(get_logs_dir())
+        .ok()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Record panics in the log and in a crash report file, then run the default hook.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        let thread = std::thread::current();
+        let report = format!(
+            "MasterSelects Helper v{} crashed\nos: {} {}\nthread: {}\n{}\n\nbacktrace:\n{}\n",
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            thread.name().unwrap_or("<unnamed>"),
+            info,
+            backtrace
+        );
+
+        error!("{}", info);
+        let dir = get_logs_dir();
+        let _ = std::fs::create_dir_all(&dir);
+        let _ = std::fs::write(dir.join(format!("{}{}.log", CRASH_PREFIX, unix_time())), report);
+
+        default_hook(info);
+    }));
+}
+
+/// Version, platform, GPUs, and external tools found by the helper.
+pub fn system_report() -> serde_json::Value {
+    let tool = |path: Option<PathBuf>| path.map(|p| p.to_string_lossy().to_string());
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "cpus": std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        "nvidia_gpus": crate::gpu::list(),
+        "tools": {
+            "ffmpeg": tool(crate::media::find_ffmpeg()),
+            "ffprobe": tool(crate::media::find_ffprobe()),
+            "yt_dlp": tool(crate::download::find_ytdlp()),
+            "whisper": tool(crate::transcribe::find_whisper()),
+        },
+        "logs_dir": get_logs_dir(),
+        "created_at": unix_time(),
+    })
+}
+
+/// Log and crash files, newest first.
+fn collect_log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            (name.starts_with(LOG_PREFIX) || name.starts_with(CRASH_PREFIX)) && name.ends_with(".log")
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().ok()?.modified().ok()?;
+            Some((modified, entry.path()))
+        })
+        .collect();
+    files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// The last `max` bytes of a file.
+fn read_tail(path: &Path, max: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::{Seek, SeekFrom};
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(max)))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Write a diagnostic bundle zip into `dest_dir` and return its path.
+pub fn export_bundle(
+    dest_dir: &Path,
+    system: &serde_json::Value,
+    project: Option<&serde_json::Value>,
+) -> Result<PathBuf> {
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("Cannot create {}", dest_dir.display()))?;
+    let path = dest_dir.join(format!("masterselects-diagnostics-{}.zip", unix_time()));
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Cannot create {}", path.display()))?;
+
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    zip.start_file("system.json", options)?;
+    zip.write_all(serde_json::to_string_pretty(system)?.as_bytes())?;
+
+    if let Some(project) = project {
+        zip.start_file("project.json", options)?;
+        zip.write_all(serde_json::to_string_pretty(project)?.as_bytes())?;
+    }
+
+    for log in collect_log_files(&get_logs_dir()) {
+        let Some(name) = log.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        match read_tail(&log, MAX_BUNDLED_LOG_BYTES) {
+            Ok(data) => {
+                zip.start_file(format!("logs/{}", name), options)?;
+                zip.write_all(&data)?;
+            }
+            Err(e) => tracing::warn!("Skipping {} in diagnostic bundle: {}", log.display(), e),
+        }
+    }
+
+    zip.finish()?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_bundle_contains_system_and_project() {
+        let dir = std::env::temp_dir().join(format!("masterselects-diag-test-{}", std::process::id()));
+        let system = serde_json::json!({ "version": "test" });
+        let project = serde_json::json!({ "tracks": [] });
+
+        let path = export_bundle(&dir, &system, Some(&project)).unwrap();
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let mut json = String::new();
+        archive
+            .by_name("system.json")
+            .unwrap()
+            .read_to_string(&mut json)
+            .unwrap();
+        assert!(json.contains("\"test\""));
+        assert!(archive.by_name("project.json").is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_tail() {
+        let path = std::env::temp_dir().join(format!("masterselects-tail-test-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        assert_eq!(read_tail(&path, 4).unwrap(), b"6789");
+        assert_eq!(read_tail(&path, 100).unwrap(), b"0123456789");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,191 @@
+//! Finding the helper from the editor
+//!
+//! The editor first tries the default ports (WebSocket 9876, HTTP 9877).
+//! When another program holds them the helper moves up in pairs
+//! (9878/9879, 9880/9881, ...) and reports where it ended up in three places:
+//! - `GET /healthz` on the HTTP port (version and capabilities, no auth),
+//!   so the editor can probe the range,
+//! - the `info` command,
+//! - optionally an mDNS record (`_masterselects._tcp.local.`) whose TXT
+//!   entries carry the ports, version and whether TLS is on.
+//!
+//! With `--bind` on a LAN address the same three work from other machines,
+//! and mDNS is how a laptop finds a desktop helper without typing its IP.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Port pairs tried when the configured one is taken
+pub const PORT_ATTEMPTS: u16 = 10;
+
+/// mDNS service type advertised with `--mdns`
+pub const SERVICE_TYPE: &str = "_masterselects._tcp.local.";
+
+/// Command groups this build supports, reported by `/healthz`
+pub const CAPABILITIES: &[&str] = &[
+    "downloads",
+    "fs_commands",
+    "ai_bridge",
+    "watch_folders",
+    "media",
+    "transcribe",
+    "youtube",
+    "matanyone",
+    "diagnostics",
+    "settings",
+    "updates",
+];
+
+/// Ports the servers actually listen on
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BoundPorts {
+    pub ws_port: u16,
+    pub http_port: u16,
+}
+
+static BOUND: OnceLock<BoundPorts> = OnceLock::new();
+
+/// Ports in use, once the servers are bound.
+pub fn bound_ports() -> Option<BoundPorts> {
+    BOUND.get().copied()
+}
+
+/// WebSocket ports to try: `base`, then `base + 2`, ... leaving room for
+/// the HTTP server on each next port.
+fn candidate_ports(base: u16, attempts: u16) -> Vec<u16> {
+    (0..attempts.max(1))
+        .map_while(|i| base.checked_add(i * 2))
+        .filter(|port| port.checked_add(1).is_some())
+        .collect()
+}
+
+/// Bind the WebSocket and HTTP listeners on `addr` at the first free port pair.
+pub async fn bind(
+    addr: IpAddr,
+    base: u16,
+    attempts: u16,
+) -> Result<(TcpListener, TcpListener, BoundPorts)> {
+    let mut last_error = None;
+    for port in candidate_ports(base, attempts) {
+        let ws = match TcpListener::bind((addr, port)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+        let http = match TcpListener::bind((addr, port + 1)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        let ports = BoundPorts {
+            ws_port: port,
+            http_port: port + 1,
+        };
+        if port != base {
+            warn!(
+                "Port {} or {} is in use; listening on {} and {} instead",
+                base,
+                base + 1,
+                ports.ws_port,
+                ports.http_port
+            );
+        }
+        let _ = BOUND.set(ports);
+        return Ok((ws, http, ports));
+    }
+    match last_error {
+        Some(e) => bail!("No free port pair from {} ({} attempts): {}", base, attempts, e),
+        None => bail!("No usable port pair from {}", base),
+    }
+}
+
+/// `GET /healthz` body.
+pub fn health(ports: BoundPorts, bind: IpAddr, tls: bool, auth_required: bool) -> serde_json::Value {
+    serde_json::json!({
+        "ok": true,
+        "service": "masterselects-helper",
+        "version": env!("CARGO_PKG_VERSION"),
+        "ws_port": ports.ws_port,
+        "http_port": ports.http_port,
+        "bind": bind,
+        "remote": !bind.is_loopback(),
+        "tls": tls,
+        "auth_required": auth_required,
+        "capabilities": CAPABILITIES,
+    })
+}
+
+/// Advertise the helper over mDNS. The daemon stops when the returned
+/// handle is dropped, so keep it for the lifetime of the server.
+pub fn advertise(ports: BoundPorts, remote: bool, tls: bool) -> Option<ServiceDaemon> {
+    let result = (|| -> Result<ServiceDaemon> {
+        let daemon = ServiceDaemon::new()?;
+        let instance = format!("MasterSelects Helper {}", ports.ws_port);
+        let host = format!("masterselects-helper-{}.local.", ports.ws_port);
+        let properties = [
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("ws_port", ports.ws_port.to_string()),
+            ("http_port", ports.http_port.to_string()),
+            ("tls", tls.to_string()),
+            ("remote", remote.to_string()),
+        ];
+        let service = ServiceInfo::new(
+            SERVICE_TYPE,
+            &instance,
+            &host,
+            "",
+            ports.ws_port,
+            &properties[..],
+        )?
+        .enable_addr_auto();
+        daemon.register(service)?;
+        Ok(daemon)
+    })();
+
+    match result {
+        Ok(daemon) => {
+            info!("Advertising {} over mDNS", SERVICE_TYPE);
+            Some(daemon)
+        }
+        Err(e) => {
+            warn!("mDNS advertisement failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_candidate_ports() {
+        assert_eq!(candidate_ports(9876, 3), vec![9876, 9878, 9880]);
+        assert_eq!(candidate_ports(9876, 0), vec![9876]);
+        // Never runs past the last port pair
+        assert_eq!(candidate_ports(65532, 5), vec![65532, 65534]);
+    }
+
+    #[tokio::test]
+    async fn test_bind_skips_taken_port() {
+        let taken = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let base = taken.local_addr().unwrap().port();
+        if base > 65000 {
+            return;
+        }
+        let (_ws, _http, ports) = bind(taken.local_addr().unwrap().ip(), base, 3).await.unwrap();
+        assert_ne!(ports.ws_port, base);
+        assert_eq!(ports.http_port, ports.ws_port + 1);
+    }
+}
@@ -0,0 +1,250 @@
+//! Browser cookies for gated downloads
+//!
+//! Age-restricted and members-only videos need a signed-in session. yt-dlp
+//! can read it straight from a browser profile (`--cookies-from-browser`) or
+//! from an exported Netscape `cookies.txt` (`--cookies`). The helper only
+//! passes the browser name or file path on; it never reads cookie contents
+//! itself, and neither value is echoed back in errors.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::Response;
+
+/// Browsers yt-dlp can read cookies from
+pub const SUPPORTED_BROWSERS: &[&str] = &[
+    "brave", "chrome", "chromium", "edge", "firefox", "opera", "safari", "vivaldi", "whale",
+];
+
+/// Browser used when YouTube asks for sign-in and no cookies were given
+const FALLBACK_BROWSER: &str = "chrome";
+
+/// Cookie options shared by the download commands
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CookieOptions {
+    /// `browser[+keyring][:profile]`, as understood by yt-dlp
+    #[serde(default)]
+    pub cookies_from_browser: Option<String>,
+    /// Absolute path of a Netscape-format cookies.txt
+    #[serde(default)]
+    pub cookies_file: Option<String>,
+}
+
+impl CookieOptions {
+    /// Check the browser name; the file path is checked against the
+    /// allowed directories by the caller.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cookies_from_browser.is_some() && self.cookies_file.is_some() {
+            return Err("Pass either cookies_from_browser or cookies_file, not both".to_string());
+        }
+        if let Some(spec) = &self.cookies_from_browser {
+            let browser = spec.split(['+', ':']).next().unwrap_or_default();
+            if !SUPPORTED_BROWSERS.contains(&browser.to_lowercase().as_str()) {
+                return Err(format!(
+                    "Unsupported browser '{}'; expected one of: {}",
+                    browser,
+                    SUPPORTED_BROWSERS.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// yt-dlp arguments for these options (empty when none were given).
+    pub fn args(&self) -> Vec<String> {
+        if let Some(spec) = &self.cookies_from_browser {
+            vec!["--cookies-from-browser".to_string(), spec.clone()]
+        } else if let Some(file) = &self.cookies_file {
+            vec!["--cookies".to_string(), file.clone()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Short description for logs and error messages.
+    pub fn describe(&self) -> String {
+        match (&self.cookies_from_browser, &self.cookies_file) {
+            (Some(spec), _) => format!("{} cookies", spec.split(['+', ':']).next().unwrap_or(spec)),
+            (None, Some(_)) => "the cookies file".to_string(),
+            (None, None) => "no cookies".to_string(),
+        }
+    }
+}
+
+/// Cookies to retry with when YouTube asks for sign-in: the first installed
+/// browser, or Chrome.
+pub fn fallback_cookies() -> CookieOptions {
+    let browser = detect_browsers()
+        .into_iter()
+        .next()
+        .map(|browser| browser.name)
+        .unwrap_or(FALLBACK_BROWSER);
+    CookieOptions {
+        cookies_from_browser: Some(browser.to_string()),
+        cookies_file: None,
+    }
+}
+
+/// An installed browser with readable profiles
+#[derive(Debug, Clone, Serialize)]
+pub struct BrowserInfo {
+    /// Name to pass as `cookies_from_browser`
+    pub name: &'static str,
+    pub profile_dir: PathBuf,
+    /// Profile names, usable as `name:profile`
+    pub profiles: Vec<String>,
+}
+
+/// Where each browser keeps its profiles on this platform.
+#[cfg(target_os = "linux")]
+fn profile_roots() -> Vec<(&'static str, PathBuf)> {
+    let config = dirs::config_dir().unwrap_or_default();
+    let home = dirs::home_dir().unwrap_or_default();
+    vec![
+        ("chrome", config.join("google-chrome")),
+        ("chromium", config.join("chromium")),
+        ("chromium", home.join("snap/chromium/common/chromium")),
+        ("brave", config.join("BraveSoftware/Brave-Browser")),
+        ("edge", config.join("microsoft-edge")),
+        ("opera", config.join("opera")),
+        ("vivaldi", config.join("vivaldi")),
+        ("firefox", home.join(".mozilla/firefox")),
+        ("firefox", home.join("snap/firefox/common/.mozilla/firefox")),
+        ("firefox", home.join(".var/app/org.mozilla.firefox/.mozilla/firefox")),
+    ]
+}
+
+#[cfg(target_os = "macos")]
+fn profile_roots() -> Vec<(&'static str, PathBuf)> {
+    let support = dirs::config_dir().unwrap_or_default();
+    let home = dirs::home_dir().unwrap_or_default();
+    vec![
+        ("chrome", support.join("Google/Chrome")),
+        ("chromium", support.join("Chromium")),
+        ("brave", support.join("BraveSoftware/Brave-Browser")),
+        ("edge", support.join("Microsoft Edge")),
+        ("opera", support.join("com.operasoftware.Opera")),
+        ("vivaldi", support.join("Vivaldi")),
+        ("firefox", support.join("Firefox/Profiles")),
+        ("safari", home.join("Library/Containers/com.apple.Safari")),
+    ]
+}
+
+#[cfg(windows)]
+fn profile_roots() -> Vec<(&'static str, PathBuf)> {
+    let roaming = dirs::config_dir().unwrap_or_default();
+    let local = dirs::data_local_dir().unwrap_or_default();
+    vec![
+        ("chrome", local.join("Google\\Chrome\\User Data")),
+        ("chromium", local.join("Chromium\\User Data")),
+        ("brave", local.join("BraveSoftware\\Brave-Browser\\User Data")),
+        ("edge", local.join("Microsoft\\Edge\\User Data")),
+        ("opera", roaming.join("Opera Software\\Opera Stable")),
+        ("vivaldi", local.join("Vivaldi\\User Data")),
+        ("firefox", roaming.join("Mozilla\\Firefox\\Profiles")),
+    ]
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn profile_roots() -> Vec<(&'static str, PathBuf)> {
+    Vec::new()
+}
+
+/// Profiles under a browser's root: Chromium-family profiles have a
+/// `Preferences` file, Firefox ones a `cookies.sqlite`.
+fn list_profiles(name: &str, root: &Path) -> Vec<String> {
+    let marker = match name {
+        "firefox" => "cookies.sqlite",
+        "safari" | "opera" => return Vec::new(),
+        _ => "Preferences",
+    };
+    let mut profiles: Vec<String> = std::fs::read_dir(root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join(marker).is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    profiles.sort();
+    profiles
+}
+
+/// Browsers whose profile directory exists, in `profile_roots` order.
+pub fn detect_browsers() -> Vec<BrowserInfo> {
+    let mut browsers: Vec<BrowserInfo> = Vec::new();
+    for (name, root) in profile_roots() {
+        if !root.is_dir() || browsers.iter().any(|b| b.name == name) {
+            continue;
+        }
+        browsers.push(BrowserInfo {
+            name,
+            profiles: list_profiles(name, &root),
+            profile_dir: root,
+        });
+    }
+    browsers
+}
+
+/// `list_browsers`: installed browsers yt-dlp can take cookies from.
+pub fn handle_list_browsers(id: &str) -> Response {
+    Response::ok(
+        id,
+        serde_json::json!({
+            "browsers": detect_browsers(),
+            "supported": SUPPORTED_BROWSERS,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_options() {
+        let none = CookieOptions::default();
+        assert!(none.validate().is_ok());
+        assert!(none.args().is_empty());
+
+        let browser = CookieOptions {
+            cookies_from_browser: Some("firefox:work".to_string()),
+            cookies_file: None,
+        };
+        assert!(browser.validate().is_ok());
+        assert_eq!(browser.args(), vec!["--cookies-from-browser", "firefox:work"]);
+        assert_eq!(browser.describe(), "firefox cookies");
+
+        let keyring = CookieOptions {
+            cookies_from_browser: Some("chrome+gnomekeyring".to_string()),
+            cookies_file: None,
+        };
+        assert!(keyring.validate().is_ok());
+
+        let unknown = CookieOptions {
+            cookies_from_browser: Some("netscape".to_string()),
+            cookies_file: None,
+        };
+        assert!(unknown.validate().is_err());
+
+        let both = CookieOptions {
+            cookies_from_browser: Some("chrome".to_string()),
+            cookies_file: Some("/tmp/cookies.txt".to_string()),
+        };
+        assert!(both.validate().is_err());
+    }
+
+    #[test]
+    fn test_list_profiles() {
+        let root = std::env::temp_dir().join(format!("ms-browsers-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("Default")).unwrap();
+        std::fs::create_dir_all(root.join("Profile 1")).unwrap();
+        std::fs::create_dir_all(root.join("Crashpad")).unwrap();
+        std::fs::write(root.join("Default/Preferences"), "{}").unwrap();
+        std::fs::write(root.join("Profile 1/Preferences"), "{}").unwrap();
+
+        assert_eq!(list_profiles("chrome", &root), vec!["Default", "Profile 1"]);
+        assert!(list_profiles("firefox", &root).is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//! Subtitles, thumbnail and chapters downloaded next to a video
+//!
+//! yt-dlp names every side file after the media file:
+//! ```text
+//! Title.mp4            the video
+//! Title.en.vtt         subtitles, one file per language
+//! Title.jpg            thumbnail
+//! Title.info.json      metadata, read for chapters and then deleted
+//! ```
+//! so they are collected by stem once the download finished rather than by
+//! parsing yt-dlp's log. The info JSON is removed after reading because it
+//! can carry request headers, including cookies.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Extra files requested with a download
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DownloadExtras {
+    /// Subtitle languages (e.g. `["en", "de"]`, or `["all"]`); none when empty
+    #[serde(default)]
+    pub subtitles: Vec<String>,
+    /// Include auto-generated captions for those languages
+    #[serde(default)]
+    pub auto_subtitles: bool,
+    #[serde(default)]
+    pub thumbnail: bool,
+    #[serde(default)]
+    pub chapters: bool,
+}
+
+/// One downloaded subtitle track
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SubtitleFile {
+    pub language: String,
+    pub path: PathBuf,
+}
+
+/// A chapter from the video's metadata
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VideoChapter {
+    pub start: f64,
+    pub end: f64,
+    pub title: String,
+}
+
+const SUBTITLE_EXTENSIONS: &[&str] = &["vtt", "srt", "ass", "ttml", "srv3", "json3"];
+const THUMBNAIL_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+impl DownloadExtras {
+    pub fn is_empty(&self) -> bool {
+        self.subtitles.is_empty() && !self.thumbnail && !self.chapters
+    }
+
+    /// yt-dlp arguments; conversions to WebVTT/JPEG need ffmpeg.
+    pub fn args(&self, has_ffmpeg: bool) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.subtitles.is_empty() {
+            args.push("--write-subs".to_string());
+            if self.auto_subtitles {
+                args.push("--write-auto-subs".to_string());
+            }
+            args.extend([
+                "--sub-langs".to_string(),
+                self.subtitles.join(","),
+                "--sub-format".to_string(),
+                "vtt/srt/best".to_string(),
+            ]);
+            if has_ffmpeg {
+                args.extend(["--convert-subs".to_string(), "vtt".to_string()]);
+            }
+        }
+        if self.thumbnail {
+            args.push("--write-thumbnail".to_string());
+            if has_ffmpeg {
+                args.extend(["--convert-thumbnails".to_string(), "jpg".to_string()]);
+            }
+        }
+        if self.chapters {
+            args.push("--write-info-json".to_string());
+        }
+        args
+    }
+
+    /// Find the requested side files of `media` and add them to `result`.
+    pub fn collect(&self, media: &Path, result: &mut serde_json::Value) {
+        if self.is_empty() {
+            return;
+        }
+        let (Some(dir), Some(stem)) = (media.parent(), media.file_stem()) else {
+            return;
+        };
+        let stem = stem.to_string_lossy();
+        let siblings: Vec<PathBuf> = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.as_path() != media)
+            .collect();
+
+        if !self.subtitles.is_empty() {
+            result["subtitles"] = serde_json::json!(find_subtitles(&stem, &siblings));
+        }
+        if self.thumbnail {
+            result["thumbnail"] = serde_json::json!(find_thumbnail(&stem, &siblings));
+        }
+        if self.chapters {
+            let info_path = dir.join(format!("{}.info.json", stem));
+            let chapters = std::fs::read_to_string(&info_path)
+                .ok()
+                .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+                .map(|info| parse_chapters(&info))
+                .unwrap_or_default();
+            if let Err(e) = std::fs::remove_file(&info_path) {
+                warn!("Could not remove {}: {}", info_path.display(), e);
+            }
+            result["chapters"] = serde_json::json!(chapters);
+        }
+    }
+}
+
+/// Split a name of the form `{stem}.{middle}.{ext}` (or `{stem}.{ext}`,
+/// with an empty middle) into middle and extension.
+fn split_side_file<'a>(stem: &str, path: &'a Path) -> Option<(&'a str, &'a str)> {
+    let name = path.file_name()?.to_str()?;
+    let rest = name.strip_prefix(stem)?.strip_prefix('.')?;
+    Some(match rest.rsplit_once('.') {
+        Some((middle, ext)) => (middle, ext),
+        None => ("", rest),
+    })
+}
+
+fn find_subtitles(stem: &str, siblings: &[PathBuf]) -> Vec<SubtitleFile> {
+    let mut subtitles: Vec<SubtitleFile> = siblings
+        .iter()
+        .filter_map(|path| {
+            let (language, ext) = split_side_file(stem, path)?;
+            (!language.is_empty() && SUBTITLE_EXTENSIONS.contains(&ext)).then(|| SubtitleFile {
+                language: language.to_string(),
+                path: path.clone(),
+            })
+        })
+        .collect();
+    subtitles.sort_by(|a, b| a.language.cmp(&b.language));
+    subtitles
+}
+
+fn find_thumbnail(stem: &str, siblings: &[PathBuf]) -> Option<PathBuf> {
+    siblings
+        .iter()
+        .find(|path| {
+            matches!(split_side_file(stem, path), Some(("", ext)) if THUMBNAIL_EXTENSIONS.contains(&ext))
+        })
+        .cloned()
+}
+
+fn parse_chapters(info: &serde_json::Value) -> Vec<VideoChapter> {
+    info.get("chapters")
+        .and_then(|v| v.as_array())
+        .map(|chapters| {
+            chapters
+                .iter()
+                .filter_map(|chapter| {
+                    Some(VideoChapter {
+                        start: chapter.get("start_time")?.as_f64()?,
+                        end: chapter.get("end_time")?.as_f64()?,
+                        title: chapter
+                            .get("title")
+                            .and_then(|v| v.as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_side_files() {
+        let siblings: Vec<PathBuf> = [
+            "/dl/My_Video.en.vtt",
+            "/dl/My_Video.de-DE.vtt",
+            "/dl/My_Video.jpg",
+            "/dl/My_Video.info.json",
+            "/dl/My_Video_2.en.vtt",
+            "/dl/Other.jpg",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+
+        let subtitles = find_subtitles("My_Video", &siblings);
+        assert_eq!(
+            subtitles.iter().map(|s| s.language.as_str()).collect::<Vec<_>>(),
+            vec!["de-DE", "en"]
+        );
+        assert_eq!(
+            find_thumbnail("My_Video", &siblings),
+            Some(PathBuf::from("/dl/My_Video.jpg"))
+        );
+        assert_eq!(find_thumbnail("Missing", &siblings), None);
+    }
+
+    #[test]
+    fn test_parse_chapters() {
+        let info = serde_json::json!({
+            "chapters": [
+                {"start_time": 0.0, "end_time": 65.5, "title": "Intro"},
+                {"start_time": 65.5, "end_time": 300.0, "title": "Main"},
+                {"title": "broken"}
+            ]
+        });
+        let chapters = parse_chapters(&info);
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[1].title, "Main");
+        assert_eq!(chapters[0].end, 65.5);
+        assert!(parse_chapters(&serde_json::json!({"chapters": null})).is_empty());
+    }
+
+    #[test]
+    fn test_args() {
+        let extras = DownloadExtras {
+            subtitles: vec!["en".to_string(), "de".to_string()],
+            auto_subtitles: true,
+            thumbnail: true,
+            chapters: false,
+        };
+        let args = extras.args(false);
+        assert!(args.contains(&"--write-auto-subs".to_string()));
+        assert!(args.contains(&"en,de".to_string()));
+        assert!(!args.contains(&"--convert-thumbnails".to_string()));
+        assert!(extras.args(true).contains(&"--convert-subs".to_string()));
+        assert!(DownloadExtras::default().args(true).is_empty());
+    }
+}
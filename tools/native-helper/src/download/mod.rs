@@ -1,8 +1,14 @@
 //! Video download module using yt-dlp
 
+mod cookies;
+mod extras;
+mod provision;
 mod ytdlp;
 
 pub use ytdlp::{
     find_ytdlp, find_deno, get_ytdlp_command, get_deno_args,
-    handle_list_formats, handle_download, WsSender,
+    handle_list_formats, handle_download, Section, WsSender,
 };
+pub use provision::{handle_ytdlp_status, handle_ytdlp_update, start_auto_update};
+pub use cookies::{handle_list_browsers, CookieOptions};
+pub use extras::DownloadExtras;
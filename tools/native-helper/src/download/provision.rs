@@ -0,0 +1,510 @@
+//! Managed yt-dlp and deno
+//!
+//! Instead of asking users to `pip install yt-dlp`, the helper can install
+//! the official release binaries itself:
+//! ```text
+//! {data_local_dir}/MasterSelects/bin/yt-dlp[.exe]
+//! {data_local_dir}/MasterSelects/bin/deno[.exe]
+//! ```
+//! Each download is checked against the SHA-256 published with its GitHub
+//! release before it replaces the previous copy. A managed yt-dlp takes
+//! precedence over bundled and PATH copies, and because sites break old
+//! yt-dlp versions quickly it is refreshed in the background at startup once
+//! it is older than `AUTO_UPDATE_AFTER`. deno (yt-dlp's JavaScript runtime)
+//! is only installed when asked for.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+use crate::updater::{digest_matches, USER_AGENT};
+use crate::utils;
+
+const YTDLP_REPO: &str = "yt-dlp/yt-dlp";
+const DENO_REPO: &str = "denoland/deno";
+
+/// Checksum list published with every yt-dlp release
+const YTDLP_CHECKSUMS: &str = "SHA2-256SUMS";
+
+/// Age after which a managed yt-dlp is updated at startup
+const AUTO_UPDATE_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Report download progress every this many bytes
+const PROGRESS_STEP: u64 = 512 * 1024;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A tool the helper can install
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    YtDlp,
+    Deno,
+}
+
+impl Tool {
+    fn name(self) -> &'static str {
+        match self {
+            Tool::YtDlp => "yt-dlp",
+            Tool::Deno => "deno",
+        }
+    }
+
+    fn file_name(self) -> String {
+        format!("{}{}", self.name(), std::env::consts::EXE_SUFFIX)
+    }
+}
+
+/// Installed or found copy of a tool
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolStatus {
+    /// `None` when the tool is not available at all
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+    /// Installed and kept updated by the helper
+    pub managed: bool,
+    /// Newest release, when asked to check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest: Option<String>,
+}
+
+/// Result of installing one tool
+#[derive(Debug, Clone, Serialize)]
+pub struct Installed {
+    pub path: PathBuf,
+    pub version: String,
+    /// `false` when the managed copy was already the latest release
+    pub updated: bool,
+}
+
+/// Directory holding the managed tools.
+pub fn get_tools_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("bin")
+}
+
+/// The managed copy of `tool`, if one is installed and runs.
+pub fn managed_path(tool: Tool) -> Option<PathBuf> {
+    let path = get_tools_dir().join(tool.file_name());
+    utils::executable_works(&path).then_some(path)
+}
+
+/// yt-dlp release asset for this platform (standalone builds, no Python).
+fn ytdlp_asset() -> Option<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("windows", "x86_64") => Some("yt-dlp.exe"),
+        ("windows", "x86") => Some("yt-dlp_x86.exe"),
+        ("macos", _) => Some("yt-dlp_macos"),
+        ("linux", "x86_64") => Some("yt-dlp_linux"),
+        ("linux", "aarch64") => Some("yt-dlp_linux_aarch64"),
+        _ => None,
+    }
+}
+
+/// deno release target triple for this platform.
+fn deno_target() -> Option<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("windows", "x86_64") => Some("x86_64-pc-windows-msvc"),
+        ("macos", "x86_64") => Some("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Some("aarch64-apple-darwin"),
+        ("linux", "x86_64") => Some("x86_64-unknown-linux-gnu"),
+        ("linux", "aarch64") => Some("aarch64-unknown-linux-gnu"),
+        _ => None,
+    }
+}
+
+/// Version reported by `tool --version`.
+pub fn tool_version(path: &Path) -> Option<String> {
+    let output = utils::no_window_std(std::process::Command::new(path).arg("--version"))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// First line of `--version` output: "2024.08.06" for yt-dlp,
+/// "deno 1.46.3 (stable, ...)" for deno.
+fn parse_version(output: &str) -> Option<String> {
+    let line = output.lines().next()?.trim();
+    let line = line.strip_prefix("deno ").unwrap_or(line);
+    line.split_whitespace().next().map(str::to_string)
+}
+
+/// Release tags carry a "v" for deno but not for yt-dlp.
+fn same_version(tag: &str, version: &str) -> bool {
+    tag.trim_start_matches('v') == version.trim_start_matches('v')
+}
+
+/// Find the digest for `asset` in a `sha256sum`-style list.
+fn parse_checksums(text: &str, asset: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        let digest = parts.next()?;
+        let name = parts.next()?.trim_start_matches('*');
+        (name == asset).then(|| digest.to_string())
+    })
+}
+
+/// First 64-digit hex string in a checksum file; deno's Windows `.sha256sum`
+/// files are PowerShell `Get-FileHash` output rather than `sha256sum` lines.
+fn find_sha256(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_hexdigit())
+        .find(|word| word.len() == 64)
+        .map(str::to_string)
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .redirects(10)
+        .timeout_connect(Duration::from_secs(15))
+        .build()
+}
+
+fn get_text(url: &str) -> Result<String> {
+    Ok(agent()
+        .get(url)
+        .set("User-Agent", USER_AGENT)
+        .call()
+        .with_context(|| format!("GET {}", url))?
+        .into_string()?)
+}
+
+/// Tag of the latest GitHub release of `repo`.
+fn latest_tag(repo: &str) -> Result<String> {
+    let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
+    let release: serde_json::Value = serde_json::from_str(&get_text(&url)?)?;
+    release["tag_name"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("No tag_name in latest {} release", repo))
+}
+
+fn release_url(repo: &str, tag: &str, asset: &str) -> String {
+    format!("https://github.com/{}/releases/download/{}/{}", repo, tag, asset)
+}
+
+/// Newest release tag of `tool`.
+pub fn latest_version(tool: Tool) -> Result<String> {
+    latest_tag(match tool {
+        Tool::YtDlp => YTDLP_REPO,
+        Tool::Deno => DENO_REPO,
+    })
+}
+
+/// Download `url` to `dest`, checking its SHA-256 against `expected`.
+/// Nothing is left at `dest` unless the digest matches.
+fn download_verified(
+    url: &str,
+    expected: &str,
+    dest: &Path,
+    on_progress: &mut dyn FnMut(u64, Option<u64>),
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let resp = agent()
+        .get(url)
+        .set("User-Agent", USER_AGENT)
+        .call()
+        .with_context(|| format!("GET {}", url))?;
+    let total = resp.header("Content-Length").and_then(|v| v.parse::<u64>().ok());
+
+    let result = (|| -> Result<()> {
+        let mut reader = resp.into_reader();
+        let mut file = std::fs::File::create(dest)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; READ_CHUNK_SIZE];
+        let mut done = 0u64;
+        let mut reported = 0u64;
+        loop {
+            if cancel.is_cancelled() {
+                bail!("Cancelled");
+            }
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n])?;
+            done += n as u64;
+            if done - reported >= PROGRESS_STEP {
+                reported = done;
+                on_progress(done, total);
+            }
+        }
+        file.sync_all()?;
+        on_progress(done, total);
+
+        let actual = format!("{:x}", hasher.finalize());
+        if !digest_matches(expected, &actual) {
+            bail!("SHA-256 mismatch for {}: expected {}, got {}", url, expected, actual);
+        }
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(dest);
+    }
+    result
+}
+
+/// Make `staged` executable and move it over `dest`.
+fn promote(staged: &Path, dest: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(staged, dest)
+        .with_context(|| format!("Cannot replace {} (is it running?)", dest.display()))
+}
+
+/// Pull the `deno` executable out of a release zip.
+fn extract_deno(zip_path: &Path, dest: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(zip_path)?)?;
+    let mut entry = archive
+        .by_name(&Tool::Deno.file_name())
+        .context("deno archive does not contain the executable")?;
+    let mut file = std::fs::File::create(dest)?;
+    std::io::copy(&mut entry, &mut file)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Install or update the managed copy of `tool` from its latest release.
+pub fn install(
+    tool: Tool,
+    on_progress: &mut dyn FnMut(u64, Option<u64>),
+    cancel: &CancellationToken,
+) -> Result<Installed> {
+    let dir = get_tools_dir();
+    let dest = dir.join(tool.file_name());
+
+    let tag = latest_version(tool)?;
+    if let Some(current) = managed_path(tool).and_then(|path| tool_version(&path)) {
+        if same_version(&tag, &current) {
+            return Ok(Installed {
+                path: dest,
+                version: current,
+                updated: false,
+            });
+        }
+    }
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let staged = dir.join(format!("{}.part", tool.file_name()));
+
+    match tool {
+        Tool::YtDlp => {
+            let asset = ytdlp_asset().ok_or_else(|| anyhow!("No yt-dlp build for this platform"))?;
+            let sums = get_text(&release_url(YTDLP_REPO, &tag, YTDLP_CHECKSUMS))?;
+            let digest = parse_checksums(&sums, asset)
+                .ok_or_else(|| anyhow!("{} is not listed in {}", asset, YTDLP_CHECKSUMS))?;
+            let url = release_url(YTDLP_REPO, &tag, asset);
+            download_verified(&url, &digest, &staged, on_progress, cancel)?;
+        }
+        Tool::Deno => {
+            let target = deno_target().ok_or_else(|| anyhow!("No deno build for this platform"))?;
+            let asset = format!("deno-{}.zip", target);
+            let sum = get_text(&release_url(DENO_REPO, &tag, &format!("{}.sha256sum", asset)))?;
+            let digest = find_sha256(&sum)
+                .ok_or_else(|| anyhow!("Invalid checksum file for {}", asset))?;
+            let zip_path = dir.join(format!("{}.part", asset));
+            let url = release_url(DENO_REPO, &tag, &asset);
+            download_verified(&url, &digest, &zip_path, on_progress, cancel)?;
+            let extracted = extract_deno(&zip_path, &staged);
+            let _ = std::fs::remove_file(&zip_path);
+            if let Err(e) = extracted {
+                let _ = std::fs::remove_file(&staged);
+                return Err(e);
+            }
+        }
+    }
+
+    if let Err(e) = promote(&staged, &dest) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(e);
+    }
+    let version = tool_version(&dest)
+        .ok_or_else(|| anyhow!("Installed {} does not run", tool.name()))?;
+    info!("Installed {} {} to {}", tool.name(), version, dest.display());
+    Ok(Installed {
+        path: dest,
+        version,
+        updated: true,
+    })
+}
+
+/// Update a managed yt-dlp in the background when it is getting old.
+pub fn start_auto_update() {
+    tokio::task::spawn_blocking(|| {
+        let Some(path) = managed_path(Tool::YtDlp) else {
+            return;
+        };
+        let stale = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age > AUTO_UPDATE_AFTER);
+        if !stale {
+            return;
+        }
+
+        match install(Tool::YtDlp, &mut |_, _| {}, &CancellationToken::new()) {
+            Ok(installed) if !installed.updated => {
+                // Touch the binary so the next check is a week away
+                let _ = std::fs::File::options()
+                    .append(true)
+                    .open(&installed.path)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Automatic yt-dlp update failed: {}", e),
+        }
+    });
+}
+
+fn status(tool: Tool, found: Option<PathBuf>, check_latest: bool) -> ToolStatus {
+    let managed = managed_path(tool);
+    ToolStatus {
+        version: found.as_deref().and_then(tool_version),
+        managed: managed.is_some() && managed == found,
+        path: found,
+        latest: if check_latest {
+            latest_version(tool)
+                .map_err(|e| warn!("Cannot check latest {}: {}", tool.name(), e))
+                .ok()
+        } else {
+            None
+        },
+    }
+}
+
+/// `ytdlp_status`: which yt-dlp/deno downloads use, their versions, and
+/// optionally the latest releases.
+pub async fn handle_ytdlp_status(id: &str, check_latest: bool) -> Response {
+    let result = tokio::task::spawn_blocking(move || {
+        serde_json::json!({
+            "ytdlp": status(Tool::YtDlp, super::find_ytdlp(), check_latest),
+            "deno": status(Tool::Deno, super::find_deno(), check_latest),
+            "tools_dir": get_tools_dir(),
+        })
+    })
+    .await;
+    match result {
+        Ok(data) => Response::ok(id, data),
+        Err(e) => Response::error(id, error_codes::INTERNAL_ERROR, format!("Task failed: {}", e)),
+    }
+}
+
+/// Run `install` off the runtime, forwarding its byte counts as progress.
+async fn install_with_progress(
+    tool: Tool,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Result<Installed> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let task_cancel = cancel.clone();
+    let task = tokio::task::spawn_blocking(move || {
+        let mut on_progress = |done, total| {
+            let _ = tx.send((done, total));
+        };
+        install(tool, &mut on_progress, &task_cancel)
+    });
+
+    // The channel closes when the install finishes
+    while let Some((done, total)) = rx.recv().await {
+        let percent = total
+            .filter(|&total| total > 0)
+            .map(|total| (done as f64 / total as f64 * 100.0) as f32)
+            .unwrap_or(0.0);
+        let mut progress = JobProgress::stage(job_stages::DOWNLOADING, percent);
+        progress.bytes_done = Some(done);
+        progress.bytes_total = total;
+        reporter.progress(&progress).await;
+    }
+    task.await.map_err(|e| anyhow!("Task failed: {}", e))?
+}
+
+/// `ytdlp_update`: install or update the managed yt-dlp, and deno when
+/// `include_deno` is set (job).
+pub async fn handle_ytdlp_update(
+    include_deno: bool,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.clone();
+    let job_id = reporter.job_id.clone();
+    let fail = |e: anyhow::Error| {
+        let code = if cancel.is_cancelled() {
+            error_codes::CANCELLED
+        } else {
+            error_codes::DOWNLOAD_FAILED
+        };
+        Response::error(&id, code, format!("{:#}", e)).with_job_id(&job_id)
+    };
+
+    let ytdlp = match install_with_progress(Tool::YtDlp, reporter, cancel).await {
+        Ok(installed) => installed,
+        Err(e) => return fail(e),
+    };
+    let mut result = serde_json::json!({ "ytdlp": ytdlp });
+    if include_deno {
+        match install_with_progress(Tool::Deno, reporter, cancel).await {
+            Ok(installed) => result["deno"] = serde_json::json!(installed),
+            Err(e) => return fail(e),
+        }
+    }
+    Response::job_complete(&id, &job_id, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_checksums() {
+        let sums = "\
+            0a1b  yt-dlp\n\
+            c0ffee  yt-dlp.exe\n\
+            beef *yt-dlp_linux\n";
+        assert_eq!(parse_checksums(sums, "yt-dlp.exe").as_deref(), Some("c0ffee"));
+        assert_eq!(parse_checksums(sums, "yt-dlp_linux").as_deref(), Some("beef"));
+        assert_eq!(parse_checksums(sums, "yt-dlp_macos"), None);
+    }
+
+    #[test]
+    fn test_find_sha256() {
+        let digest = "a".repeat(64);
+        let posix = format!("{}  deno-x86_64-unknown-linux-gnu.zip\n", digest);
+        let powershell = format!(
+            "Algorithm : SHA256\r\nHash      : {}\r\nPath      : C:\\deno.zip\r\n",
+            digest.to_uppercase()
+        );
+        assert_eq!(find_sha256(&posix), Some(digest.clone()));
+        assert!(digest_matches(&find_sha256(&powershell).unwrap(), &digest));
+        assert_eq!(find_sha256("not a checksum"), None);
+    }
+
+    #[test]
+    fn test_versions() {
+        assert_eq!(parse_version("2024.08.06\n").as_deref(), Some("2024.08.06"));
+        assert_eq!(
+            parse_version("deno 1.46.3 (stable, release, x86_64-unknown-linux-gnu)\nv8 12.9\n")
+                .as_deref(),
+            Some("1.46.3")
+        );
+        assert!(same_version("v1.46.3", "1.46.3"));
+        assert!(same_version("2024.08.06", "2024.08.06"));
+        assert!(!same_version("2024.09.27", "2024.08.06"));
+    }
+}
@@ -8,56 +8,55 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use futures_util::SinkExt;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::TcpStream;
 use tokio::process::Command as TokioCommand;
 use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
-use crate::protocol::{error_codes, Response};
+use super::cookies::{fallback_cookies, CookieOptions};
+use super::extras::DownloadExtras;
+use super::provision::{self, Tool};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+use crate::media;
+use crate::metrics;
+use crate::quota;
 use crate::utils;
 
 const YTDLP_NOT_FOUND_MESSAGE: &str =
-    "yt-dlp not found. Install or update the Native Helper MSI, or install yt-dlp on PATH.";
+    "yt-dlp not found. Install it from the helper (ytdlp_update), update the Native Helper MSI, or install yt-dlp on PATH.";
 const FFMPEG_NOT_FOUND_MESSAGE: &str =
     "MP3 audio download requires ffmpeg. Install ffmpeg on PATH or ship it next to the Native Helper.";
+const SECTION_FFMPEG_NOT_FOUND_MESSAGE: &str =
+    "Section downloads require ffmpeg. Install ffmpeg on PATH or ship it next to the Native Helper.";
 const AUDIO_MP3_FORMAT_ID: &str = "__masterselects_audio_mp3";
 
 /// Type for sending WebSocket messages (for progress streaming)
 pub type WsSender = Arc<
     tokio::sync::Mutex<
-        futures_util::stream::SplitSink<tokio_tungstenite::WebSocketStream<TcpStream>, Message>,
+        futures_util::stream::SplitSink<
+            tokio_tungstenite::WebSocketStream<crate::tls::HelperStream>,
+            Message,
+        >,
     >,
 >;
 
-fn executable_works(path: &Path) -> bool {
-    if !path.exists() || !path.is_file() {
-        return false;
+/// Find yt-dlp executable: the managed copy, then the helper install
+/// folder, then PATH.
+pub fn find_ytdlp() -> Option<PathBuf> {
+    // A managed copy is kept up to date, so it wins over a bundled one.
+    if let Some(managed) = provision::managed_path(Tool::YtDlp) {
+        return Some(managed);
     }
 
-    crate::utils::no_window_std(std::process::Command::new(path).arg("--version"))
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
-}
-
-fn command_works(command: &str) -> bool {
-    crate::utils::no_window_std(std::process::Command::new(command).arg("--version"))
-        .output()
-        .map(|output| output.status.success())
-        .unwrap_or(false)
-}
-
-/// Find yt-dlp executable, checking the helper install folder before PATH.
-pub fn find_ytdlp() -> Option<PathBuf> {
     // Packaged helper builds can ship yt-dlp next to the helper executable.
     if let Ok(exe_path) = std::env::current_exe() {
         if let Some(exe_dir) = exe_path.parent() {
             #[cfg(windows)]
             {
                 let bundled = exe_dir.join("yt-dlp.exe");
-                if executable_works(&bundled) {
+                if utils::executable_works(&bundled) {
                     return Some(bundled);
                 }
             }
@@ -65,7 +64,7 @@ pub fn find_ytdlp() -> Option<PathBuf> {
             #[cfg(not(windows))]
             {
                 let bundled = exe_dir.join("yt-dlp");
-                if executable_works(&bundled) {
+                if utils::executable_works(&bundled) {
                     return Some(bundled);
                 }
             }
@@ -73,7 +72,7 @@ pub fn find_ytdlp() -> Option<PathBuf> {
     }
 
     // Then check if yt-dlp is in PATH, which keeps local development flexible.
-    if command_works("yt-dlp") {
+    if utils::command_works("yt-dlp") {
         return Some(PathBuf::from("yt-dlp"));
     }
 
@@ -85,7 +84,7 @@ pub fn find_ytdlp() -> Option<PathBuf> {
             if let Ok(entries) = std::fs::read_dir(appdata_path.join("Python")) {
                 for entry in entries.flatten() {
                     let scripts = entry.path().join("Scripts").join("yt-dlp.exe");
-                    if executable_works(&scripts) {
+                    if utils::executable_works(&scripts) {
                         return Some(scripts);
                     }
                 }
@@ -98,7 +97,7 @@ pub fn find_ytdlp() -> Option<PathBuf> {
             if let Ok(entries) = std::fs::read_dir(local_path.join("Programs").join("Python")) {
                 for entry in entries.flatten() {
                     let scripts = entry.path().join("Scripts").join("yt-dlp.exe");
-                    if executable_works(&scripts) {
+                    if utils::executable_works(&scripts) {
                         return Some(scripts);
                     }
                 }
@@ -109,37 +108,12 @@ pub fn find_ytdlp() -> Option<PathBuf> {
     None
 }
 
-/// Find ffmpeg executable for yt-dlp post-processing.
-pub fn find_ffmpeg() -> Option<PathBuf> {
-    if let Ok(exe_path) = std::env::current_exe() {
-        if let Some(exe_dir) = exe_path.parent() {
-            #[cfg(windows)]
-            {
-                let bundled = exe_dir.join("ffmpeg.exe");
-                if executable_works(&bundled) {
-                    return Some(bundled);
-                }
-            }
-
-            #[cfg(not(windows))]
-            {
-                let bundled = exe_dir.join("ffmpeg");
-                if executable_works(&bundled) {
-                    return Some(bundled);
-                }
-            }
-        }
-    }
-
-    if command_works("ffmpeg") {
-        return Some(PathBuf::from("ffmpeg"));
-    }
-
-    None
-}
-
 /// Find deno executable for yt-dlp JavaScript runtime
 pub fn find_deno() -> Option<PathBuf> {
+    if let Some(managed) = provision::managed_path(Tool::Deno) {
+        return Some(managed);
+    }
+
     // Check if deno is in PATH
     if let Ok(output) =
         crate::utils::no_window_std(std::process::Command::new("deno").arg("--version")).output()
@@ -194,7 +168,7 @@ pub fn get_deno_args() -> Vec<String> {
 }
 
 /// List available formats for a video URL (supports all yt-dlp platforms)
-pub async fn handle_list_formats(id: &str, url: &str) -> Response {
+pub async fn handle_list_formats(id: &str, url: &str, cookies: &CookieOptions) -> Response {
     use std::process::Stdio;
 
     if !url.starts_with("http://") && !url.starts_with("https://") {
@@ -208,16 +182,20 @@ pub async fn handle_list_formats(id: &str, url: &str) -> Response {
     let ytdlp_cmd = get_ytdlp_command();
     let deno_args = get_deno_args();
 
-    // Try without cookies first, then with cookies if bot-blocked
-    for use_cookies in [false, true] {
+    // Use the given cookies; without any, try without cookies first, then
+    // with browser cookies if bot-blocked
+    let explicit_cookies = !cookies.args().is_empty();
+    for retry in [false, true] {
+        let attempt_cookies = if retry { fallback_cookies() } else { cookies.clone() };
+        let use_cookies = !attempt_cookies.args().is_empty();
         let mut cmd = TokioCommand::new(&ytdlp_cmd);
         crate::utils::no_window(&mut cmd);
         for arg in &deno_args {
             cmd.arg(arg);
         }
         if use_cookies {
-            info!("Retrying list_formats with --cookies-from-browser chrome");
-            cmd.args(["--cookies-from-browser", "chrome"]);
+            info!("Listing formats with {}", attempt_cookies.describe());
+            cmd.args(attempt_cookies.args());
         }
         let result = cmd
             .args(["--dump-json", "--no-playlist", "--force-ipv4", url])
@@ -246,7 +224,8 @@ pub async fn handle_list_formats(id: &str, url: &str) -> Response {
                 let stderr = String::from_utf8_lossy(&output.stderr);
                 let stderr_str = stderr.to_string();
                 // If bot-blocked and haven't tried cookies yet, retry with cookies
-                if !use_cookies
+                if !retry
+                    && !explicit_cookies
                     && (stderr_str.contains("Sign in to confirm")
                         || stderr_str.contains("not a bot"))
                 {
@@ -258,9 +237,9 @@ pub async fn handle_list_formats(id: &str, url: &str) -> Response {
                     && (stderr_str.contains("Could not copy")
                         || stderr_str.contains("cookie database"))
                 {
-                    warn!("Could not access browser cookies — YouTube requires authentication for this video");
+                    warn!("Could not access {} — YouTube requires authentication for this video", attempt_cookies.describe());
                     return Response::error(id, error_codes::DOWNLOAD_FAILED,
-                        "YouTube requires sign-in for this video. Close Chrome and retry, or try a different video.".to_string());
+                        format!("YouTube requires sign-in for this video and {} could not be read. Close the browser and retry, or pass a cookies file.", attempt_cookies.describe()));
                 }
                 // Filter to only ERROR lines for the response
                 let error_lines: Vec<&str> = stderr_str
@@ -425,7 +404,7 @@ fn build_formats_response(id: &str, info: &serde_json::Value) -> Response {
                 abr.max(tbr) as i64
             });
 
-        if let (Some(fmt), Some(_ffmpeg)) = (best_audio, find_ffmpeg()) {
+        if let (Some(fmt), Some(_ffmpeg)) = (best_audio, media::find_ffmpeg()) {
             let filesize = fmt
                 .get("filesize")
                 .and_then(|v| v.as_i64())
@@ -461,33 +440,141 @@ enum DownloadResult {
     /// Download succeeded — return the file path
     Success(String),
     /// Bot detection triggered — should retry with cookies
-    BotBlocked(String),
+    BotBlocked,
+    /// Cancelled via `cancel_job` or because the session closed
+    Cancelled,
     /// Other failure — don't retry
     Failed(Response),
 }
 
+/// Parse a yt-dlp size such as `12.34MiB` or `~ 1.5GiB` into bytes
+fn parse_size(text: &str) -> Option<u64> {
+    let text = text.trim().trim_start_matches('~').trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = text.split_at(split);
+    let value: f64 = number.trim().parse().ok()?;
+    let multiplier = match unit {
+        "B" => 1.0,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "KB" => 1000.0,
+        "MB" => 1000.0 * 1000.0,
+        "GB" => 1000.0 * 1000.0 * 1000.0,
+        _ => return None,
+    };
+    Some((value * multiplier) as u64)
+}
+
+/// Parse a yt-dlp ETA (`SS`, `MM:SS` or `HH:MM:SS`) into seconds
+fn parse_eta(text: &str) -> Option<u64> {
+    text.split(':')
+        .try_fold(0u64, |acc, part| part.parse::<u64>().ok().map(|v| acc * 60 + v))
+}
+
+/// Parse a yt-dlp progress line:
+/// `[download]  45.2% of ~ 12.34MiB at 1.20MiB/s ETA 00:10 (frag 3/20)`
+fn parse_progress_line(line: &str) -> Option<JobProgress> {
+    let pct_str = line.split('%').next()?;
+    let pct_part = pct_str
+        .trim()
+        .rsplit_once(' ')
+        .map(|(_, p)| p)
+        .unwrap_or(pct_str.trim());
+    let percent = pct_part.trim().parse::<f32>().ok()?.min(100.0);
+
+    let word_after = |marker: &str| -> Option<&str> {
+        let idx = line.find(marker)?;
+        line[idx + marker.len()..].split_whitespace().next()
+    };
+
+    let speed = word_after(" at ")
+        .map(|s| s.trim_start_matches('~'))
+        .filter(|s| s.contains("/s"))
+        .map(str::to_string);
+    let eta = word_after("ETA ")
+        .filter(|s| !s.is_empty() && *s != "Unknown")
+        .map(str::to_string);
+    let bytes_total = line.find(" of ").and_then(|idx| {
+        let rest = line[idx + 4..].trim_start().trim_start_matches('~').trim_start();
+        parse_size(rest.split_whitespace().next()?)
+    });
+
+    Some(JobProgress {
+        stage: job_stages::DOWNLOADING.to_string(),
+        percent,
+        eta_secs: eta.as_deref().and_then(parse_eta),
+        bytes_done: bytes_total.map(|total| (total as f64 * percent as f64 / 100.0) as u64),
+        bytes_total,
+        speed,
+        eta,
+    })
+}
+
+/// Time range of a video, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Section {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Section {
+    fn validate(&self) -> Result<(), String> {
+        if !self.start.is_finite() || !self.end.is_finite() || self.start < 0.0 {
+            return Err("start and end must be non-negative seconds".to_string());
+        }
+        if self.end <= self.start {
+            return Err("end must be after start".to_string());
+        }
+        Ok(())
+    }
+
+    /// `--download-sections` value
+    fn spec(&self) -> String {
+        format!("*{}-{}", self.start, self.end)
+    }
+
+    /// File name suffix, e.g. `_90-120.5s`
+    fn file_suffix(&self) -> String {
+        format!("_{}-{}s", self.start, self.end)
+    }
+}
+
+/// What to download; the same for every attempt
+struct DownloadSpec<'a> {
+    url: &'a str,
+    format_str: String,
+    output_template: String,
+    audio_mp3: bool,
+    section: Option<Section>,
+    extras: &'a DownloadExtras,
+}
+
 /// Run a single yt-dlp download attempt
 async fn run_download(
-    id: &str,
-    url: &str,
-    format_str: &str,
-    output_template: &str,
-    audio_mp3: bool,
-    use_cookies: bool,
-    ws_sender: &Option<WsSender>,
+    spec: &DownloadSpec<'_>,
+    cookies: &CookieOptions,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
 ) -> DownloadResult {
     use std::process::Stdio;
 
+    let id = reporter.id.as_str();
     let ytdlp_cmd = get_ytdlp_command();
     let deno_args = get_deno_args();
-    let ffmpeg_cmd = if audio_mp3 {
-        match find_ffmpeg() {
+    // MP3 extraction and cutting sections both run through ffmpeg
+    let ffmpeg_cmd = if spec.audio_mp3 || spec.section.is_some() {
+        match media::find_ffmpeg() {
             Some(ffmpeg) => Some(ffmpeg),
             None => {
                 return DownloadResult::Failed(Response::error(
                     id,
                     error_codes::DOWNLOAD_FAILED,
-                    FFMPEG_NOT_FOUND_MESSAGE,
+                    if spec.audio_mp3 {
+                        FFMPEG_NOT_FOUND_MESSAGE
+                    } else {
+                        SECTION_FFMPEG_NOT_FOUND_MESSAGE
+                    },
                 ));
             }
         }
@@ -502,7 +589,7 @@ async fn run_download(
     }
 
     let mut args = Vec::<String>::new();
-    if audio_mp3 {
+    if spec.audio_mp3 {
         args.extend([
             "-f".to_string(),
             "bestaudio/best".to_string(),
@@ -512,23 +599,31 @@ async fn run_download(
             "--audio-quality".to_string(),
             "0".to_string(),
         ]);
-        if let Some(ffmpeg_path) = ffmpeg_cmd.as_deref().filter(|path| path.is_absolute()) {
-            args.extend([
-                "--ffmpeg-location".to_string(),
-                ffmpeg_path.to_string_lossy().to_string(),
-            ]);
-        }
     } else {
         args.extend([
             "-f".to_string(),
-            format_str.to_string(),
+            spec.format_str.clone(),
             "--merge-output-format".to_string(),
             "mp4".to_string(),
         ]);
     }
+    if let Some(ffmpeg_path) = ffmpeg_cmd.as_deref().filter(|path| path.is_absolute()) {
+        args.extend([
+            "--ffmpeg-location".to_string(),
+            ffmpeg_path.to_string_lossy().to_string(),
+        ]);
+    }
+    if let Some(section) = spec.section {
+        // Cut on exact frames rather than the nearest keyframes
+        args.extend([
+            "--download-sections".to_string(),
+            section.spec(),
+            "--force-keyframes-at-cuts".to_string(),
+        ]);
+    }
     args.extend([
         "-o".to_string(),
-        output_template.to_string(),
+        spec.output_template.clone(),
         "--print".to_string(),
         "after_move:filepath".to_string(),
         "--no-playlist".to_string(),
@@ -540,15 +635,15 @@ async fn run_download(
         "--windows-filenames".to_string(),
         "--force-ipv4".to_string(),
     ]);
-    if use_cookies {
-        args.extend(["--cookies-from-browser".to_string(), "chrome".to_string()]);
-    }
-    args.push(url.to_string());
+    args.extend(spec.extras.args(media::find_ffmpeg().is_some()));
+    args.extend(cookies.args());
+    args.push(spec.url.to_string());
 
     let mut child = match cmd
         .args(&args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
     {
         Ok(c) => c,
@@ -604,11 +699,24 @@ async fn run_download(
     if let Some(stdout) = stdout {
         let reader = BufReader::new(stdout);
         let mut lines = reader.lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            if line.contains("[download] Destination:") || line.contains("[download] Downloading") {
-                if download_phase == 0 && last_sent_percent > 50 {
-                    download_phase = 1;
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => line,
+                    _ => break,
+                },
+                _ = cancel.cancelled() => {
+                    info!("[yt-dlp] Cancelled, killing download");
+                    let _ = child.kill().await;
+                    return DownloadResult::Cancelled;
                 }
+            };
+
+            if (line.contains("[download] Destination:") || line.contains("[download] Downloading"))
+                && download_phase == 0
+                && last_sent_percent > 50
+            {
+                download_phase = 1;
             }
 
             if line.contains("[Merger]") || line.contains("Merging") {
@@ -616,56 +724,16 @@ async fn run_download(
                 if merge_percent > last_sent_percent {
                     last_sent_percent = merge_percent;
                     info!("[yt-dlp] Merging streams...");
-                    if let Some(ref sender) = ws_sender {
-                        let progress_msg =
-                            Response::download_progress(id, merge_percent, None, None);
-                        let json = serde_json::to_string(&progress_msg).unwrap();
-                        let mut sender = sender.lock().await;
-                        let _ = sender.send(Message::Text(json)).await;
-                    }
+                    reporter
+                        .progress(&JobProgress::stage(job_stages::MERGING, merge_percent as f32))
+                        .await;
                 }
                 continue;
             }
 
             if line.contains('%') {
-                let mut percent_val: Option<f32> = None;
-                if let Some(pct_str) = line.split('%').next() {
-                    let pct_part = pct_str
-                        .trim()
-                        .rsplit_once(' ')
-                        .map(|(_, p)| p)
-                        .unwrap_or(pct_str.trim());
-                    if let Ok(pct) = pct_part.trim().parse::<f32>() {
-                        percent_val = Some(pct.min(100.0));
-                    }
-                }
-
-                if let Some(raw_percent) = percent_val {
-                    let speed: Option<String> = if let Some(at_idx) = line.find(" at ") {
-                        let after_at = &line[at_idx + 4..];
-                        let speed_str = after_at.trim().split_whitespace().next().unwrap_or("");
-                        let cleaned = speed_str.trim_start_matches('~');
-                        if cleaned.contains("/s") {
-                            Some(cleaned.to_string())
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
-                    let eta: Option<String> = if let Some(eta_idx) = line.find("ETA ") {
-                        let after_eta = &line[eta_idx + 4..];
-                        let eta_str = after_eta.trim().split_whitespace().next().unwrap_or("");
-                        if !eta_str.is_empty() && eta_str != "Unknown" {
-                            Some(eta_str.to_string())
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
+                if let Some(mut progress) = parse_progress_line(&line) {
+                    let raw_percent = progress.percent;
                     let overall = match download_phase {
                         0 => (raw_percent * 0.80) as u8,
                         _ => 80 + (raw_percent * 0.15) as u8,
@@ -676,19 +744,10 @@ async fn run_download(
                         last_sent_percent = overall;
                         info!(
                             "[yt-dlp] Phase {} raw={:.1}% overall={}% speed={:?} eta={:?}",
-                            download_phase, raw_percent, overall, speed, eta
+                            download_phase, raw_percent, overall, progress.speed, progress.eta
                         );
-                        if let Some(ref sender) = ws_sender {
-                            let progress_msg = Response::download_progress(
-                                id,
-                                overall,
-                                speed.as_deref(),
-                                eta.as_deref(),
-                            );
-                            let json = serde_json::to_string(&progress_msg).unwrap();
-                            let mut sender = sender.lock().await;
-                            let _ = sender.send(Message::Text(json)).await;
-                        }
+                        progress.percent = overall as f32;
+                        reporter.progress(&progress).await;
                     }
                 }
             } else if line.contains("Downloading") || line.contains("Merging") {
@@ -719,13 +778,13 @@ async fn run_download(
         }
         Ok(s) => {
             // Use full stderr to detect bot-blocking (ERROR line might not always be present)
-            if !use_cookies
+            if cookies.args().is_empty()
                 && (full_stderr.contains("Sign in to confirm")
                     || full_stderr.contains("not a bot")
                     || full_stderr.contains("No title found in player responses"))
             {
                 warn!("YouTube bot detection triggered, will retry with cookies");
-                return DownloadResult::BotBlocked(full_stderr);
+                return DownloadResult::BotBlocked;
             }
 
             // Show ERROR lines to user, or full stderr, or generic message
@@ -751,21 +810,34 @@ async fn run_download(
     }
 }
 
-/// Download a video with progress streaming via WebSocket.
-/// Automatically retries with browser cookies if YouTube bot detection triggers.
+/// Download a video, or only `section` of it, as a job, streaming progress
+/// through `reporter`. Without `cookies`, automatically retries with browser
+/// cookies if YouTube bot detection triggers. The returned response is the
+/// job's final message.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_download(
-    id: &str,
     url: &str,
     format_id: Option<&str>,
     output_dir: Option<&str>,
-    ws_sender: Option<WsSender>,
+    section: Option<Section>,
+    extras: &DownloadExtras,
+    cookies: &CookieOptions,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
 ) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+
     if !url.starts_with("http://") && !url.starts_with("https://") {
         return Response::error(
             id,
             error_codes::INVALID_URL,
             "URL must start with http:// or https://",
-        );
+        )
+        .with_job_id(job_id);
+    }
+    if let Some(Err(message)) = section.map(|section| section.validate()) {
+        return Response::error(id, error_codes::INVALID_ARGUMENT, message).with_job_id(job_id);
     }
 
     let download_dir = output_dir
@@ -777,13 +849,15 @@ pub async fn handle_download(
             id,
             error_codes::PERMISSION_DENIED,
             format!("Cannot create directory: {}", e),
-        );
+        )
+        .with_job_id(job_id);
     }
 
-    info!("Downloading: {} to {:?}", url, download_dir);
+    info!("Downloading: {} to {:?} (job {})", url, download_dir, job_id);
 
+    let suffix = section.map(|section| section.file_suffix()).unwrap_or_default();
     let output_template = download_dir
-        .join("%(title)s.%(ext)s")
+        .join(format!("%(title)s{}.%(ext)s", suffix))
         .to_string_lossy()
         .to_string();
 
@@ -796,48 +870,85 @@ pub async fn handle_download(
         "bestvideo[ext=mp4][vcodec^=avc1]+bestaudio[ext=m4a]/bestvideo[ext=mp4]+bestaudio[ext=m4a]/bestvideo+bestaudio/best[ext=mp4]/best".to_string()
     };
 
-    // Attempt 1: without cookies
-    match run_download(
-        id,
+    let cancelled = || Response::error(id, error_codes::CANCELLED, "Download cancelled").with_job_id(job_id);
+    let complete = |path: String| {
+        quota::record_use(Path::new(&path));
+        quota::enforce_in_background();
+        metrics::add_download(std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0));
+        let mut result = serde_json::json!({ "path": path });
+        extras.collect(Path::new(&path), &mut result);
+        Response::job_complete(id, job_id, result)
+    };
+
+    let spec = DownloadSpec {
         url,
-        &format_str,
-        &output_template,
+        format_str,
+        output_template,
         audio_mp3,
-        false,
-        &ws_sender,
-    )
-    .await
-    {
-        DownloadResult::Success(path) => {
-            return Response::ok(id, serde_json::json!({ "path": path }));
-        }
-        DownloadResult::BotBlocked(_) => {
-            // YouTube wants authentication — retry with Chrome cookies
-            info!("Retrying download with --cookies-from-browser chrome");
+        section,
+        extras,
+    };
+
+    // Attempt 1: with the given cookies, if any
+    match run_download(&spec, cookies, reporter, cancel).await {
+        DownloadResult::Success(path) => return complete(path),
+        DownloadResult::BotBlocked => {
+            // YouTube wants authentication — retry with browser cookies
+            info!("Retrying download with browser cookies");
         }
+        DownloadResult::Cancelled => return cancelled(),
         DownloadResult::Failed(resp) => {
-            return resp;
+            return resp.with_job_id(job_id);
         }
     }
 
-    // Attempt 2: with Chrome cookies
-    match run_download(
-        id,
-        url,
-        &format_str,
-        &output_template,
-        audio_mp3,
-        true,
-        &ws_sender,
-    )
-    .await
-    {
-        DownloadResult::Success(path) => Response::ok(id, serde_json::json!({ "path": path })),
+    // Attempt 2: with cookies from the first installed browser
+    let retry_cookies = fallback_cookies();
+    match run_download(&spec, &retry_cookies, reporter, cancel).await {
+        DownloadResult::Success(path) => complete(path),
+        DownloadResult::Cancelled => cancelled(),
         _ => {
             // If cookies also failed, give a helpful error
             warn!("Download failed even with cookies");
             Response::error(id, error_codes::DOWNLOAD_FAILED,
-                "YouTube requires sign-in for this video. Try closing Chrome completely, then retry.".to_string())
+                format!("YouTube requires sign-in for this video and {} did not work. Close the browser completely and retry, or pass cookies_from_browser / cookies_file.", retry_cookies.describe()))
+            .with_job_id(job_id)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section() {
+        let section = Section { start: 90.0, end: 120.5 };
+        assert!(section.validate().is_ok());
+        assert_eq!(section.spec(), "*90-120.5");
+        assert_eq!(section.file_suffix(), "_90-120.5s");
+
+        assert!(Section { start: 30.0, end: 30.0 }.validate().is_err());
+        assert!(Section { start: -1.0, end: 5.0 }.validate().is_err());
+        assert!(Section { start: 0.0, end: f64::INFINITY }.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_progress_line() {
+        let progress =
+            parse_progress_line("[download]  50.0% of ~ 10.00MiB at  1.25MiB/s ETA 01:05 (frag 3/20)")
+                .unwrap();
+        assert_eq!(progress.percent, 50.0);
+        assert_eq!(progress.bytes_total, Some(10 * 1024 * 1024));
+        assert_eq!(progress.bytes_done, Some(5 * 1024 * 1024));
+        assert_eq!(progress.speed.as_deref(), Some("1.25MiB/s"));
+        assert_eq!(progress.eta_secs, Some(65));
+
+        let unknown = parse_progress_line("[download]   3.1% of 200.00KiB at Unknown B/s ETA Unknown").unwrap();
+        assert_eq!(unknown.speed, None);
+        assert_eq!(unknown.eta_secs, None);
+        assert_eq!(unknown.bytes_total, Some(204800));
+
+        assert!(parse_progress_line("[info] no percent here").is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! NVIDIA GPU enumeration and device selection
+//!
+//! GPUs are listed with `nvidia-smi` on every request rather than once at
+//! startup, so an eGPU that was unplugged (or plugged in) is noticed the
+//! next time a job picks a device. The preferred device is stored by UUID in
+//! `config.toml` (`[media] gpu`); indices change when devices come and go,
+//! UUIDs do not. When the preferred GPU is missing, work falls back to the
+//! driver's default device instead of failing.
+//!
+//! nvidia-smi numbers GPUs in PCI bus order while CUDA and NVENC default to
+//! fastest-first, so ffmpeg commands that are given an index also get
+//! `CUDA_DEVICE_ORDER=PCI_BUS_ID` (see [`use_pci_bus_order`]).
+
+use serde::Serialize;
+use tokio::process::Command as TokioCommand;
+use tracing::warn;
+
+use crate::{config, utils};
+
+/// One NVIDIA GPU as reported by the driver
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpuInfo {
+    /// nvidia-smi index, in PCI bus order. It matches ffmpeg's `-gpu` /
+    /// `-hwaccel_device` only under `CUDA_DEVICE_ORDER=PCI_BUS_ID`.
+    pub index: u32,
+    pub uuid: String,
+    pub name: String,
+    pub driver_version: String,
+    pub memory_total_mb: u64,
+    pub memory_free_mb: u64,
+    /// e.g. "8.6"; missing on drivers older than 510
+    pub compute_capability: Option<String>,
+    /// Codecs the NVDEC generation of this GPU decodes
+    pub nvdec_codecs: Vec<&'static str>,
+}
+
+/// NVDEC codec support by GPU generation (compute capability major.minor).
+///
+/// Follows NVIDIA's Video Codec SDK support matrix at generation
+/// granularity; a few low-end parts of a generation may lack a codec.
+fn nvdec_codecs(compute_capability: Option<&str>) -> Vec<&'static str> {
+    let Some((major, minor)) = compute_capability
+        .and_then(|cc| cc.split_once('.'))
+        .and_then(|(major, minor)| Some((major.parse::<u32>().ok()?, minor.parse::<u32>().ok()?)))
+    else {
+        return Vec::new();
+    };
+    let version = major * 10 + minor;
+
+    let mut codecs = vec!["mpeg2", "vc1", "h264"];
+    if version >= 60 {
+        codecs.extend(["hevc", "vp9"]);
+    }
+    if version >= 80 {
+        codecs.push("av1");
+    }
+    codecs
+}
+
+/// Largest frame side NVDEC decodes for `codec` (from the same matrix).
+pub fn nvdec_max_size(codec: &str) -> Option<u32> {
+    match codec {
+        "h264" => Some(4096),
+        "mpeg2" => Some(4080),
+        "vc1" => Some(2048),
+        "hevc" | "vp9" | "av1" => Some(8192),
+        _ => None,
+    }
+}
+
+const QUERY_FIELDS: &str = "index,uuid,name,driver_version,memory.total,memory.free";
+
+/// Parse `nvidia-smi --format=csv,noheader,nounits` output for `QUERY_FIELDS`.
+fn parse_gpu_list(csv: &str) -> Vec<GpuInfo> {
+    csv.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            let compute_capability = fields
+                .get(6)
+                .filter(|cc| !cc.is_empty() && !cc.starts_with('['))
+                .map(|cc| cc.to_string());
+            Some(GpuInfo {
+                index: fields[0].parse().ok()?,
+                uuid: fields[1].to_string(),
+                name: fields[2].to_string(),
+                driver_version: fields[3].to_string(),
+                memory_total_mb: fields[4].parse().unwrap_or(0),
+                memory_free_mb: fields[5].parse().unwrap_or(0),
+                nvdec_codecs: nvdec_codecs(compute_capability.as_deref()),
+                compute_capability,
+            })
+        })
+        .collect()
+}
+
+fn run_nvidia_smi(fields: &str) -> Option<String> {
+    let mut cmd = std::process::Command::new("nvidia-smi");
+    utils::no_window_std(&mut cmd);
+    let output = cmd
+        .arg(format!("--query-gpu={}", fields))
+        .arg("--format=csv,noheader,nounits")
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// All NVIDIA GPUs currently present; empty without a driver.
+pub fn list() -> Vec<GpuInfo> {
+    // `compute_cap` is rejected by older drivers; query without it then
+    let csv = run_nvidia_smi(&format!("{},compute_cap", QUERY_FIELDS))
+        .or_else(|| run_nvidia_smi(QUERY_FIELDS));
+    csv.map(|csv| parse_gpu_list(&csv)).unwrap_or_default()
+}
+
+/// The preferred GPU if it is present.
+pub fn resolve<'a>(gpus: &'a [GpuInfo], preferred: Option<&str>) -> Option<&'a GpuInfo> {
+    let uuid = preferred?;
+    gpus.iter().find(|gpu| gpu.uuid == uuid)
+}
+
+/// Device index to run GPU work on: the configured GPU when present,
+/// otherwise `None` (driver default). Commands given the index need
+/// [`use_pci_bus_order`].
+pub async fn selected_index() -> Option<u32> {
+    let preferred = config::current().media.gpu?;
+    let gpus = tokio::task::spawn_blocking(list).await.unwrap_or_default();
+    match resolve(&gpus, Some(&preferred)) {
+        Some(gpu) => Some(gpu.index),
+        None => {
+            warn!("Selected GPU {} is not present; using the default device", preferred);
+            None
+        }
+    }
+}
+
+/// Number CUDA devices like nvidia-smi, so `GpuInfo::index` picks the
+/// same card in ffmpeg.
+pub fn use_pci_bus_order(cmd: &mut TokioCommand) {
+    cmd.env("CUDA_DEVICE_ORDER", "PCI_BUS_ID");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gpu_list() {
+        let csv = "0, GPU-aaaa, NVIDIA GeForce RTX 3080, 550.54, 10240, 9000, 8.6\n\
+                   1, GPU-bbbb, Quadro P2000, 470.10, 5120, 5000, [N/A]\n";
+        let gpus = parse_gpu_list(csv);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].memory_total_mb, 10240);
+        assert_eq!(gpus[0].compute_capability.as_deref(), Some("8.6"));
+        assert!(gpus[0].nvdec_codecs.contains(&"av1"));
+        assert_eq!(gpus[1].compute_capability, None);
+        assert!(gpus[1].nvdec_codecs.is_empty());
+
+        assert_eq!(resolve(&gpus, Some("GPU-bbbb")).map(|g| g.index), Some(1));
+        assert!(resolve(&gpus, Some("GPU-gone")).is_none());
+        assert!(resolve(&gpus, None).is_none());
+    }
+
+    #[test]
+    fn test_nvdec_codecs_by_generation() {
+        assert_eq!(nvdec_codecs(Some("5.2")), vec!["mpeg2", "vc1", "h264"]);
+        assert!(nvdec_codecs(Some("6.1")).contains(&"hevc"));
+        assert!(!nvdec_codecs(Some("7.5")).contains(&"av1"));
+        assert!(nvdec_codecs(Some("8.9")).contains(&"av1"));
+        assert!(nvdec_codecs(Some("8.9"))
+            .iter()
+            .all(|codec| nvdec_max_size(codec).is_some()));
+    }
+}
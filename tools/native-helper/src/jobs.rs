@@ -0,0 +1,274 @@
+//! Registry of running jobs
+//!
+//! Each long-running command registers a job owned by the session that
+//! started it. The job's task watches its cancellation token; `cancel_job`
+//! and session disconnects trigger it. Job ownership lives only here: only
+//! the owner may cancel or otherwise control a job, and a closed session's
+//! jobs are cancelled and forgotten.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use futures_util::SinkExt;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::download::WsSender;
+use crate::metrics;
+use crate::protocol::{JobProgress, Response};
+
+/// What a job is doing, reported by `list_sessions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Download,
+    Matte,
+    Transcribe,
+    YoutubeLogin,
+    Upload,
+    AudioSync,
+    AutoDuck,
+    Conform,
+    Transcode,
+    AudioExtract,
+    AudioDenoise,
+    ToolInstall,
+}
+
+impl JobKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Download => "download",
+            JobKind::Matte => "matte",
+            JobKind::Transcribe => "transcribe",
+            JobKind::YoutubeLogin => "youtube_login",
+            JobKind::Upload => "upload",
+            JobKind::AudioSync => "audio_sync",
+            JobKind::AutoDuck => "auto_duck",
+            JobKind::Conform => "conform",
+            JobKind::Transcode => "transcode",
+            JobKind::AudioExtract => "audio_extract",
+            JobKind::AudioDenoise => "audio_denoise",
+            JobKind::ToolInstall => "tool_install",
+        }
+    }
+}
+
+struct JobEntry {
+    kind: JobKind,
+    owner: String,
+    cancel: CancellationToken,
+    started: Instant,
+}
+
+impl JobEntry {
+    fn is_owned_by(&self, session_id: &str) -> bool {
+        self.owner == session_id
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelError {
+    NotFound,
+    NotOwner,
+}
+
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, JobEntry>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a job under a fresh ID.
+    pub fn start(&self, kind: JobKind, owner: &str) -> (String, CancellationToken) {
+        let job_id = uuid::Uuid::new_v4().to_string();
+        let cancel = CancellationToken::new();
+        self.register(&job_id, kind, owner, cancel.clone());
+        (job_id, cancel)
+    }
+
+    /// Register a job whose ID was assigned elsewhere (e.g. by the MatAnyone2
+    /// sidecar). The first registration of an ID wins.
+    pub fn register(&self, job_id: &str, kind: JobKind, owner: &str, cancel: CancellationToken) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.entry(job_id.to_string()).or_insert(JobEntry {
+            kind,
+            owner: owner.to_string(),
+            cancel,
+            started: Instant::now(),
+        });
+    }
+
+    pub fn finish(&self, job_id: &str) {
+        let entry = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(job_id);
+        if let Some(entry) = entry {
+            metrics::record_job(entry.kind.as_str(), entry.started.elapsed());
+        }
+    }
+
+    /// Cancel a job on behalf of `session_id`.
+    pub fn cancel(&self, job_id: &str, session_id: &str) -> Result<JobKind, CancelError> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = jobs.get(job_id).ok_or(CancelError::NotFound)?;
+        if !entry.is_owned_by(session_id) {
+            return Err(CancelError::NotOwner);
+        }
+        entry.cancel.cancel();
+        info!("Cancelling {} job {}", entry.kind.as_str(), job_id);
+        Ok(entry.kind)
+    }
+
+    /// Whether `session_id` owns the running job `job_id`. Finished and unknown
+    /// jobs can't be controlled by anyone.
+    pub fn may_control(&self, job_id: &str, session_id: &str) -> bool {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(job_id)
+            .is_some_and(|entry| entry.is_owned_by(session_id))
+    }
+
+    /// `(job_id, kind)` of every job owned by a session
+    pub fn owned_by(&self, session_id: &str) -> Vec<(String, JobKind)> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(_, entry)| entry.is_owned_by(session_id))
+            .map(|(job_id, entry)| (job_id.clone(), entry.kind))
+            .collect()
+    }
+
+    /// Number of running jobs
+    pub fn count(&self) -> usize {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Cancel every job when the helper shuts down. The jobs stay registered
+    /// until their tasks finish, so their sessions still get the result.
+    pub fn cancel_all(&self) -> usize {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        for (job_id, entry) in jobs.iter() {
+            info!("Shutting down, cancelling {} job {}", entry.kind.as_str(), job_id);
+            entry.cancel.cancel();
+        }
+        jobs.len()
+    }
+
+    /// Cancel all jobs of a closed session; nobody is left to receive their results.
+    pub fn cancel_session(&self, session_id: &str) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.retain(|job_id, entry| {
+            if entry.is_owned_by(session_id) {
+                info!("Session closed, cancelling {} job {}", entry.kind.as_str(), job_id);
+                entry.cancel.cancel();
+                metrics::record_job(entry.kind.as_str(), entry.started.elapsed());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Sends the messages of one job back to the session that started it.
+#[derive(Clone)]
+pub struct JobReporter {
+    pub id: String,
+    pub job_id: String,
+    sender: Option<WsSender>,
+}
+
+impl JobReporter {
+    pub fn new(id: &str, job_id: &str, sender: Option<WsSender>) -> Self {
+        Self {
+            id: id.to_string(),
+            job_id: job_id.to_string(),
+            sender,
+        }
+    }
+
+    pub async fn progress(&self, progress: &JobProgress) {
+        self.send(&Response::job_progress(&self.id, &self.job_id, progress))
+            .await;
+    }
+
+    pub async fn send(&self, response: &Response) {
+        if let Some(ref sender) = self.sender {
+            if let Ok(json) = serde_json::to_string(response) {
+                let mut w = sender.lock().await;
+                let _ = w.send(Message::Text(json)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_requires_owner() {
+        let registry = JobRegistry::new();
+        let (job_id, token) = registry.start(JobKind::Download, "session-a");
+
+        assert_eq!(registry.cancel(&job_id, "session-b"), Err(CancelError::NotOwner));
+        assert!(!token.is_cancelled());
+        assert_eq!(registry.cancel(&job_id, "session-a"), Ok(JobKind::Download));
+        assert!(token.is_cancelled());
+
+        registry.finish(&job_id);
+        assert_eq!(registry.cancel(&job_id, "session-a"), Err(CancelError::NotFound));
+    }
+
+    #[test]
+    fn test_may_control() {
+        let registry = JobRegistry::new();
+        registry.register("job-1", JobKind::Matte, "session-a", CancellationToken::new());
+        registry.register("job-1", JobKind::Matte, "session-b", CancellationToken::new());
+
+        assert!(registry.may_control("job-1", "session-a"));
+        assert!(!registry.may_control("job-1", "session-b"));
+        assert!(!registry.may_control("unknown", "session-a"));
+
+        // Closing the owning session ends its jobs
+        registry.cancel_session("session-a");
+        assert!(!registry.may_control("job-1", "session-a"));
+        assert!(!registry.may_control("job-1", "session-b"));
+    }
+
+    #[test]
+    fn test_closed_session_cancels_its_jobs() {
+        let registry = JobRegistry::new();
+        let (_, mine) = registry.start(JobKind::Download, "session-a");
+        let (other_id, other) = registry.start(JobKind::Download, "session-b");
+
+        registry.cancel_session("session-a");
+        assert!(mine.is_cancelled());
+        assert!(!other.is_cancelled());
+        assert!(registry.owned_by("session-a").is_empty());
+        assert_eq!(registry.owned_by("session-b"), vec![(other_id, JobKind::Download)]);
+    }
+
+    #[test]
+    fn test_cancel_all_keeps_jobs_until_finished() {
+        let registry = JobRegistry::new();
+        let (a, token_a) = registry.start(JobKind::Download, "session-a");
+        let (_, token_b) = registry.start(JobKind::Transcribe, "session-b");
+
+        assert_eq!(registry.cancel_all(), 2);
+        assert!(token_a.is_cancelled() && token_b.is_cancelled());
+        assert_eq!(registry.count(), 2);
+        registry.finish(&a);
+        assert_eq!(registry.count(), 1);
+    }
+}
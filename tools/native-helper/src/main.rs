@@ -13,20 +13,36 @@
     windows_subsystem = "windows"
 )]
 
+mod cloud_sync;
+mod config;
+mod diagnostics;
+mod discovery;
 mod download;
+mod gpu;
+mod jobs;
 mod matanyone;
+mod media;
+mod metrics;
+mod project_lock;
 mod protocol;
+mod quota;
 mod server;
 mod session;
+mod tls;
+mod transcribe;
 #[cfg(windows)]
 mod tray;
-#[cfg(windows)]
 mod updater;
 mod utils;
+mod watch_folders;
+mod youtube;
 
 use clap::Parser;
 use tracing::{error, info, warn, Level};
-use tracing_subscriber::FmtSubscriber;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
 
 /// MasterSelects Native Helper - Download acceleration for masterselects.app
 #[derive(Parser, Debug)]
@@ -34,15 +50,31 @@ use tracing_subscriber::FmtSubscriber;
 #[command(about = "Cross-platform download helper for MasterSelects web application")]
 #[command(version)]
 struct Args {
-    /// Port to listen on
-    #[arg(short, long, default_value = "9876")]
-    port: u16,
+    /// Port to listen on (default: from config.toml, else 9876). Without
+    /// this flag the next free port pair is used when the default is taken.
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Address to listen on (default: from config.toml, else 127.0.0.1). Use
+    /// a LAN address or 0.0.0.0 to serve editors on other machines; this
+    /// requires auth and turns on mDNS.
+    #[arg(long)]
+    bind: Option<std::net::IpAddr>,
+
+    /// Advertise the helper over mDNS so the editor can find it on another port
+    #[arg(long)]
+    mdns: bool,
+
+    /// Serve Prometheus metrics at /metrics on the HTTP port
+    #[arg(long)]
+    metrics: bool,
 
     /// Run in background (minimal output)
     #[arg(long)]
     background: bool,
 
-    /// Allowed origins (comma-separated, empty = allow all localhost)
+    /// Allowed browser origins (comma-separated). Only these exact origins and
+    /// *.masterselects.pages.dev previews may open a WebSocket.
     #[arg(long)]
     allowed_origins: Option<String>,
 
@@ -50,9 +82,9 @@ struct Args {
     #[arg(long)]
     generate_token: bool,
 
-    /// Log level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    /// Log level (trace, debug, info, warn, error; default: from config.toml, else info)
+    #[arg(long)]
+    log_level: Option<String>,
 
     /// Run in console mode (show terminal window, no system tray).
     /// On Linux/macOS this is always the default.
@@ -62,6 +94,18 @@ struct Args {
     /// Disable authentication (NOT recommended for production use)
     #[arg(long)]
     no_auth: bool,
+
+    /// Serve wss:// and https:// using a generated self-signed localhost certificate
+    #[arg(long)]
+    tls: bool,
+
+    /// PEM certificate for TLS (implies --tls, requires --tls-key)
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<std::path::PathBuf>,
+
+    /// PEM private key for TLS (implies --tls, requires --tls-cert)
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<std::path::PathBuf>,
 }
 
 fn main() {
@@ -74,8 +118,14 @@ fn main() {
         return;
     }
 
-    // Initialize logging
-    init_logging(&args);
+    // Read preferences before anything that depends on them
+    let config_result = config::init();
+
+    // Initialize logging (the guard flushes the log file on exit)
+    let _log_guard = init_logging(&args);
+    if let Err(e) = config_result {
+        warn!("{:#}; using default settings", e);
+    }
 
     // Build server config
     let config = build_config(&args);
@@ -84,43 +134,69 @@ fn main() {
     #[cfg(windows)]
     {
         if !args.console {
-            run_with_tray(config, &args);
+            if run_with_tray(config, &args) == server::ExitAction::Restart {
+                restart_helper();
+            }
             return;
         }
     }
 
     // Console mode (all platforms, or --console on Windows)
-    run_console(config, &args);
+    if run_console(config, &args) == server::ExitAction::Restart {
+        restart_helper();
+    }
 }
 
 // ---------------------------------------------------------------------------
 // Setup helpers
 // ---------------------------------------------------------------------------
 
-fn init_logging(args: &Args) {
-    if !args.background {
-        let level = match args.log_level.to_lowercase().as_str() {
-            "trace" => Level::TRACE,
-            "debug" => Level::DEBUG,
-            "info" => Level::INFO,
-            "warn" => Level::WARN,
-            "error" => Level::ERROR,
-            _ => Level::INFO,
-        };
-
-        let _subscriber = FmtSubscriber::builder()
-            .with_max_level(level)
-            .with_target(false)
-            .compact()
-            .init();
-    }
+/// Log to the console (unless --background) and always to the rotating log file.
+fn init_logging(args: &Args) -> Option<WorkerGuard> {
+    let log_level = args
+        .log_level
+        .clone()
+        .unwrap_or_else(|| config::current().helper.log_level);
+    let level = match log_level.to_lowercase().as_str() {
+        "trace" => Level::TRACE,
+        "debug" => Level::DEBUG,
+        "info" => Level::INFO,
+        "warn" => Level::WARN,
+        "error" => Level::ERROR,
+        _ => Level::INFO,
+    };
+
+    let console = (!args.background).then(|| fmt::layer().with_target(false).compact());
+
+    let (file, guard) = match diagnostics::log_file_appender() {
+        Some(appender) => {
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_target(false);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .with(LevelFilter::from_level(level))
+        .init();
+
+    diagnostics::install_panic_hook();
+    guard
 }
 
 fn build_config(args: &Args) -> server::ServerConfig {
+    let settings = config::current().helper;
     let allowed_origins: Vec<String> = args
         .allowed_origins
         .as_ref()
         .map(|s| s.split(',').map(|s| s.trim().to_string()).collect())
+        .or(settings.allowed_origins)
         .unwrap_or_else(|| {
             vec![
                 "https://masterselects.app".to_string(),
@@ -136,6 +212,17 @@ fn build_config(args: &Args) -> server::ServerConfig {
             ]
         });
 
+    let bind = args.bind.unwrap_or(settings.bind);
+    let remote = !bind.is_loopback();
+    if remote && args.no_auth {
+        error!("--no-auth cannot be combined with a non-loopback --bind ({})", bind);
+        eprintln!(
+            "Refusing to listen on {} without authentication. Drop --no-auth or bind to 127.0.0.1.",
+            bind
+        );
+        std::process::exit(1);
+    }
+
     let auth_token = if args.no_auth {
         warn!("Authentication disabled via --no-auth flag. This is NOT recommended for production.");
         None
@@ -145,10 +232,44 @@ fn build_config(args: &Args) -> server::ServerConfig {
         Some(token)
     };
 
+    let tls = match build_tls_settings(args) {
+        Ok(tls) => tls,
+        Err(e) => {
+            error!("TLS setup failed: {}", e);
+            eprintln!("TLS setup failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if remote {
+        warn!("Listening on {}: editors on other machines can connect with the auth token", bind);
+        if tls.is_none() {
+            warn!("Without --tls the token and media cross the network unencrypted, and https editors cannot connect");
+        }
+    }
+
     server::ServerConfig {
-        port: args.port,
+        bind,
+        port: args.port.unwrap_or(settings.port),
+        port_attempts: if args.port.is_some() {
+            1
+        } else {
+            discovery::PORT_ATTEMPTS
+        },
+        mdns: args.mdns || settings.mdns || remote,
+        metrics: args.metrics || settings.metrics,
         allowed_origins,
         auth_token,
+        tls,
+    }
+}
+
+/// Resolve --tls / --tls-cert / --tls-key into certificate paths
+fn build_tls_settings(args: &Args) -> anyhow::Result<Option<tls::TlsSettings>> {
+    match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Ok(Some(tls::settings_from_files(cert, key)?)),
+        _ if args.tls => Ok(Some(tls::ensure_self_signed()?)),
+        _ => Ok(None),
     }
 }
 
@@ -171,7 +292,7 @@ fn write_token_file(token: &str) {
     }
 }
 
-fn print_banner(config: &server::ServerConfig) {
+fn print_banner(config: &server::ServerConfig, ports: discovery::BoundPorts) {
     let ytdlp_path = download::get_ytdlp_command();
     let ytdlp_available = download::find_ytdlp().is_some();
     let deno_available = download::find_deno().is_some();
@@ -194,8 +315,18 @@ fn print_banner(config: &server::ServerConfig) {
     );
     println!("  Platform: {}", os_name);
     println!("========================================================");
-    println!("  WebSocket: ws://127.0.0.1:{}", config.port);
-    println!("  HTTP File: http://127.0.0.1:{}", config.port + 1);
+    let (ws_scheme, http_scheme) = if config.tls.is_some() {
+        ("wss", "https")
+    } else {
+        ("ws", "http")
+    };
+    let ws_addr = std::net::SocketAddr::new(config.bind, ports.ws_port);
+    let http_addr = std::net::SocketAddr::new(config.bind, ports.http_port);
+    println!("  WebSocket: {}://{}", ws_scheme, ws_addr);
+    println!("  HTTP File: {}://{}", http_scheme, http_addr);
+    if !config.bind.is_loopback() {
+        println!("  REMOTE:    Other machines on the network can connect with the token below.");
+    }
     println!(
         "  yt-dlp:    {} [{}]",
         ytdlp_path,
@@ -211,6 +342,16 @@ fn print_banner(config: &server::ServerConfig) {
     );
     println!("  Downloads: {}", utils::get_download_dir().display());
     println!("  Projects:  {}", utils::get_project_root().display());
+    if let Some(tls) = &config.tls {
+        println!("  TLS cert:  {}", tls.cert_path.display());
+        if tls.self_signed {
+            println!(
+                "  TRUST:     Open https://{}/tls-cert and add it to your trusted",
+                http_addr
+            );
+            println!("             roots, or accept the warning at https://{}", http_addr);
+        }
+    }
     match &config.auth_token {
         Some(token) => {
             println!("  Auth:      ENABLED");
@@ -230,22 +371,55 @@ fn print_banner(config: &server::ServerConfig) {
 // Run modes
 // ---------------------------------------------------------------------------
 
-/// Console mode: print banner, run server in a tokio runtime (blocks forever).
-fn run_console(config: server::ServerConfig, args: &Args) {
-    if !args.background {
-        print_banner(&config);
-    }
+/// Console mode: run server in a tokio runtime until it shuts down, printing
+/// the banner once it is bound.
+fn run_console(config: server::ServerConfig, args: &Args) -> server::ExitAction {
+    let banner = (!args.background).then(|| config.clone());
 
     let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-    if let Err(e) = rt.block_on(server::run(config)) {
-        error!("Server error: {}", e);
-        std::process::exit(1);
+    let result = rt.block_on(async move {
+        let server = tokio::spawn(server::run(config));
+        // The servers may fall back to another port pair; show the one in use
+        if let Some(config) = banner {
+            while !server.is_finished() {
+                if let Some(ports) = discovery::bound_ports() {
+                    print_banner(&config, ports);
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        }
+        server.await.map_err(anyhow::Error::from).and_then(|result| result)
+    });
+    let action = match result {
+        Ok(action) => action,
+        Err(e) => {
+            error!("Server error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Don't wait for blocking tasks (e.g. a yt-dlp version check) to finish
+    rt.shutdown_timeout(std::time::Duration::from_secs(1));
+    info!("Helper stopped");
+    action
+}
+
+/// Start a new helper with the same arguments once this one has released its ports.
+fn restart_helper() {
+    let result = std::env::current_exe().and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(std::env::args_os().skip(1))
+            .spawn()
+    });
+    match result {
+        Ok(child) => info!("Restarted helper (pid {})", child.id()),
+        Err(e) => error!("Failed to restart helper: {}", e),
     }
 }
 
 /// Windows tray mode: hide console, tray icon on main thread, server on worker thread.
 #[cfg(windows)]
-fn run_with_tray(config: server::ServerConfig, _args: &Args) {
+fn run_with_tray(config: server::ServerConfig, _args: &Args) -> server::ExitAction {
     use std::sync::Arc;
 
     // Hide the console window
@@ -256,7 +430,7 @@ fn run_with_tray(config: server::ServerConfig, _args: &Args) {
         Some(handle) => handle,
         None => {
             // Another instance is already running — exit silently
-            return;
+            return server::ExitAction::Exit;
         }
     };
 
@@ -268,17 +442,21 @@ fn run_with_tray(config: server::ServerConfig, _args: &Args) {
     // Spawn server on a worker thread (with its own tokio runtime)
     let server_thread = std::thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
-        if let Err(e) = rt.block_on(server::run_with_shutdown(config, state_for_server)) {
-            if let Ok(mut slot) = state_for_error.server_error.lock() {
-                *slot = Some(e.to_string());
+        match rt.block_on(server::run_with_shutdown(config, state_for_server)) {
+            Ok(action) => action,
+            Err(e) => {
+                if let Ok(mut slot) = state_for_error.server_error.lock() {
+                    *slot = Some(e.to_string());
+                }
+                state_for_error
+                    .running
+                    .store(false, std::sync::atomic::Ordering::Relaxed);
+                state_for_error
+                    .quit_requested
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+                eprintln!("Server error: {}", e);
+                server::ExitAction::Exit
             }
-            state_for_error
-                .running
-                .store(false, std::sync::atomic::Ordering::Relaxed);
-            state_for_error
-                .quit_requested
-                .store(true, std::sync::atomic::Ordering::Relaxed);
-            eprintln!("Server error: {}", e);
         }
     });
 
@@ -288,5 +466,5 @@ fn run_with_tray(config: server::ServerConfig, _args: &Args) {
     }
 
     // Wait for the server thread to finish
-    let _ = server_thread.join();
+    server_thread.join().unwrap_or(server::ExitAction::Exit)
 }
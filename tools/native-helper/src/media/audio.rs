@@ -0,0 +1,280 @@
+//! Audio extraction
+//!
+//! `extract_audio` writes a clip's first audio track to its own file, for the
+//! editor's waveforms or for users who only want the sound of a download.
+//! A track that is already in the requested codec is copied, not re-encoded.
+//!
+//! `denoise_audio` renders a cleaned copy with ffmpeg's FFT denoiser, either
+//! tracking the noise floor on its own or learning it from a stretch of the
+//! clip that only holds background noise.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tokio::process::Command as TokioCommand;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::{
+    default_output, ffmpeg_command, ffmpeg_missing, find_ffmpeg, find_ffprobe, probe_duration,
+    render_to,
+};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, Response};
+use crate::utils;
+
+/// Output format of `extract_audio`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFormat {
+    /// 16-bit PCM at the source rate
+    #[default]
+    Wav,
+    Mp3,
+    /// AAC in an `.m4a` file
+    Aac,
+}
+
+impl AudioFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Aac => "m4a",
+        }
+    }
+
+    /// Source codec that can be copied into this format as is
+    fn copyable_codec(&self) -> Option<&'static str> {
+        match self {
+            AudioFormat::Wav => None,
+            AudioFormat::Mp3 => Some("mp3"),
+            AudioFormat::Aac => Some("aac"),
+        }
+    }
+
+    /// ffmpeg output arguments; `copy` keeps the source stream.
+    fn args(&self, copy: bool) -> Vec<&'static str> {
+        let mut args = vec!["-vn", "-sn", "-dn"];
+        match self {
+            _ if copy => args.extend(["-c:a", "copy"]),
+            AudioFormat::Wav => args.extend(["-c:a", "pcm_s16le"]),
+            AudioFormat::Mp3 => args.extend(["-c:a", "libmp3lame", "-q:a", "2"]),
+            AudioFormat::Aac => args.extend(["-c:a", "aac", "-b:a", "256k"]),
+        }
+        let muxer = match self {
+            AudioFormat::Wav => "wav",
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Aac => "ipod",
+        };
+        args.extend(["-f", muxer]);
+        args
+    }
+}
+
+/// Settings of `denoise_audio`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenoiseOptions {
+    /// 0..1, mapped to 6..40 dB of noise reduction
+    pub strength: f32,
+    /// Seconds `(start, end)` of the clip with noise only
+    pub noise_sample: Option<(f64, f64)>,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            strength: 0.5,
+            noise_sample: None,
+        }
+    }
+}
+
+impl DenoiseOptions {
+    /// The `-af` filter chain.
+    pub fn filter(&self) -> Result<String, String> {
+        if !(0.0..=1.0).contains(&self.strength) {
+            return Err(format!("Strength {} is out of range (0-1)", self.strength));
+        }
+        let reduction = 6.0 + self.strength * 34.0;
+        match self.noise_sample {
+            None => Ok(format!("afftdn=nr={:.1}:tn=1", reduction)),
+            Some((start, end)) if start >= 0.0 && end - start >= 0.1 => Ok(format!(
+                "asendcmd=c='{:.3} afftdn sn start',asendcmd=c='{:.3} afftdn sn stop',afftdn=nr={:.1}",
+                start, end, reduction
+            )),
+            Some(_) => Err("The noise sample must be at least 0.1 s long".to_string()),
+        }
+    }
+}
+
+/// Codec of the first audio track, `None` when there is no audio.
+async fn probe_audio_codec(ffprobe: &Path, path: &Path) -> Result<Option<String>, String> {
+    let mut cmd = TokioCommand::new(ffprobe);
+    utils::no_window(&mut cmd);
+    let output = cmd
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "a:0",
+            "-show_entries",
+            "stream=codec_name",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let codec = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!codec.is_empty()).then_some(codec))
+}
+
+/// `extract_audio` job: write the audio of `path` to `output` (default:
+/// `<name>_audio.<ext>` next to the source).
+pub async fn handle_extract_audio(
+    path: &Path,
+    format: AudioFormat,
+    output: Option<PathBuf>,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let output = output.unwrap_or_else(|| default_output(path, "audio", format.extension()));
+    render_audio(path, &output, format, None, job_stages::EXTRACTING_AUDIO, reporter, cancel).await
+}
+
+/// `denoise_audio` job: write a noise-reduced copy of the audio of `path`
+/// to `output` (default: `<name>_denoised.<ext>` next to the source).
+pub async fn handle_denoise_audio(
+    path: &Path,
+    options: DenoiseOptions,
+    format: AudioFormat,
+    output: Option<PathBuf>,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let filter = match options.filter() {
+        Ok(filter) => filter,
+        Err(e) => {
+            return Response::error(&reporter.id, error_codes::INVALID_ARGUMENT, e)
+                .with_job_id(&reporter.job_id)
+        }
+    };
+    let output = output.unwrap_or_else(|| default_output(path, "denoised", format.extension()));
+    render_audio(path, &output, format, Some(filter), job_stages::PROCESSING, reporter, cancel).await
+}
+
+/// Write the first audio track of `path` to `output`, through `filter` when
+/// given. Without a filter a track already in the target codec is copied.
+async fn render_audio(
+    path: &Path,
+    output: &Path,
+    format: AudioFormat,
+    filter: Option<String>,
+    stage: &str,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+
+    let (Some(ffmpeg), Some(ffprobe)) = (find_ffmpeg(), find_ffprobe()) else {
+        return ffmpeg_missing(id).with_job_id(job_id);
+    };
+    let codec = match probe_audio_codec(&ffprobe, path).await {
+        Ok(Some(codec)) => codec,
+        Ok(None) => return fail(error_codes::MEDIA_FAILED, "The file has no audio track".to_string()),
+        Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+    };
+    let duration = match probe_duration(&ffprobe, path).await {
+        Ok(duration) => duration,
+        Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+    };
+
+    let copy = filter.is_none() && format.copyable_codec() == Some(codec.as_str());
+
+    let mut args: Vec<OsString> = vec!["-i".into(), path.into()];
+    args.extend(["-map", "0:a:0"].map(OsString::from));
+    if let Some(filter) = &filter {
+        args.extend(["-af".into(), filter.into()]);
+    }
+    args.extend(format.args(copy).into_iter().map(OsString::from));
+
+    info!(
+        "Writing {} audio from {} to {}{}",
+        codec,
+        path.display(),
+        output.display(),
+        match &filter {
+            Some(filter) => format!(" ({})", filter),
+            None if copy => " (copy)".to_string(),
+            None => String::new(),
+        }
+    );
+    let cmd = ffmpeg_command(&ffmpeg);
+    match render_to(cmd, args, output, duration, stage, reporter, cancel).await {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+        None => return fail(error_codes::CANCELLED, "Audio job cancelled".to_string()),
+    }
+
+    Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "path": output,
+            "duration": duration,
+            "source_codec": codec,
+            "copied": copy,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_format_args() {
+        let format: AudioFormat = serde_json::from_str(r#""aac""#).unwrap();
+        assert_eq!(format.extension(), "m4a");
+        assert_eq!(format.copyable_codec(), Some("aac"));
+        assert!(format.args(true).windows(2).any(|w| w == ["-c:a", "copy"]));
+        assert!(format.args(false).windows(2).any(|w| w == ["-f", "ipod"]));
+
+        let wav = AudioFormat::default();
+        assert_eq!(wav.copyable_codec(), None);
+        assert!(wav.args(false).windows(2).any(|w| w == ["-c:a", "pcm_s16le"]));
+    }
+
+    #[test]
+    fn test_denoise_filter() {
+        let adaptive = DenoiseOptions::default();
+        assert_eq!(adaptive.filter().unwrap(), "afftdn=nr=23.0:tn=1");
+
+        let sampled = DenoiseOptions {
+            strength: 1.0,
+            noise_sample: Some((0.5, 2.0)),
+        };
+        assert_eq!(
+            sampled.filter().unwrap(),
+            "asendcmd=c='0.500 afftdn sn start',asendcmd=c='2.000 afftdn sn stop',afftdn=nr=40.0"
+        );
+
+        let too_short = DenoiseOptions {
+            strength: 0.5,
+            noise_sample: Some((1.0, 1.05)),
+        };
+        assert!(too_short.filter().is_err());
+        assert!(DenoiseOptions { strength: 2.0, ..adaptive }.filter().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Decoder and hardware acceleration detection
+//!
+//! `ffmpeg -hwaccels` only says which acceleration APIs ffmpeg was built
+//! with; each one is then confirmed by creating a device, which fails fast
+//! when the driver or GPU is missing. Like encoder detection this runs once
+//! per helper run.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::Serialize;
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use super::ffmpeg_command;
+use crate::metrics;
+
+/// Hardware decode APIs the editor can make use of, with their
+/// `-hwaccel` / `-init_hw_device` names
+const HWACCELS: &[(&str, &str)] = &[
+    ("nvdec", "cuda"),
+    ("qsv", "qsv"),
+    ("vaapi", "vaapi"),
+    ("videotoolbox", "videotoolbox"),
+    ("d3d11va", "d3d11va"),
+    ("dxva2", "dxva2"),
+];
+
+/// Codecs reported to the editor, with the ffmpeg decoders that handle them
+const CODECS: &[(&str, &[&str])] = &[
+    ("h264", &["h264"]),
+    ("hevc", &["hevc"]),
+    ("vp8", &["vp8", "libvpx"]),
+    ("vp9", &["vp9", "libvpx-vp9"]),
+    ("av1", &["libdav1d", "av1", "libaom-av1"]),
+    ("prores", &["prores"]),
+    ("dnxhd", &["dnxhd"]),
+    ("mpeg2", &["mpeg2video"]),
+    ("mjpeg", &["mjpeg"]),
+    ("aac", &["aac"]),
+    ("opus", &["opus", "libopus"]),
+    ("flac", &["flac"]),
+];
+
+/// One hardware decode API
+#[derive(Debug, Clone, Serialize)]
+pub struct HwAccel {
+    pub api: &'static str,
+    pub hwaccel: &'static str,
+    /// A device could be created, so decoding through it should work
+    pub usable: bool,
+}
+
+/// What this ffmpeg build can decode
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DecoderCapabilities {
+    /// APIs ffmpeg was built with
+    pub hwaccels: Vec<HwAccel>,
+    /// Codec -> a software decoder is available
+    pub codecs: BTreeMap<&'static str, bool>,
+}
+
+static CAPABILITIES: OnceCell<DecoderCapabilities> = OnceCell::const_new();
+
+/// Detect decoders once per helper run.
+pub async fn capabilities(ffmpeg: &Path) -> &'static DecoderCapabilities {
+    metrics::record_cache("decoder_capabilities", CAPABILITIES.initialized());
+    CAPABILITIES
+        .get_or_init(|| async {
+            let caps = detect(ffmpeg).await;
+            info!("Decoder capabilities: {:?}", caps);
+            caps
+        })
+        .await
+}
+
+/// Words of a `-decoders` / `-hwaccels` listing; names are matched whole.
+fn listed_words(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+async fn detect(ffmpeg: &Path) -> DecoderCapabilities {
+    let mut caps = DecoderCapabilities::default();
+
+    if let Ok(output) = ffmpeg_command(ffmpeg).arg("-decoders").output().await {
+        let words = listed_words(&output.stdout);
+        for (codec, decoders) in CODECS {
+            let available = decoders.iter().any(|d| words.iter().any(|w| w == d));
+            caps.codecs.insert(codec, available);
+        }
+    }
+
+    if let Ok(output) = ffmpeg_command(ffmpeg).arg("-hwaccels").output().await {
+        let words = listed_words(&output.stdout);
+        for &(api, hwaccel) in HWACCELS {
+            if words.iter().any(|w| w == hwaccel) {
+                caps.hwaccels.push(HwAccel {
+                    api,
+                    hwaccel,
+                    usable: test_device(ffmpeg, hwaccel).await,
+                });
+            }
+        }
+    }
+    caps
+}
+
+async fn test_device(ffmpeg: &Path, hwaccel: &str) -> bool {
+    ffmpeg_command(ffmpeg)
+        .args(["-init_hw_device", hwaccel])
+        .args(["-f", "lavfi", "-i", "color=black:s=64x64:d=0.04"])
+        .args(["-frames:v", "1", "-f", "null", "-"])
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
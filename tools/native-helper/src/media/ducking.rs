@@ -0,0 +1,237 @@
+//! Auto-ducking of music under dialogue
+//!
+//! The dialogue recording is decoded at the sync analysis rate and split
+//! into 20 ms windows; windows above a level threshold count as speech.
+//! Pauses too short for the music to come back up are bridged, and every
+//! speech region becomes a dip of volume keyframes (ramp down over `attack`
+//! before it, back up over `release` after it) that the web app puts on the
+//! music tracks, shifted by the dialogue clip's timeline position.
+
+use std::path::Path;
+
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use super::find_ffmpeg;
+use super::sync::{decode_audio, SAMPLE_RATE};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+
+/// Length of one level measurement
+const WINDOW_SECONDS: f64 = 0.02;
+/// Shorter bursts (clicks, bumps) are not speech
+const MIN_SPEECH_SECONDS: f64 = 0.1;
+
+/// Settings of an `auto_duck` analysis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckOptions {
+    /// How far the music dips, in dB
+    pub duck_db: f32,
+    /// Dialogue level (dBFS RMS) counted as speech
+    pub threshold_db: f32,
+    /// Seconds the music takes to dip before speech
+    pub attack: f64,
+    /// Seconds the music takes to come back after speech
+    pub release: f64,
+}
+
+impl Default for DuckOptions {
+    fn default() -> Self {
+        Self {
+            duck_db: 12.0,
+            threshold_db: -38.0,
+            attack: 0.15,
+            release: 0.6,
+        }
+    }
+}
+
+impl DuckOptions {
+    fn clamped(self) -> Self {
+        Self {
+            duck_db: self.duck_db.clamp(1.0, 60.0),
+            threshold_db: self.threshold_db.clamp(-90.0, 0.0),
+            attack: self.attack.clamp(0.0, 5.0),
+            release: self.release.clamp(0.0, 10.0),
+        }
+    }
+}
+
+/// A stretch of the dialogue recording with speech, in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SpeechRegion {
+    pub start: f64,
+    pub end: f64,
+}
+
+/// A volume keyframe for the music tracks, relative to the dialogue start
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GainKeyframe {
+    pub time: f64,
+    pub gain_db: f32,
+}
+
+/// Regions of `samples` louder than `threshold_db`. Gaps up to `bridge`
+/// seconds are merged into the surrounding region.
+pub fn speech_regions(samples: &[f32], rate: u32, threshold_db: f32, bridge: f64) -> Vec<SpeechRegion> {
+    let window = ((rate as f64 * WINDOW_SECONDS) as usize).max(1);
+    let mut regions: Vec<SpeechRegion> = Vec::new();
+
+    for (index, chunk) in samples.chunks(window).enumerate() {
+        let power = chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32;
+        let level_db = 10.0 * power.max(1e-12).log10();
+        if level_db < threshold_db {
+            continue;
+        }
+
+        let start = (index * window) as f64 / rate as f64;
+        let end = (index * window + chunk.len()) as f64 / rate as f64;
+        match regions.last_mut() {
+            Some(last) if start - last.end <= bridge => last.end = end,
+            _ => regions.push(SpeechRegion { start, end }),
+        }
+    }
+
+    regions.retain(|region| region.end - region.start >= MIN_SPEECH_SECONDS);
+    regions
+}
+
+/// Volume keyframes that dip the music by `duck_db` over every region.
+pub fn duck_keyframes(regions: &[SpeechRegion], options: &DuckOptions) -> Vec<GainKeyframe> {
+    let ducked = -options.duck_db;
+    let mut keyframes = Vec::with_capacity(regions.len() * 4);
+    for region in regions {
+        let ramp_start = (region.start - options.attack).max(0.0);
+        if ramp_start < region.start {
+            keyframes.push(GainKeyframe {
+                time: ramp_start,
+                gain_db: 0.0,
+            });
+        }
+        keyframes.push(GainKeyframe {
+            time: region.start,
+            gain_db: ducked,
+        });
+        keyframes.push(GainKeyframe {
+            time: region.end,
+            gain_db: ducked,
+        });
+        keyframes.push(GainKeyframe {
+            time: region.end + options.release,
+            gain_db: 0.0,
+        });
+    }
+    keyframes
+}
+
+/// `auto_duck` job: speech regions of `dialogue` and the matching keyframes.
+pub async fn handle_auto_duck(
+    dialogue: &Path,
+    options: DuckOptions,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+    let cancelled = || fail(error_codes::CANCELLED, "Auto-ducking cancelled".to_string());
+
+    let Some(ffmpeg) = find_ffmpeg() else {
+        return fail(
+            error_codes::FFMPEG_NOT_FOUND,
+            "Auto-ducking requires ffmpeg on PATH or next to the helper.".to_string(),
+        );
+    };
+
+    reporter
+        .progress(&JobProgress::stage(job_stages::EXTRACTING_AUDIO, 0.0))
+        .await;
+    let samples = match decode_audio(&ffmpeg, dialogue, None, cancel).await {
+        Some(Ok(samples)) => samples,
+        Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+        None => return cancelled(),
+    };
+
+    reporter
+        .progress(&JobProgress::stage(job_stages::ANALYZING, 50.0))
+        .await;
+    let options = options.clamped();
+    let duration = samples.len() as f64 / SAMPLE_RATE as f64;
+    let analysis = tokio::task::spawn_blocking(move || {
+        let regions = speech_regions(
+            &samples,
+            SAMPLE_RATE,
+            options.threshold_db,
+            options.attack + options.release,
+        );
+        let keyframes = duck_keyframes(&regions, &options);
+        (regions, keyframes)
+    });
+    let (regions, keyframes) = tokio::select! {
+        result = analysis => match result {
+            Ok(result) => result,
+            Err(e) => return fail(error_codes::INTERNAL_ERROR, e.to_string()),
+        },
+        _ = cancel.cancelled() => return cancelled(),
+    };
+
+    Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "dialogue": dialogue,
+            "duration": duration,
+            "speech": regions,
+            "keyframes": keyframes,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `seconds` of a 200 Hz tone at `amplitude`, sampled at `SAMPLE_RATE`
+    fn tone(seconds: f64, amplitude: f32) -> Vec<f32> {
+        let len = (seconds * SAMPLE_RATE as f64) as usize;
+        (0..len)
+            .map(|i| amplitude * (i as f32 * 200.0 * std::f32::consts::TAU / SAMPLE_RATE as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_speech_regions() {
+        // 1 s silence, 1 s speech, 0.3 s pause, 1 s speech, 2 s silence, a 40 ms click
+        let mut samples = tone(1.0, 0.0);
+        samples.extend(tone(1.0, 0.3));
+        samples.extend(tone(0.3, 0.0));
+        samples.extend(tone(1.0, 0.3));
+        samples.extend(tone(2.0, 0.0));
+        samples.extend(tone(0.04, 0.5));
+        samples.extend(tone(1.0, 0.0));
+
+        let regions = speech_regions(&samples, SAMPLE_RATE, -38.0, 0.75);
+        assert_eq!(regions.len(), 1);
+        assert!((regions[0].start - 1.0).abs() < 0.021);
+        assert!((regions[0].end - 3.3).abs() < 0.021);
+
+        // Without bridging the pause splits the region
+        assert_eq!(speech_regions(&samples, SAMPLE_RATE, -38.0, 0.1).len(), 2);
+    }
+
+    #[test]
+    fn test_duck_keyframes() {
+        let options = DuckOptions::default();
+        let regions = [
+            SpeechRegion { start: 0.0, end: 2.0 },
+            SpeechRegion { start: 5.0, end: 6.0 },
+        ];
+        let keyframes = duck_keyframes(&regions, &options);
+        let times: Vec<f64> = keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 2.0, 2.6, 4.85, 5.0, 6.0, 6.6]);
+        assert_eq!(keyframes[0].gain_db, -12.0);
+        assert_eq!(keyframes[2].gain_db, 0.0);
+        assert_eq!(keyframes[3].gain_db, 0.0);
+        assert_eq!(keyframes[4].gain_db, -12.0);
+    }
+}
@@ -0,0 +1,485 @@
+//! Video encoder selection
+//!
+//! Picks an ffmpeg encoder for a codec and turns rate-control settings into
+//! ffmpeg arguments. NVENC is used when ffmpeg was built with it and a test
+//! encode succeeds on this machine; otherwise the software encoder is used.
+//! ProRes and DNxHR mezzanine profiles go through ffmpeg's software encoders.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::info;
+
+use super::ffmpeg_command;
+use crate::metrics;
+
+/// Output video codec
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+}
+
+impl VideoCodec {
+    fn nvenc_encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264_nvenc",
+            VideoCodec::Hevc => "hevc_nvenc",
+        }
+    }
+
+    fn software_encoder(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "libx264",
+            VideoCodec::Hevc => "libx265",
+        }
+    }
+}
+
+/// Rate control mode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RateControl {
+    /// Constant quality (x264/x265 CRF, NVENC `-cq`); lower is better
+    Crf { value: u8 },
+    /// Constant quantizer
+    Cqp { qp: u8 },
+    /// Constant bitrate
+    Cbr { bitrate_kbps: u32 },
+    /// Variable bitrate with a peak limit
+    Vbr { bitrate_kbps: u32, max_kbps: u32 },
+}
+
+impl Default for RateControl {
+    fn default() -> Self {
+        RateControl::Crf { value: 20 }
+    }
+}
+
+/// Which encoder family to use
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderPreference {
+    /// NVENC when available, software otherwise
+    #[default]
+    Auto,
+    Hardware,
+    Software,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Nvenc,
+    Software,
+}
+
+/// Encoders usable on this machine
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EncoderCapabilities {
+    pub ffmpeg_version: Option<String>,
+    pub nvenc_h264: bool,
+    pub nvenc_hevc: bool,
+    pub libx264: bool,
+    pub libx265: bool,
+    pub prores_ks: bool,
+    pub dnxhd: bool,
+}
+
+impl EncoderCapabilities {
+    fn has_nvenc(&self, codec: VideoCodec) -> bool {
+        match codec {
+            VideoCodec::H264 => self.nvenc_h264,
+            VideoCodec::Hevc => self.nvenc_hevc,
+        }
+    }
+
+    fn has_software(&self, codec: VideoCodec) -> bool {
+        match codec {
+            VideoCodec::H264 => self.libx264,
+            VideoCodec::Hevc => self.libx265,
+        }
+    }
+
+    pub fn has_mezzanine(&self, profile: MezzanineProfile) -> bool {
+        if profile.is_prores() {
+            self.prores_ks
+        } else {
+            self.dnxhd
+        }
+    }
+}
+
+/// The encoder chosen for a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SelectedEncoder {
+    pub backend: Backend,
+    pub encoder: &'static str,
+    /// GPU index for NVENC; the driver's default device when `None`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<u32>,
+}
+
+static CAPABILITIES: OnceCell<EncoderCapabilities> = OnceCell::const_new();
+
+/// Detect encoders once per helper run.
+pub async fn capabilities(ffmpeg: &Path) -> &'static EncoderCapabilities {
+    metrics::record_cache("encoder_capabilities", CAPABILITIES.initialized());
+    CAPABILITIES
+        .get_or_init(|| async {
+            let caps = detect(ffmpeg).await;
+            info!("Encoder capabilities: {:?}", caps);
+            caps
+        })
+        .await
+}
+
+async fn detect(ffmpeg: &Path) -> EncoderCapabilities {
+    let mut caps = EncoderCapabilities::default();
+
+    if let Ok(output) = ffmpeg_command(ffmpeg).arg("-version").output().await {
+        caps.ffmpeg_version = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("ffmpeg version "))
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(str::to_string);
+    }
+
+    let listed = match ffmpeg_command(ffmpeg).arg("-encoders").output().await {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(_) => return caps,
+    };
+    let has = |name: &str| listed.split_whitespace().any(|word| word == name);
+
+    caps.libx264 = has("libx264");
+    caps.libx265 = has("libx265");
+    caps.prores_ks = has("prores_ks");
+    caps.dnxhd = has("dnxhd");
+    // A listed NVENC encoder only means ffmpeg was built with it; a tiny
+    // test encode confirms there is a usable NVIDIA GPU and driver.
+    caps.nvenc_h264 = has("h264_nvenc") && test_encode(ffmpeg, "h264_nvenc").await;
+    caps.nvenc_hevc = has("hevc_nvenc") && test_encode(ffmpeg, "hevc_nvenc").await;
+    caps
+}
+
+async fn test_encode(ffmpeg: &Path, encoder: &str) -> bool {
+    ffmpeg_command(ffmpeg)
+        .args([
+            "-f",
+            "lavfi",
+            "-i",
+            "color=black:s=256x256:d=0.1",
+            "-frames:v",
+            "1",
+            "-c:v",
+            encoder,
+            "-f",
+            "null",
+            "-",
+        ])
+        .output()
+        .await
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Choose the encoder for `codec`, falling back to software when NVENC is unavailable.
+pub fn select(
+    codec: VideoCodec,
+    preference: EncoderPreference,
+    caps: &EncoderCapabilities,
+) -> Result<SelectedEncoder, String> {
+    let nvenc = SelectedEncoder {
+        backend: Backend::Nvenc,
+        encoder: codec.nvenc_encoder(),
+        gpu: None,
+    };
+    let software = SelectedEncoder {
+        backend: Backend::Software,
+        encoder: codec.software_encoder(),
+        gpu: None,
+    };
+
+    match preference {
+        EncoderPreference::Hardware if caps.has_nvenc(codec) => Ok(nvenc),
+        EncoderPreference::Hardware => Err(format!("{} is not available", codec.nvenc_encoder())),
+        EncoderPreference::Auto if caps.has_nvenc(codec) => Ok(nvenc),
+        _ if caps.has_software(codec) => Ok(software),
+        _ => Err(format!(
+            "No {} encoder available in this ffmpeg build",
+            codec.software_encoder()
+        )),
+    }
+}
+
+/// ffmpeg output arguments for the selected encoder and rate control.
+pub fn video_args(codec: VideoCodec, selected: &SelectedEncoder, rate: RateControl) -> Vec<String> {
+    let mut args: Vec<String> = vec!["-c:v".into(), selected.encoder.into()];
+
+    match selected.backend {
+        Backend::Nvenc => {
+            args.extend(["-preset".into(), "p5".into()]);
+            if let Some(gpu) = selected.gpu {
+                args.extend(["-gpu".into(), gpu.to_string()]);
+            }
+            match rate {
+                RateControl::Crf { value } => args.extend([
+                    "-rc".into(),
+                    "vbr".into(),
+                    "-cq".into(),
+                    value.to_string(),
+                    "-b:v".into(),
+                    "0".into(),
+                ]),
+                RateControl::Cqp { qp } => {
+                    args.extend(["-rc".into(), "constqp".into(), "-qp".into(), qp.to_string()])
+                }
+                RateControl::Cbr { bitrate_kbps } => args.extend([
+                    "-rc".into(),
+                    "cbr".into(),
+                    "-b:v".into(),
+                    format!("{}k", bitrate_kbps),
+                    "-maxrate".into(),
+                    format!("{}k", bitrate_kbps),
+                    "-bufsize".into(),
+                    format!("{}k", bitrate_kbps * 2),
+                ]),
+                RateControl::Vbr {
+                    bitrate_kbps,
+                    max_kbps,
+                } => args.extend([
+                    "-rc".into(),
+                    "vbr".into(),
+                    "-b:v".into(),
+                    format!("{}k", bitrate_kbps),
+                    "-maxrate".into(),
+                    format!("{}k", max_kbps.max(bitrate_kbps)),
+                    "-bufsize".into(),
+                    format!("{}k", max_kbps.max(bitrate_kbps) * 2),
+                ]),
+            }
+        }
+        Backend::Software => {
+            args.extend(["-preset".into(), "medium".into()]);
+            match rate {
+                RateControl::Crf { value } => args.extend(["-crf".into(), value.to_string()]),
+                RateControl::Cqp { qp } => args.extend(["-qp".into(), qp.to_string()]),
+                RateControl::Cbr { bitrate_kbps } => {
+                    args.extend([
+                        "-b:v".into(),
+                        format!("{}k", bitrate_kbps),
+                        "-minrate".into(),
+                        format!("{}k", bitrate_kbps),
+                        "-maxrate".into(),
+                        format!("{}k", bitrate_kbps),
+                        "-bufsize".into(),
+                        format!("{}k", bitrate_kbps * 2),
+                    ]);
+                    if codec == VideoCodec::H264 {
+                        args.extend(["-x264-params".into(), "nal-hrd=cbr".into()]);
+                    }
+                }
+                RateControl::Vbr {
+                    bitrate_kbps,
+                    max_kbps,
+                } => args.extend([
+                    "-b:v".into(),
+                    format!("{}k", bitrate_kbps),
+                    "-maxrate".into(),
+                    format!("{}k", max_kbps.max(bitrate_kbps)),
+                    "-bufsize".into(),
+                    format!("{}k", max_kbps.max(bitrate_kbps) * 2),
+                ]),
+            }
+        }
+    }
+
+    // 8-bit 4:2:0 decodes everywhere, including browsers
+    args.extend(["-pix_fmt".into(), "yuv420p".into()]);
+    if codec == VideoCodec::Hevc {
+        // Lets Safari/QuickTime recognise HEVC in MP4/MOV
+        args.extend(["-tag:v".into(), "hvc1".into()]);
+    }
+    args
+}
+
+/// Intra-frame mezzanine formats for finishing and grading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MezzanineProfile {
+    Prores422,
+    Prores422Hq,
+    Prores4444,
+    DnxhrLb,
+    DnxhrSq,
+    DnxhrHq,
+}
+
+impl MezzanineProfile {
+    pub const ALL: [MezzanineProfile; 6] = [
+        MezzanineProfile::Prores422,
+        MezzanineProfile::Prores422Hq,
+        MezzanineProfile::Prores4444,
+        MezzanineProfile::DnxhrLb,
+        MezzanineProfile::DnxhrSq,
+        MezzanineProfile::DnxhrHq,
+    ];
+
+    fn is_prores(&self) -> bool {
+        matches!(
+            self,
+            MezzanineProfile::Prores422 | MezzanineProfile::Prores422Hq | MezzanineProfile::Prores4444
+        )
+    }
+
+    /// Containers finishing tools accept for this profile
+    pub fn containers(&self) -> &'static [Container] {
+        if self.is_prores() {
+            &[Container::Mov]
+        } else {
+            &[Container::Mov, Container::Mxf]
+        }
+    }
+
+    /// `-c:v`, `-profile:v` and `-pix_fmt` for this profile
+    fn codec_args(&self) -> [&'static str; 6] {
+        let (encoder, profile, pix_fmt) = match self {
+            MezzanineProfile::Prores422 => ("prores_ks", "2", "yuv422p10le"),
+            MezzanineProfile::Prores422Hq => ("prores_ks", "3", "yuv422p10le"),
+            // 4444 keeps the alpha channel
+            MezzanineProfile::Prores4444 => ("prores_ks", "4", "yuva444p10le"),
+            MezzanineProfile::DnxhrLb => ("dnxhd", "dnxhr_lb", "yuv422p"),
+            MezzanineProfile::DnxhrSq => ("dnxhd", "dnxhr_sq", "yuv422p"),
+            MezzanineProfile::DnxhrHq => ("dnxhd", "dnxhr_hq", "yuv422p"),
+        };
+        ["-c:v", encoder, "-profile:v", profile, "-pix_fmt", pix_fmt]
+    }
+}
+
+/// Output container for mezzanine exports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Container {
+    #[default]
+    Mov,
+    Mxf,
+}
+
+impl Container {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Mov => "mov",
+            Container::Mxf => "mxf",
+        }
+    }
+}
+
+/// ffmpeg output arguments for a mezzanine export, including Rec.709 color
+/// tags and uncompressed PCM audio.
+pub fn mezzanine_args(profile: MezzanineProfile, container: Container) -> Result<Vec<String>, String> {
+    if !profile.containers().contains(&container) {
+        return Err(format!(
+            "{:?} cannot be written to {}",
+            profile,
+            container.extension().to_uppercase()
+        ));
+    }
+
+    let mut args: Vec<String> = profile.codec_args().iter().map(|a| a.to_string()).collect();
+    if profile.is_prores() {
+        // Apple vendor tag; some players reject ProRes without it
+        args.extend(["-vendor".into(), "apl0".into()]);
+    }
+    // Untagged files are guessed differently by every NLE; be explicit
+    args.extend(
+        [
+            "-color_primaries",
+            "bt709",
+            "-color_trc",
+            "bt709",
+            "-colorspace",
+            "bt709",
+            "-color_range",
+            "tv",
+            "-c:a",
+            "pcm_s24le",
+            "-f",
+            container.extension(),
+        ]
+        .map(String::from),
+    );
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(nvenc: bool) -> EncoderCapabilities {
+        EncoderCapabilities {
+            ffmpeg_version: None,
+            nvenc_h264: nvenc,
+            nvenc_hevc: nvenc,
+            libx264: true,
+            libx265: true,
+            prores_ks: true,
+            dnxhd: true,
+        }
+    }
+
+    #[test]
+    fn test_select_falls_back_to_software() {
+        let auto = select(VideoCodec::H264, EncoderPreference::Auto, &caps(false)).unwrap();
+        assert_eq!(auto.encoder, "libx264");
+        let auto = select(VideoCodec::Hevc, EncoderPreference::Auto, &caps(true)).unwrap();
+        assert_eq!(auto.encoder, "hevc_nvenc");
+
+        assert!(select(VideoCodec::H264, EncoderPreference::Hardware, &caps(false)).is_err());
+        let forced = select(VideoCodec::H264, EncoderPreference::Software, &caps(true)).unwrap();
+        assert_eq!(forced.backend, Backend::Software);
+    }
+
+    #[test]
+    fn test_rate_control_args() {
+        let mut nvenc = select(VideoCodec::H264, EncoderPreference::Auto, &caps(true)).unwrap();
+        let args = video_args(VideoCodec::H264, &nvenc, RateControl::Cqp { qp: 23 });
+        assert!(args.windows(2).any(|w| w == ["-rc", "constqp"]));
+        assert!(args.windows(2).any(|w| w == ["-qp", "23"]));
+        assert!(!args.contains(&"-gpu".to_string()));
+        nvenc.gpu = Some(1);
+        let args = video_args(VideoCodec::H264, &nvenc, RateControl::Cqp { qp: 23 });
+        assert!(args.windows(2).any(|w| w == ["-gpu", "1"]));
+
+        let x264 = select(VideoCodec::H264, EncoderPreference::Software, &caps(true)).unwrap();
+        let args = video_args(VideoCodec::H264, &x264, RateControl::Cbr { bitrate_kbps: 8000 });
+        assert!(args.windows(2).any(|w| w == ["-b:v", "8000k"]));
+        assert!(args.windows(2).any(|w| w == ["-x264-params", "nal-hrd=cbr"]));
+
+        let rate: RateControl =
+            serde_json::from_str(r#"{"mode":"vbr","bitrate_kbps":5000,"max_kbps":8000}"#).unwrap();
+        assert_eq!(
+            rate,
+            RateControl::Vbr {
+                bitrate_kbps: 5000,
+                max_kbps: 8000
+            }
+        );
+    }
+
+    #[test]
+    fn test_mezzanine_args() {
+        let args = mezzanine_args(MezzanineProfile::Prores4444, Container::Mov).unwrap();
+        assert!(args.windows(2).any(|w| w == ["-profile:v", "4"]));
+        assert!(args.windows(2).any(|w| w == ["-pix_fmt", "yuva444p10le"]));
+        assert!(args.windows(2).any(|w| w == ["-colorspace", "bt709"]));
+
+        let args = mezzanine_args(MezzanineProfile::DnxhrHq, Container::Mxf).unwrap();
+        assert!(args.windows(2).any(|w| w == ["-profile:v", "dnxhr_hq"]));
+        assert!(args.ends_with(&["-f".to_string(), "mxf".to_string()]));
+
+        assert!(mezzanine_args(MezzanineProfile::Prores422, Container::Mxf).is_err());
+    }
+}
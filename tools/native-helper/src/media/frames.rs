@@ -0,0 +1,227 @@
+//! Single-frame grabs and contact sheets
+
+use std::path::Path;
+
+use super::{ffmpeg_command, probe_duration, stderr_tail};
+use crate::metrics;
+
+/// Largest grid `render_contact_sheet` accepts per side
+pub const MAX_SHEET_SIDE: u32 = 8;
+
+/// Widest frame grab, as for `transcode`
+const MAX_FRAME_WIDTH: u32 = 8192;
+
+/// Widest frame sent to a session on another machine unless it asks for a
+/// width; full-resolution stills are too slow over Wi-Fi.
+pub const REMOTE_FRAME_WIDTH: u32 = 1280;
+
+/// Encoded image format for frame grabs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageFormat {
+    /// Parse the `format` argument; JPEG when omitted.
+    pub fn parse(format: Option<&str>) -> Result<Self, String> {
+        match format.map(|f| f.to_ascii_lowercase()).as_deref() {
+            None | Some("jpeg") | Some("jpg") => Ok(ImageFormat::Jpeg),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("webp") => Ok(ImageFormat::Webp),
+            Some(other) => Err(format!("Unsupported image format: {}", other)),
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+
+    fn codec_args(&self) -> &'static [&'static str] {
+        match self {
+            ImageFormat::Jpeg => &["-c:v", "mjpeg", "-q:v", "3"],
+            ImageFormat::Png => &["-c:v", "png"],
+            ImageFormat::Webp => &["-c:v", "libwebp", "-quality", "80"],
+        }
+    }
+}
+
+/// A grid of evenly spaced frames
+pub struct ContactSheet {
+    pub data: Vec<u8>,
+    pub duration: f64,
+    /// Source time (seconds) of each tile, row by row
+    pub times: Vec<f64>,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+/// Even height for a 16:9 tile of the given width.
+fn tile_height(width: u32) -> u32 {
+    ((width * 9 / 16) / 2 * 2).max(2)
+}
+
+/// Sample times at the middle of `count` equal slices of `duration`.
+fn sample_times(duration: f64, count: u32) -> Vec<f64> {
+    let interval = duration / count as f64;
+    (0..count).map(|i| (i as f64 + 0.5) * interval).collect()
+}
+
+fn contact_sheet_filter(count: u32, cols: u32, rows: u32, width: u32, height: u32) -> String {
+    let mut filter = String::new();
+    for i in 0..count {
+        filter.push_str(&format!(
+            "[{i}:v:0]trim=end_frame=1,setpts=PTS-STARTPTS,\
+             scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1[v{i}];"
+        ));
+    }
+    for i in 0..count {
+        filter.push_str(&format!("[v{i}]"));
+    }
+    filter.push_str(&format!(
+        "concat=n={count}:v=1:a=0,tile={cols}x{rows}[out]"
+    ));
+    filter
+}
+
+/// `-vf` scaling for a frame grab: exactly `width` when given, otherwise
+/// down to `max_width` for sources wider than that. Both are kept within
+/// 16..=`MAX_FRAME_WIDTH`.
+fn frame_scale(width: Option<u32>, max_width: Option<u32>) -> Option<String> {
+    match (width, max_width) {
+        (Some(width), _) => Some(format!("scale={}:-2", width.clamp(16, MAX_FRAME_WIDTH))),
+        (None, Some(max_width)) => Some(format!(
+            "scale='min(iw,{})':-2",
+            max_width.clamp(16, MAX_FRAME_WIDTH)
+        )),
+        (None, None) => None,
+    }
+}
+
+/// Decode the frame at `time` seconds and encode it as an image.
+pub async fn extract_frame(
+    ffmpeg: &Path,
+    path: &Path,
+    time: f64,
+    format: ImageFormat,
+    width: Option<u32>,
+    max_width: Option<u32>,
+) -> Result<Vec<u8>, String> {
+    let mut cmd = ffmpeg_command(ffmpeg);
+    cmd.args(["-ss", &format!("{:.3}", time.max(0.0)), "-i"])
+        .arg(path)
+        .args(["-frames:v", "1", "-an"]);
+    if let Some(scale) = frame_scale(width, max_width) {
+        cmd.args(["-vf", &scale]);
+    }
+    cmd.args(format.codec_args())
+        .args(["-f", "image2pipe", "-"]);
+
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffmpeg failed: {}", stderr_tail(&output)));
+    }
+    if output.stdout.is_empty() {
+        return Err(format!("No frame at {:.3}s", time));
+    }
+    metrics::add_decoded_frames(1);
+    Ok(output.stdout)
+}
+
+/// Render a `cols` x `rows` grid of frames spread evenly across the file.
+pub async fn render_contact_sheet(
+    ffmpeg: &Path,
+    ffprobe: &Path,
+    path: &Path,
+    cols: u32,
+    rows: u32,
+    tile_width: u32,
+    format: ImageFormat,
+) -> Result<ContactSheet, String> {
+    let cols = cols.clamp(1, MAX_SHEET_SIDE);
+    let rows = rows.clamp(1, MAX_SHEET_SIDE);
+    let tile_width = (tile_width.clamp(16, 1920) / 2) * 2;
+    let tile_height = tile_height(tile_width);
+    let count = cols * rows;
+
+    let duration = probe_duration(ffprobe, path).await?;
+    let times = sample_times(duration, count);
+
+    // One fast-seeked input per tile keeps long files cheap: only the frames
+    // around each sample point are decoded.
+    let mut cmd = ffmpeg_command(ffmpeg);
+    for time in &times {
+        cmd.args(["-ss", &format!("{:.3}", time)]).arg("-i").arg(path);
+    }
+    cmd.args([
+        "-filter_complex",
+        &contact_sheet_filter(count, cols, rows, tile_width, tile_height),
+        "-map",
+        "[out]",
+        "-frames:v",
+        "1",
+    ])
+    .args(format.codec_args())
+    .args(["-f", "image2pipe", "-"]);
+
+    let output = cmd
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(format!("ffmpeg failed: {}", stderr_tail(&output)));
+    }
+    metrics::add_decoded_frames(count as u64);
+
+    Ok(ContactSheet {
+        data: output.stdout,
+        duration,
+        times,
+        tile_width,
+        tile_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_format_parse() {
+        assert_eq!(ImageFormat::parse(None), Ok(ImageFormat::Jpeg));
+        assert_eq!(ImageFormat::parse(Some("PNG")), Ok(ImageFormat::Png));
+        assert!(ImageFormat::parse(Some("tiff")).is_err());
+    }
+
+    #[test]
+    fn test_sample_times_and_filter() {
+        assert_eq!(sample_times(8.0, 4), vec![1.0, 3.0, 5.0, 7.0]);
+        assert_eq!(tile_height(240), 134);
+
+        let filter = contact_sheet_filter(2, 2, 1, 240, 134);
+        assert!(filter.starts_with("[0:v:0]trim=end_frame=1"));
+        assert!(filter.contains("[1:v:0]"));
+        assert!(filter.ends_with("[v0][v1]concat=n=2:v=1:a=0,tile=2x1[out]"));
+    }
+
+    #[test]
+    fn test_frame_scale() {
+        assert_eq!(frame_scale(None, None), None);
+        assert_eq!(frame_scale(Some(640), Some(1280)).as_deref(), Some("scale=640:-2"));
+        assert_eq!(frame_scale(Some(200_000), None).as_deref(), Some("scale=8192:-2"));
+        assert_eq!(frame_scale(Some(0), None).as_deref(), Some("scale=16:-2"));
+        assert_eq!(
+            frame_scale(None, Some(REMOTE_FRAME_WIDTH)).as_deref(),
+            Some("scale='min(iw,1280)':-2")
+        );
+    }
+}
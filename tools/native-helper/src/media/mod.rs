@@ -0,0 +1,500 @@
+//! Media processing through ffmpeg/ffprobe
+//!
+//! The helper never links a decoder; everything here shells out to the
+//! ffmpeg/ffprobe binaries shipped next to the helper or found on PATH.
+
+mod audio;
+mod decoders;
+mod ducking;
+pub mod encoder;
+mod frames;
+mod sync;
+mod transcode;
+mod vfr;
+
+pub use audio::{handle_denoise_audio, handle_extract_audio, AudioFormat, DenoiseOptions};
+pub use ducking::{handle_auto_duck, DuckOptions};
+pub use encoder::{EncoderPreference, RateControl, VideoCodec};
+pub use frames::{extract_frame, render_contact_sheet, ImageFormat, REMOTE_FRAME_WIDTH};
+pub use sync::handle_sync_audio;
+pub use transcode::{handle_transcode, TranscodeCodec, TranscodeOptions};
+pub use vfr::{handle_conform_cfr, handle_probe_frame_timing};
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::process::Command as TokioCommand;
+use tokio_util::sync::CancellationToken;
+
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, JobProgress, Response};
+use crate::utils;
+
+/// Find a tool next to the helper executable, then on PATH.
+fn find_tool(name: &str) -> Option<PathBuf> {
+    if let Ok(exe_path) = std::env::current_exe() {
+        if let Some(exe_dir) = exe_path.parent() {
+            let bundled = exe_dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+            if utils::executable_works(&bundled) {
+                return Some(bundled);
+            }
+        }
+    }
+
+    if utils::command_works(name) {
+        return Some(PathBuf::from(name));
+    }
+
+    None
+}
+
+/// Find the ffmpeg executable.
+pub fn find_ffmpeg() -> Option<PathBuf> {
+    find_tool("ffmpeg")
+}
+
+/// Find the ffprobe executable.
+pub fn find_ffprobe() -> Option<PathBuf> {
+    find_tool("ffprobe")
+}
+
+/// Build an ffmpeg command with the flags every helper invocation uses.
+pub fn ffmpeg_command(ffmpeg: &Path) -> TokioCommand {
+    let mut cmd = TokioCommand::new(ffmpeg);
+    utils::no_window(&mut cmd);
+    cmd.args(["-hide_banner", "-nostdin", "-loglevel", "error"]);
+    cmd
+}
+
+/// Container duration in seconds.
+pub async fn probe_duration(ffprobe: &Path, path: &Path) -> Result<f64, String> {
+    let mut cmd = TokioCommand::new(ffprobe);
+    utils::no_window(&mut cmd);
+    let output = cmd
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|d| d.is_finite() && *d > 0.0)
+        .ok_or_else(|| "Media has no duration".to_string())
+}
+
+/// Run a command to completion, killing it if `cancel` fires.
+/// Returns `None` when cancelled.
+pub async fn run_cancellable(
+    cmd: &mut TokioCommand,
+    cancel: &CancellationToken,
+) -> Option<std::io::Result<std::process::Output>> {
+    let child = match cmd
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Some(Err(e)),
+    };
+    tokio::select! {
+        output = child.wait_with_output() => Some(output),
+        _ = cancel.cancelled() => None,
+    }
+}
+
+/// Last non-empty stderr lines of a failed tool run, for error messages.
+pub fn stderr_tail(output: &std::process::Output) -> String {
+    last_lines(&String::from_utf8_lossy(&output.stderr))
+}
+
+fn last_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(5)..].join("\n")
+}
+
+/// Output time from an ffmpeg `-progress` line, in seconds.
+/// (`out_time_ms` is in microseconds too, despite its name.)
+fn parse_progress_time(line: &str) -> Option<f64> {
+    let value = line
+        .strip_prefix("out_time_us=")
+        .or_else(|| line.strip_prefix("out_time_ms="))?;
+    let micros: i64 = value.trim().parse().ok()?;
+    (micros >= 0).then(|| micros as f64 / 1_000_000.0)
+}
+
+/// Run `cmd` (from [`ffmpeg_command`]) with `args` to completion, reporting
+/// how much of `duration` it has written as `stage` progress. Returns `None`
+/// when cancelled.
+pub async fn run_with_progress<I, S>(
+    mut cmd: TokioCommand,
+    args: I,
+    duration: f64,
+    stage: &str,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Option<Result<(), String>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<std::ffi::OsStr>,
+{
+    // Global options must come before the first input
+    let mut child = match cmd
+        .args(["-progress", "pipe:1", "-nostats"])
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Some(Err(format!("Failed to run ffmpeg: {}", e))),
+    };
+
+    // Drain stderr alongside stdout so neither pipe fills up
+    let stderr = child.stderr.take();
+    let stderr_task = tokio::spawn(async move {
+        let mut text = String::new();
+        if let Some(mut stderr) = stderr {
+            let _ = stderr.read_to_string(&mut text).await;
+        }
+        text
+    });
+
+    if let Some(stdout) = child.stdout.take() {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut reported = 0.0f32;
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => line,
+                    _ => break,
+                },
+                _ = cancel.cancelled() => return None,
+            };
+            let Some(seconds) = parse_progress_time(&line) else {
+                continue;
+            };
+            let percent = (seconds / duration.max(0.001) * 100.0).clamp(0.0, 100.0) as f32;
+            if percent - reported >= 1.0 {
+                reported = percent;
+                reporter.progress(&JobProgress::stage(stage, percent)).await;
+            }
+        }
+    }
+
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = cancel.cancelled() => return None,
+    };
+    let stderr = stderr_task.await.unwrap_or_default();
+    match status {
+        Ok(status) if status.success() => Some(Ok(())),
+        Ok(status) => Some(Err(format!(
+            "ffmpeg exited with code {}: {}",
+            status.code().unwrap_or(-1),
+            last_lines(&stderr)
+        ))),
+        Err(e) => Some(Err(format!("ffmpeg failed: {}", e))),
+    }
+}
+
+/// `out.mp4` -> `out.mp4.part`, where [`render_to`] writes until ffmpeg is done
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    output.with_file_name(name)
+}
+
+/// Reject an `output` the job would fill with a different container, since
+/// [`render_to`] names the muxer instead of letting ffmpeg pick it by name.
+fn check_output_extension(output: &Path, extension: &str) -> Result<(), String> {
+    let matches = output
+        .extension()
+        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(extension));
+    if matches {
+        Ok(())
+    } else {
+        Err(format!("Output {} must be a .{} file", output.display(), extension))
+    }
+}
+
+/// `clip.webm` -> `clip_h264.mp4` in the same directory, without
+/// overwriting an existing file or one another job is still writing.
+fn default_output(path: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "clip".to_string());
+    let dir = path.parent().unwrap_or(Path::new("."));
+
+    let mut candidate = dir.join(format!("{}_{}.{}", stem, suffix, extension));
+    let mut n = 2;
+    while candidate.exists() || partial_path(&candidate).exists() {
+        candidate = dir.join(format!("{}_{}_{}.{}", stem, suffix, n, extension));
+        n += 1;
+    }
+    candidate
+}
+
+/// Run `cmd` with `args` into `output` like [`run_with_progress`]. ffmpeg
+/// writes `output` + `.part`, which is renamed on success and removed when
+/// the job fails or is cancelled, so `output` never holds half a file. The
+/// `.part` file is created up front, so a second job writing the same output
+/// fails instead of sharing it. `args` must name the muxer with `-f`.
+/// Returns `None` when cancelled.
+async fn render_to(
+    cmd: TokioCommand,
+    mut args: Vec<OsString>,
+    output: &Path,
+    duration: f64,
+    stage: &str,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Option<Result<(), String>> {
+    let partial = partial_path(output);
+    let reserved = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&partial)
+        .await;
+    if let Err(e) = reserved {
+        return Some(Err(if e.kind() == std::io::ErrorKind::AlreadyExists {
+            format!("{} is already being written by another job", output.display())
+        } else {
+            format!("Cannot create {}: {}", partial.display(), e)
+        }));
+    }
+    args.extend(["-y".into(), partial.clone().into()]);
+
+    let result = match run_with_progress(cmd, args, duration, stage, reporter, cancel).await {
+        Some(Ok(())) => tokio::fs::rename(&partial, output)
+            .await
+            .map_err(|e| format!("Cannot move output to {}: {}", output.display(), e)),
+        Some(Err(e)) => Err(e),
+        None => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return None;
+        }
+    };
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    Some(result)
+}
+
+/// `extract_frame`: return one frame as a base64 image. Without `width` it
+/// keeps the source size, shrunk to `max_width` when that is set.
+pub async fn handle_extract_frame(
+    id: &str,
+    path: &Path,
+    time: f64,
+    format: Option<&str>,
+    width: Option<u32>,
+    max_width: Option<u32>,
+) -> Response {
+    let format = match ImageFormat::parse(format) {
+        Ok(format) => format,
+        Err(e) => return Response::error(id, error_codes::INVALID_ARGUMENT, e),
+    };
+    let Some(ffmpeg) = find_ffmpeg() else {
+        return ffmpeg_missing(id);
+    };
+
+    match extract_frame(&ffmpeg, path, time, format, width, max_width).await {
+        Ok(data) => Response::ok(
+            id,
+            serde_json::json!({
+                "time": time,
+                "mime": format.mime(),
+                "size": data.len(),
+                "data": BASE64.encode(&data),
+            }),
+        ),
+        Err(e) => Response::error(id, error_codes::MEDIA_FAILED, e),
+    }
+}
+
+/// `render_contact_sheet`: return a grid of frames as a base64 image.
+pub async fn handle_render_contact_sheet(
+    id: &str,
+    path: &Path,
+    cols: u32,
+    rows: u32,
+    tile_width: u32,
+    format: Option<&str>,
+) -> Response {
+    let format = match ImageFormat::parse(format) {
+        Ok(format) => format,
+        Err(e) => return Response::error(id, error_codes::INVALID_ARGUMENT, e),
+    };
+    let (Some(ffmpeg), Some(ffprobe)) = (find_ffmpeg(), find_ffprobe()) else {
+        return ffmpeg_missing(id);
+    };
+
+    match render_contact_sheet(&ffmpeg, &ffprobe, path, cols, rows, tile_width, format).await {
+        Ok(sheet) => Response::ok(
+            id,
+            serde_json::json!({
+                "mime": format.mime(),
+                "duration": sheet.duration,
+                "cols": cols.clamp(1, frames::MAX_SHEET_SIDE),
+                "rows": rows.clamp(1, frames::MAX_SHEET_SIDE),
+                "tile_width": sheet.tile_width,
+                "tile_height": sheet.tile_height,
+                "times": sheet.times,
+                "size": sheet.data.len(),
+                "data": BASE64.encode(&sheet.data),
+            }),
+        ),
+        Err(e) => Response::error(id, error_codes::MEDIA_FAILED, e),
+    }
+}
+
+/// `list_encoders`: which video encoders this machine can use, and the
+/// arguments each codec would be encoded with.
+pub async fn handle_list_encoders(
+    id: &str,
+    preference: Option<EncoderPreference>,
+    rate_control: RateControl,
+) -> Response {
+    let Some(ffmpeg) = find_ffmpeg() else {
+        return ffmpeg_missing(id);
+    };
+    let preference =
+        preference.unwrap_or_else(|| crate::config::current().media.encoder_preference);
+    let caps = encoder::capabilities(&ffmpeg).await;
+    let gpu = crate::gpu::selected_index().await;
+    let resolve = |codec| match encoder::select(codec, preference, caps) {
+        Ok(mut selected) => {
+            if selected.backend == encoder::Backend::Nvenc {
+                selected.gpu = gpu;
+            }
+            serde_json::json!({
+                "encoder": selected.encoder,
+                "backend": selected.backend,
+                "args": encoder::video_args(codec, &selected, rate_control),
+            })
+        }
+        Err(e) => serde_json::json!({ "error": e }),
+    };
+
+    let mezzanine: Vec<_> = encoder::MezzanineProfile::ALL
+        .iter()
+        .map(|&profile| {
+            serde_json::json!({
+                "profile": profile,
+                "available": caps.has_mezzanine(profile),
+                "containers": profile.containers(),
+                "args": encoder::mezzanine_args(profile, encoder::Container::Mov).ok(),
+            })
+        })
+        .collect();
+
+    Response::ok(
+        id,
+        serde_json::json!({
+            "capabilities": caps,
+            "mezzanine": mezzanine,
+            "codecs": {
+                "h264": resolve(VideoCodec::H264),
+                "hevc": resolve(VideoCodec::Hevc),
+            },
+        }),
+    )
+}
+
+/// `capabilities`: hardware decode APIs, decodable codecs, encoders, GPUs
+/// and free disk space, so the editor can offer only what will work.
+/// Reports what it can without ffmpeg rather than failing.
+pub async fn handle_capabilities(id: &str) -> Response {
+    let ffmpeg = find_ffmpeg();
+    let (decoders, encoders) = match &ffmpeg {
+        Some(ffmpeg) => (
+            Some(decoders::capabilities(ffmpeg).await),
+            Some(encoder::capabilities(ffmpeg).await),
+        ),
+        None => (None, None),
+    };
+    let gpus = tokio::task::spawn_blocking(crate::gpu::list)
+        .await
+        .unwrap_or_default();
+
+    let settings = crate::config::current();
+
+    // NVDEC codecs and limits of the GPU work runs on
+    let active_gpu = crate::gpu::resolve(&gpus, settings.media.gpu.as_deref()).or(gpus.first());
+    let nvdec: Vec<_> = active_gpu
+        .map(|gpu| {
+            gpu.nvdec_codecs
+                .iter()
+                .map(|&codec| {
+                    serde_json::json!({
+                        "codec": codec,
+                        "max_width": crate::gpu::nvdec_max_size(codec),
+                        "max_height": crate::gpu::nvdec_max_size(codec),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let dirs = [
+        ("downloads", utils::get_download_dir()),
+        ("projects", utils::get_project_root()),
+    ]
+    .into_iter()
+    .chain(settings.paths.scratch.into_iter().map(|dir| ("scratch", dir)));
+    let disks: Vec<_> = dirs
+        .map(|(role, path)| {
+            let space = utils::disk_space(&path);
+            serde_json::json!({
+                "role": role,
+                "path": path,
+                "available_bytes": space.map(|(available, _)| available),
+                "total_bytes": space.map(|(_, total)| total),
+            })
+        })
+        .collect();
+
+    Response::ok(
+        id,
+        serde_json::json!({
+            "ffmpeg": ffmpeg.is_some(),
+            "ffmpeg_version": encoders.and_then(|caps| caps.ffmpeg_version.clone()),
+            "decoders": decoders,
+            "nvdec": nvdec,
+            "encoders": encoders,
+            "gpus": gpus,
+            "disks": disks,
+        }),
+    )
+}
+
+fn ffmpeg_missing(id: &str) -> Response {
+    Response::error(
+        id,
+        error_codes::FFMPEG_NOT_FOUND,
+        "ffmpeg/ffprobe not found. Install ffmpeg on PATH or ship it next to the Native Helper.",
+    )
+}
@@ -0,0 +1,273 @@
+//! Clip synchronization by audio
+//!
+//! Each clip's audio is decoded to mono 4 kHz and aligned against the
+//! reference with GCC-PHAT (phase-transform weighted cross-correlation),
+//! which keeps a sharp peak even when one recording is a noisy camera mic
+//! and the other a clean external recorder.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::RealFftPlanner;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use super::{ffmpeg_command, find_ffmpeg, run_cancellable, stderr_tail};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+
+pub(super) const SAMPLE_RATE: u32 = 4000;
+/// Seconds of each clip used for matching
+const ANALYSIS_SECONDS: f64 = 300.0;
+/// Peaks closer than this to the best one don't count as competitors
+const PEAK_EXCLUSION_SECONDS: f64 = 0.05;
+
+/// Offset of one clip relative to the reference
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipOffset {
+    pub path: PathBuf,
+    /// Seconds after the reference start at which the clip starts (negative: before)
+    pub offset: f64,
+    /// 0..1; how much the best match stands out from the next best
+    pub confidence: f32,
+}
+
+/// Decode mono audio at `SAMPLE_RATE`, all of it or the first `seconds`.
+/// `None` when cancelled.
+pub(super) async fn decode_audio(
+    ffmpeg: &Path,
+    path: &Path,
+    seconds: Option<f64>,
+    cancel: &CancellationToken,
+) -> Option<Result<Vec<f32>, String>> {
+    let mut cmd = ffmpeg_command(ffmpeg);
+    cmd.arg("-i").arg(path);
+    if let Some(seconds) = seconds {
+        cmd.args(["-t", &format!("{:.3}", seconds)]);
+    }
+    cmd.args(["-vn", "-ac", "1", "-ar", &SAMPLE_RATE.to_string()])
+        .args(["-f", "f32le", "-"]);
+
+    let output = match run_cancellable(&mut cmd, cancel).await? {
+        Ok(output) => output,
+        Err(e) => return Some(Err(format!("Failed to run ffmpeg: {}", e))),
+    };
+    if !output.status.success() {
+        return Some(Err(format!(
+            "Cannot decode audio of {}: {}",
+            path.display(),
+            stderr_tail(&output)
+        )));
+    }
+
+    let samples: Vec<f32> = output
+        .stdout
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    if samples.len() < SAMPLE_RATE as usize {
+        return Some(Err(format!("{} has less than a second of audio", path.display())));
+    }
+    Some(Ok(samples))
+}
+
+/// Lag (in samples, fractional) at which `clip` best matches `reference`,
+/// searched within `±max_lag`, and the match confidence.
+///
+/// A positive lag means the clip's content appears that many samples into
+/// the reference.
+pub fn find_offset(reference: &[f32], clip: &[f32], max_lag: usize) -> Option<(f64, f32)> {
+    if reference.is_empty() || clip.is_empty() {
+        return None;
+    }
+    let n = (reference.len() + clip.len()).next_power_of_two();
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(n);
+    let inverse = planner.plan_fft_inverse(n);
+
+    let spectrum = |signal: &[f32]| -> Option<Vec<Complex<f32>>> {
+        let mut input = forward.make_input_vec();
+        input[..signal.len()].copy_from_slice(signal);
+        let mut output = forward.make_output_vec();
+        forward.process(&mut input, &mut output).ok()?;
+        Some(output)
+    };
+    let a = spectrum(reference)?;
+    let b = spectrum(clip)?;
+
+    // Phase transform: keep only the phase of the cross spectrum
+    let mut cross: Vec<Complex<f32>> = a
+        .iter()
+        .zip(&b)
+        .map(|(x, y)| {
+            let c = x * y.conj();
+            let mag = c.norm();
+            if mag > 1e-12 {
+                c / mag
+            } else {
+                Complex::new(0.0, 0.0)
+            }
+        })
+        .collect();
+    // The DC and Nyquist bins of a real signal's spectrum must be real
+    cross[0].im = 0.0;
+    if let Some(last) = cross.last_mut() {
+        last.im = 0.0;
+    }
+
+    let mut correlation = inverse.make_output_vec();
+    inverse.process(&mut cross, &mut correlation).ok()?;
+
+    // Circular lags: index k is lag k, index n - k is lag -k
+    let max_lag = max_lag.min(n / 2 - 1) as isize;
+    let at = |lag: isize| correlation[lag.rem_euclid(n as isize) as usize];
+
+    let mut best = (0isize, f32::MIN);
+    for lag in -max_lag..=max_lag {
+        let value = at(lag);
+        if value > best.1 {
+            best = (lag, value);
+        }
+    }
+
+    let exclusion = (PEAK_EXCLUSION_SECONDS * SAMPLE_RATE as f64) as isize;
+    let runner_up = (-max_lag..=max_lag)
+        .filter(|lag| (lag - best.0).abs() > exclusion)
+        .map(at)
+        .fold(0.0f32, f32::max);
+    let confidence = if best.1 > 0.0 {
+        (1.0 - runner_up / best.1).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    // Parabolic interpolation around the peak for sub-sample precision
+    let (l, c, r) = (at(best.0 - 1), best.1, at(best.0 + 1));
+    let denom = l - 2.0 * c + r;
+    let shift = if denom.abs() > f32::EPSILON {
+        (0.5 * (l - r) / denom).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+
+    Some((best.0 as f64 + shift as f64, confidence))
+}
+
+/// `sync_audio` job: offsets of `clips` relative to `reference`.
+pub async fn handle_sync_audio(
+    reference: &Path,
+    clips: &[PathBuf],
+    max_offset: f64,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+    let cancelled = || fail(error_codes::CANCELLED, "Audio sync cancelled".to_string());
+
+    let Some(ffmpeg) = find_ffmpeg() else {
+        return fail(
+            error_codes::FFMPEG_NOT_FOUND,
+            "Audio sync requires ffmpeg on PATH or next to the helper.".to_string(),
+        );
+    };
+
+    reporter
+        .progress(&JobProgress::stage(job_stages::EXTRACTING_AUDIO, 0.0))
+        .await;
+    let reference_audio =
+        match decode_audio(&ffmpeg, reference, Some(ANALYSIS_SECONDS + max_offset), cancel).await {
+            Some(Ok(samples)) => Arc::new(samples),
+            Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+            None => return cancelled(),
+        };
+
+    let max_lag = (max_offset * SAMPLE_RATE as f64) as usize;
+    let mut offsets = Vec::with_capacity(clips.len());
+    for (index, clip) in clips.iter().enumerate() {
+        let percent = index as f32 / clips.len() as f32 * 100.0;
+        reporter
+            .progress(&JobProgress::stage(job_stages::ANALYZING, percent))
+            .await;
+
+        let clip_audio = match decode_audio(&ffmpeg, clip, Some(ANALYSIS_SECONDS), cancel).await {
+            Some(Ok(samples)) => samples,
+            Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+            None => return cancelled(),
+        };
+        let reference_audio = reference_audio.clone();
+        let result = tokio::select! {
+            result = tokio::task::spawn_blocking(move || find_offset(&reference_audio, &clip_audio, max_lag)) => result,
+            _ = cancel.cancelled() => return cancelled(),
+        };
+
+        match result {
+            Ok(Some((lag, confidence))) => offsets.push(ClipOffset {
+                path: clip.clone(),
+                offset: lag / SAMPLE_RATE as f64,
+                confidence,
+            }),
+            _ => {
+                return fail(
+                    error_codes::MEDIA_FAILED,
+                    format!("Cannot correlate {}", clip.display()),
+                )
+            }
+        }
+    }
+
+    Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "reference": reference,
+            "offsets": offsets,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise so the tests don't need a rand seed
+    fn noise(len: usize, mut state: u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 8) as f32 / (1u32 << 24) as f32 - 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_offset_positive_and_negative() {
+        let reference = noise(20_000, 7);
+
+        // Clip starts 3000 samples into the reference
+        let clip: Vec<f32> = reference[3000..15_000].to_vec();
+        let (lag, confidence) = find_offset(&reference, &clip, 8000).unwrap();
+        assert!((lag - 3000.0).abs() < 0.5, "lag {}", lag);
+        assert!(confidence > 0.5);
+
+        // Clip starts 1200 samples before the reference, with its own noise floor
+        let mut clip = noise(1200, 99);
+        clip.extend_from_slice(&reference[..10_000]);
+        for (sample, n) in clip.iter_mut().zip(noise(11_200, 5)) {
+            *sample += n * 0.3;
+        }
+        let (lag, _) = find_offset(&reference, &clip, 8000).unwrap();
+        assert!((lag + 1200.0).abs() < 0.5, "lag {}", lag);
+    }
+
+    #[test]
+    fn test_find_offset_respects_max_lag() {
+        let reference = noise(20_000, 3);
+        let clip: Vec<f32> = reference[9000..14_000].to_vec();
+        let (lag, confidence) = find_offset(&reference, &clip, 4000).unwrap();
+        assert!(lag.abs() <= 4000.5);
+        assert!(confidence < 0.5);
+    }
+}
@@ -0,0 +1,291 @@
+//! Transcoding into editing-friendly formats
+//!
+//! VP9 and AV1 downloads often decode slowly or not at all in the browser.
+//! `transcode` re-encodes a clip to H.264/HEVC (NVENC when available, see
+//! `encoder`) or to a ProRes/DNxHR mezzanine, optionally scaled down.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::encoder::{self, Backend, Container, MezzanineProfile, RateControl, VideoCodec};
+use super::{
+    check_output_extension, default_output, ffmpeg_command, ffmpeg_missing, find_ffmpeg,
+    find_ffprobe, probe_duration, render_to,
+};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, Response};
+
+/// Target codec of a transcode: a delivery codec or a mezzanine profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TranscodeCodec {
+    Video(VideoCodec),
+    Mezzanine(MezzanineProfile),
+}
+
+impl TryFrom<String> for TranscodeCodec {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, String> {
+        let value = serde_json::Value::String(name.clone());
+        serde_json::from_value(value.clone())
+            .map(TranscodeCodec::Video)
+            .or_else(|_| serde_json::from_value(value).map(TranscodeCodec::Mezzanine))
+            .map_err(|_| {
+                format!(
+                    "Unknown codec '{}'; expected h264, hevc, prores422, prores422_hq, prores4444, dnxhr_lb, dnxhr_sq or dnxhr_hq",
+                    name
+                )
+            })
+    }
+}
+
+impl Default for TranscodeCodec {
+    fn default() -> Self {
+        TranscodeCodec::Video(VideoCodec::H264)
+    }
+}
+
+impl TranscodeCodec {
+    fn extension(&self) -> &'static str {
+        match self {
+            TranscodeCodec::Video(_) => "mp4",
+            TranscodeCodec::Mezzanine(_) => Container::Mov.extension(),
+        }
+    }
+
+    fn suffix(&self) -> String {
+        let name = match self {
+            TranscodeCodec::Video(codec) => serde_json::to_value(codec),
+            TranscodeCodec::Mezzanine(profile) => serde_json::to_value(profile),
+        };
+        name.ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_else(|| "transcoded".to_string())
+    }
+}
+
+/// Options of a `transcode` command
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOptions {
+    pub codec: TranscodeCodec,
+    /// Constant quality; lower is better
+    pub crf: Option<u8>,
+    /// Average bitrate in kbit/s (peaks up to 1.5x)
+    pub bitrate_kbps: Option<u32>,
+    /// `"1280x720"` (fit inside) or `"720p"` / `"720"` (height)
+    pub resolution: Option<String>,
+}
+
+impl TranscodeOptions {
+    fn rate_control(&self) -> Result<RateControl, String> {
+        match (self.crf, self.bitrate_kbps) {
+            (Some(_), Some(_)) => Err("Pass either crf or bitrate_kbps, not both".to_string()),
+            (Some(value), None) if value <= 51 => Ok(RateControl::Crf { value }),
+            (Some(value), None) => Err(format!("CRF {} is out of range (0-51)", value)),
+            (None, Some(kbps)) if (100..=500_000).contains(&kbps) => Ok(RateControl::Vbr {
+                bitrate_kbps: kbps,
+                max_kbps: kbps + kbps / 2,
+            }),
+            (None, Some(kbps)) => Err(format!("Bitrate {} kbit/s is out of range", kbps)),
+            (None, None) => Ok(RateControl::default()),
+        }
+    }
+}
+
+/// Scale filter for a resolution. Never upscales; sizes stay even for 4:2:0.
+pub fn scale_filter(resolution: &str) -> Result<String, String> {
+    let value = resolution.trim().to_lowercase();
+    let invalid = || format!("Invalid resolution '{}'; use e.g. 1920x1080 or 720p", resolution);
+    let parse = |s: &str| s.parse::<u32>().ok().filter(|n| (16..=8192).contains(n));
+
+    if let Some((w, h)) = value.split_once('x') {
+        let (w, h) = (parse(w).ok_or_else(invalid)?, parse(h).ok_or_else(invalid)?);
+        return Ok(format!(
+            "scale='min({w},iw)':'min({h},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2"
+        ));
+    }
+    let h = parse(value.trim_end_matches('p')).ok_or_else(invalid)?;
+    Ok(format!("scale=-2:'min({h},ih)'"))
+}
+
+/// `transcode` job: re-encode `path` into `output` (default: next to the source).
+pub async fn handle_transcode(
+    path: &Path,
+    output: Option<PathBuf>,
+    options: TranscodeOptions,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+
+    let rate = match options.rate_control() {
+        Ok(rate) => rate,
+        Err(e) => return fail(error_codes::INVALID_ARGUMENT, e),
+    };
+    let scale = match options.resolution.as_deref().map(scale_filter).transpose() {
+        Ok(scale) => scale,
+        Err(e) => return fail(error_codes::INVALID_ARGUMENT, e),
+    };
+    if let Some(output) = &output {
+        if let Err(e) = check_output_extension(output, options.codec.extension()) {
+            return fail(error_codes::INVALID_ARGUMENT, e);
+        }
+    }
+    let (Some(ffmpeg), Some(ffprobe)) = (find_ffmpeg(), find_ffprobe()) else {
+        return ffmpeg_missing(id).with_job_id(job_id);
+    };
+    let duration = match probe_duration(&ffprobe, path).await {
+        Ok(duration) => duration,
+        Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+    };
+
+    let caps = encoder::capabilities(&ffmpeg).await;
+    let (codec_args, encoder_name, gpu) = match options.codec {
+        TranscodeCodec::Video(codec) => {
+            let preference = crate::config::current().media.encoder_preference;
+            let mut selected = match encoder::select(codec, preference, caps) {
+                Ok(selected) => selected,
+                Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+            };
+            if selected.backend == Backend::Nvenc {
+                selected.gpu = crate::gpu::selected_index().await;
+            }
+            let mut args = encoder::video_args(codec, &selected, rate);
+            args.extend(
+                ["-c:a", "aac", "-b:a", "320k", "-movflags", "+faststart", "-f", "mp4"]
+                    .map(String::from),
+            );
+            (args, selected.encoder.to_string(), selected.gpu)
+        }
+        TranscodeCodec::Mezzanine(profile) => {
+            if !caps.has_mezzanine(profile) {
+                return fail(
+                    error_codes::MEDIA_FAILED,
+                    format!("This ffmpeg build cannot encode {}", options.codec.suffix()),
+                );
+            }
+            match encoder::mezzanine_args(profile, Container::Mov) {
+                Ok(args) => {
+                    // `-c:v <encoder>` leads the mezzanine arguments
+                    let name = args.get(1).cloned().unwrap_or_default();
+                    (args, name, None)
+                }
+                Err(e) => return fail(error_codes::INVALID_ARGUMENT, e),
+            }
+        }
+    };
+
+    let output = output
+        .unwrap_or_else(|| default_output(path, &options.codec.suffix(), options.codec.extension()));
+
+    let mut args: Vec<OsString> = vec!["-i".into(), path.into()];
+    args.extend(["-map", "0:v:0", "-map", "0:a?"].map(OsString::from));
+    if let Some(scale) = &scale {
+        args.extend(["-vf".into(), scale.into()]);
+    }
+    args.extend(codec_args.into_iter().map(OsString::from));
+
+    info!(
+        "Transcoding {} to {} with {}",
+        path.display(),
+        output.display(),
+        encoder_name
+    );
+    let mut cmd = ffmpeg_command(&ffmpeg);
+    if gpu.is_some() {
+        crate::gpu::use_pci_bus_order(&mut cmd);
+    }
+    match render_to(cmd, args, &output, duration, job_stages::ENCODING, reporter, cancel).await {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+        None => return fail(error_codes::CANCELLED, "Transcode cancelled".to_string()),
+    }
+
+    Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "path": output,
+            "encoder": encoder_name,
+            "duration": duration,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_filter() {
+        assert_eq!(scale_filter("720p").unwrap(), "scale=-2:'min(720,ih)'");
+        assert_eq!(scale_filter("1080").unwrap(), "scale=-2:'min(1080,ih)'");
+        assert!(scale_filter("1280x720").unwrap().starts_with("scale='min(1280,iw)':'min(720,ih)'"));
+        assert!(scale_filter("huge").is_err());
+        assert!(scale_filter("0x0").is_err());
+    }
+
+    #[test]
+    fn test_options() {
+        let codec: TranscodeCodec = serde_json::from_str(r#""hevc""#).unwrap();
+        assert_eq!(codec, TranscodeCodec::Video(VideoCodec::Hevc));
+        let codec: TranscodeCodec = serde_json::from_str(r#""prores422_hq""#).unwrap();
+        assert_eq!(codec, TranscodeCodec::Mezzanine(MezzanineProfile::Prores422Hq));
+        assert_eq!(codec.suffix(), "prores422_hq");
+        assert_eq!(codec.extension(), "mov");
+        assert!(serde_json::from_str::<TranscodeCodec>(r#""vp9""#).is_err());
+
+        let options = TranscodeOptions {
+            bitrate_kbps: Some(8000),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.rate_control(),
+            Ok(RateControl::Vbr {
+                bitrate_kbps: 8000,
+                max_kbps: 12000
+            })
+        );
+        let both = TranscodeOptions {
+            crf: Some(18),
+            bitrate_kbps: Some(8000),
+            ..Default::default()
+        };
+        assert!(both.rate_control().is_err());
+        assert_eq!(TranscodeOptions::default().rate_control(), Ok(RateControl::default()));
+    }
+
+    #[test]
+    fn test_output_extension() {
+        let h264 = TranscodeCodec::Video(VideoCodec::H264);
+        let prores = TranscodeCodec::Mezzanine(MezzanineProfile::Prores422Hq);
+        assert!(check_output_extension(Path::new("/out/a.mp4"), h264.extension()).is_ok());
+        assert!(check_output_extension(Path::new("/out/A.MP4"), h264.extension()).is_ok());
+        assert!(check_output_extension(Path::new("/out/a.mkv"), h264.extension()).is_err());
+        assert!(check_output_extension(Path::new("/out/a.mp4"), prores.extension()).is_err());
+        assert!(check_output_extension(Path::new("/out/a"), h264.extension()).is_err());
+    }
+
+    #[test]
+    fn test_default_output_skips_names_in_use() {
+        let dir = std::env::temp_dir().join(format!("ms-transcode-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("a.mov");
+
+        assert_eq!(default_output(&source, "h264", "mp4"), dir.join("a_h264.mp4"));
+        // Another job is still rendering a_h264.mp4
+        std::fs::write(dir.join("a_h264.mp4.part"), "").unwrap();
+        assert_eq!(default_output(&source, "h264", "mp4"), dir.join("a_h264_2.mp4"));
+        std::fs::write(dir.join("a_h264_2.mp4"), "").unwrap();
+        assert_eq!(default_output(&source, "h264", "mp4"), dir.join("a_h264_3.mp4"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+//! Variable frame rate detection and conforming
+//!
+//! Phone and screen recordings often have irregular frame timestamps, which
+//! breaks timeline math that assumes `frame = time * fps`. `probe_frame_timing`
+//! reads every video packet's PTS (no decoding, so it is fast even for long
+//! files) and flags clips whose frame intervals wander; `conform_cfr`
+//! re-encodes such a clip to a constant rate next to the original.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tokio::process::Command as TokioCommand;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use super::encoder::{self, Backend, RateControl, VideoCodec};
+use super::{
+    check_output_extension, default_output, ffmpeg_command, ffmpeg_missing, find_ffmpeg,
+    find_ffprobe, render_to,
+};
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+use crate::utils;
+
+/// Intervals further than this from the median count as irregular
+const INTERVAL_TOLERANCE: f64 = 0.1;
+/// Share of irregular intervals above which a clip is treated as VFR
+const VFR_THRESHOLD: f64 = 0.01;
+/// Rates a conform snaps to when the measured average is this close
+const SNAP_TOLERANCE: f64 = 0.01;
+const STANDARD_RATES: &[f64] = &[
+    24000.0 / 1001.0,
+    24.0,
+    25.0,
+    30000.0 / 1001.0,
+    30.0,
+    48.0,
+    50.0,
+    60000.0 / 1001.0,
+    60.0,
+    100.0,
+    120000.0 / 1001.0,
+    120.0,
+];
+/// Quality of conformed clips; they replace the source on the timeline
+const CONFORM_CRF: u8 = 18;
+
+/// Frame timing of a clip's first video stream
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameTiming {
+    pub frames: usize,
+    /// First to last frame, plus one typical frame
+    pub duration: f64,
+    pub average_fps: f64,
+    /// Rate the container declares (`r_frame_rate`)
+    pub nominal_fps: Option<f64>,
+    pub min_interval: f64,
+    pub max_interval: f64,
+    /// Share of frame intervals more than 10% away from the median
+    pub irregular_ratio: f64,
+    pub vfr: bool,
+    /// Constant rate to conform to: the declared rate when it is a standard
+    /// one close to the average, else the nearest standard rate or the average
+    pub suggested_fps: f64,
+    /// Presentation time of every frame in display order (seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pts: Option<Vec<f64>>,
+}
+
+/// Nearest standard rate within `SNAP_TOLERANCE`, else `fps` itself.
+fn snap_fps(fps: f64) -> f64 {
+    STANDARD_RATES
+        .iter()
+        .copied()
+        .min_by(|a, b| (a - fps).abs().total_cmp(&(b - fps).abs()))
+        .filter(|rate| (rate - fps).abs() / rate <= SNAP_TOLERANCE)
+        .unwrap_or(fps)
+}
+
+/// Rate to conform to; see [`FrameTiming::suggested_fps`].
+fn suggest_fps(average_fps: f64, nominal_fps: Option<f64>) -> f64 {
+    nominal_fps
+        .filter(|&nominal| snap_fps(nominal) == nominal)
+        .filter(|&nominal| (nominal - average_fps).abs() / nominal <= INTERVAL_TOLERANCE)
+        .unwrap_or_else(|| snap_fps(average_fps))
+}
+
+/// Timing statistics for frame timestamps in any order. `None` with fewer
+/// than two distinct timestamps.
+fn analyze(mut pts: Vec<f64>, nominal_fps: Option<f64>) -> Option<FrameTiming> {
+    pts.retain(|t| t.is_finite());
+    pts.sort_by(f64::total_cmp);
+    pts.dedup();
+    if pts.len() < 2 {
+        return None;
+    }
+
+    let intervals: Vec<f64> = pts.windows(2).map(|w| w[1] - w[0]).collect();
+    let mut sorted = intervals.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    let irregular = intervals
+        .iter()
+        .filter(|&&i| (i - median).abs() > median * INTERVAL_TOLERANCE)
+        .count();
+    let irregular_ratio = irregular as f64 / intervals.len() as f64;
+
+    let span = pts[pts.len() - 1] - pts[0];
+    let average_fps = intervals.len() as f64 / span;
+
+    Some(FrameTiming {
+        frames: pts.len(),
+        duration: span + median,
+        average_fps,
+        nominal_fps,
+        min_interval: sorted[0],
+        max_interval: sorted[sorted.len() - 1],
+        irregular_ratio,
+        vfr: irregular_ratio > VFR_THRESHOLD,
+        suggested_fps: suggest_fps(average_fps, nominal_fps),
+        pts: Some(pts),
+    })
+}
+
+/// Parse an ffprobe rational such as `30000/1001`.
+fn parse_rate(rate: &str) -> Option<f64> {
+    let (num, den) = rate.trim().split_once('/')?;
+    let (num, den): (f64, f64) = (num.parse().ok()?, den.parse().ok()?);
+    (num > 0.0 && den > 0.0).then(|| num / den)
+}
+
+async fn ffprobe_video(ffprobe: &Path, path: &Path, entries: &str) -> Result<String, String> {
+    let mut cmd = TokioCommand::new(ffprobe);
+    utils::no_window(&mut cmd);
+    let output = cmd
+        .args(["-v", "error", "-select_streams", "v:0", "-show_entries", entries])
+        .args(["-of", "csv=p=0"])
+        .arg(path)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Read the PTS of every video packet of `path`.
+pub async fn probe_frame_timing(ffprobe: &Path, path: &Path) -> Result<FrameTiming, String> {
+    let nominal_fps = ffprobe_video(ffprobe, path, "stream=r_frame_rate")
+        .await?
+        .lines()
+        .find_map(parse_rate);
+    let pts: Vec<f64> = ffprobe_video(ffprobe, path, "packet=pts_time")
+        .await?
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse().ok())
+        .collect();
+    analyze(pts, nominal_fps).ok_or_else(|| format!("{} has no video frames", path.display()))
+}
+
+/// `probe_frame_timing`: frame rate statistics and, on request, every frame's PTS.
+pub async fn handle_probe_frame_timing(id: &str, path: &Path, include_pts: bool) -> Response {
+    let Some(ffprobe) = find_ffprobe() else {
+        return ffmpeg_missing(id);
+    };
+    match probe_frame_timing(&ffprobe, path).await {
+        Ok(mut timing) => {
+            if !include_pts {
+                timing.pts = None;
+            }
+            Response::ok(id, serde_json::to_value(timing).unwrap_or_default())
+        }
+        Err(e) => Response::error(id, error_codes::MEDIA_FAILED, e),
+    }
+}
+
+/// Output name suffix for a conform to `fps`: `cfr29.97`, `cfr25`
+fn cfr_suffix(fps: f64) -> String {
+    let rate = format!("{:.3}", fps)
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string();
+    format!("cfr{}", rate)
+}
+
+/// `conform_cfr` job: re-encode `path` at a constant `fps` (default: the
+/// suggested rate) into `output` (default: next to the source).
+pub async fn handle_conform_cfr(
+    path: &Path,
+    fps: Option<f64>,
+    output: Option<PathBuf>,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+
+    if let Some(output) = &output {
+        if let Err(e) = check_output_extension(output, "mp4") {
+            return fail(error_codes::INVALID_ARGUMENT, e);
+        }
+    }
+    let (Some(ffmpeg), Some(ffprobe)) = (find_ffmpeg(), find_ffprobe()) else {
+        return ffmpeg_missing(id).with_job_id(job_id);
+    };
+
+    reporter
+        .progress(&JobProgress::stage(job_stages::ANALYZING, 0.0))
+        .await;
+    let timing = tokio::select! {
+        timing = probe_frame_timing(&ffprobe, path) => match timing {
+            Ok(timing) => timing,
+            Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+        },
+        _ = cancel.cancelled() => return fail(error_codes::CANCELLED, "Conform cancelled".to_string()),
+    };
+    let fps = fps.unwrap_or(timing.suggested_fps);
+    if !(1.0..=240.0).contains(&fps) {
+        return fail(
+            error_codes::INVALID_ARGUMENT,
+            format!("Frame rate {} is out of range", fps),
+        );
+    }
+    let output = output.unwrap_or_else(|| default_output(path, &cfr_suffix(fps), "mp4"));
+
+    let caps = encoder::capabilities(&ffmpeg).await;
+    let preference = crate::config::current().media.encoder_preference;
+    let mut selected = match encoder::select(VideoCodec::H264, preference, caps) {
+        Ok(selected) => selected,
+        Err(e) => return fail(error_codes::MEDIA_FAILED, e),
+    };
+    if selected.backend == Backend::Nvenc {
+        selected.gpu = crate::gpu::selected_index().await;
+    }
+
+    let mut args: Vec<OsString> = vec!["-i".into(), path.into()];
+    args.extend(["-map", "0:v:0", "-map", "0:a?", "-fps_mode", "cfr", "-r"].map(OsString::from));
+    args.push(format!("{:.6}", fps).into());
+    args.extend(
+        encoder::video_args(VideoCodec::H264, &selected, RateControl::Crf { value: CONFORM_CRF })
+            .into_iter()
+            .map(OsString::from),
+    );
+    // Fill audio gaps left by dropped frames so sound stays in sync
+    args.extend(
+        [
+            "-af", "aresample=async=1", "-c:a", "aac", "-b:a", "320k", "-movflags", "+faststart",
+            "-f", "mp4",
+        ]
+        .map(OsString::from),
+    );
+
+    info!(
+        "Conforming {} to {:.3} fps with {}",
+        path.display(),
+        fps,
+        selected.encoder
+    );
+    let mut cmd = ffmpeg_command(&ffmpeg);
+    if selected.gpu.is_some() {
+        crate::gpu::use_pci_bus_order(&mut cmd);
+    }
+    let stage = job_stages::ENCODING;
+    match render_to(cmd, args, &output, timing.duration, stage, reporter, cancel).await {
+        Some(Ok(())) => {}
+        Some(Err(e)) => return fail(error_codes::MEDIA_FAILED, e),
+        None => return fail(error_codes::CANCELLED, "Conform cancelled".to_string()),
+    }
+
+    Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "path": output,
+            "fps": fps,
+            "encoder": selected.encoder,
+            "source_vfr": timing.vfr,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyze_constant_and_variable() {
+        // 30 fps in decode order (B-frames), with a duplicate packet
+        let mut pts: Vec<f64> = (0..300).map(|i| i as f64 / 30.0).collect();
+        pts.swap(10, 11);
+        pts.push(0.0);
+        let timing = analyze(pts, Some(30.0)).unwrap();
+        assert_eq!(timing.frames, 300);
+        assert!(!timing.vfr);
+        assert!((timing.average_fps - 30.0).abs() < 1e-6);
+        assert_eq!(timing.suggested_fps, 30.0);
+
+        // Phone recording drifting between ~24 and ~30 fps
+        let mut t = 0.0;
+        let pts: Vec<f64> = (0..300)
+            .map(|i| {
+                t += if i % 3 == 0 { 1.0 / 24.0 } else { 1.0 / 30.0 };
+                t
+            })
+            .collect();
+        let timing = analyze(pts, Some(30.0)).unwrap();
+        assert!(timing.vfr);
+        assert!(timing.irregular_ratio > 0.3);
+
+        assert!(analyze(vec![1.0], None).is_none());
+    }
+
+    #[test]
+    fn test_snap_fps_and_rates() {
+        assert!((snap_fps(29.95) - 30000.0 / 1001.0).abs() < 1e-9);
+        assert_eq!(snap_fps(25.1), 25.0);
+        assert_eq!(snap_fps(37.0), 37.0);
+        // Dropped frames pull the average below the declared rate
+        assert_eq!(suggest_fps(28.4, Some(30.0)), 30.0);
+        assert_eq!(suggest_fps(28.4, Some(90000.0)), 28.4);
+        assert_eq!(suggest_fps(49.9, None), 50.0);
+        assert!((parse_rate("30000/1001").unwrap() - 29.97).abs() < 0.001);
+        assert_eq!(parse_rate("0/0"), None);
+    }
+
+    #[test]
+    fn test_default_output_name() {
+        let path = Path::new("/nonexistent/media/clip.mov");
+        assert_eq!(
+            default_output(path, &cfr_suffix(30000.0 / 1001.0), "mp4"),
+            PathBuf::from("/nonexistent/media/clip_cfr29.97.mp4")
+        );
+        assert_eq!(
+            default_output(path, &cfr_suffix(25.0), "mp4"),
+            PathBuf::from("/nonexistent/media/clip_cfr25.mp4")
+        );
+    }
+}
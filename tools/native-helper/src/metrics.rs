@@ -0,0 +1,178 @@
+//! Prometheus metrics
+//!
+//! With `--metrics` (or `[helper] metrics = true`) the HTTP server exposes
+//! `GET /metrics` in the Prometheus text format, for people who monitor an
+//! edit workstation with standard tooling. Counters are always collected;
+//! they are process-local and reset when the helper restarts.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the job duration histogram buckets
+const DURATION_BUCKETS: &[f64] = &[1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 3600.0];
+
+static DECODED_FRAMES: AtomicU64 = AtomicU64::new(0);
+static DOWNLOAD_BYTES: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+static CACHE: Mutex<BTreeMap<&'static str, (u64, u64)>> = Mutex::new(BTreeMap::new());
+static JOBS: Mutex<BTreeMap<&'static str, Histogram>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Count per bucket of `DURATION_BUCKETS` (not cumulative)
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if let Some(i) = DURATION_BUCKETS.iter().position(|&le| secs <= le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Frames decoded by ffmpeg for the editor (stills, contact sheet tiles).
+pub fn add_decoded_frames(frames: u64) {
+    DECODED_FRAMES.fetch_add(frames, Ordering::Relaxed);
+}
+
+/// A finished download of `bytes`.
+pub fn add_download(bytes: u64) {
+    DOWNLOADS.fetch_add(1, Ordering::Relaxed);
+    DOWNLOAD_BYTES.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// A lookup in one of the helper's caches.
+pub fn record_cache(cache: &'static str, hit: bool) {
+    let mut caches = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let entry = caches.entry(cache).or_default();
+    if hit {
+        entry.0 += 1;
+    } else {
+        entry.1 += 1;
+    }
+}
+
+/// A job of `kind` ran for `elapsed` (finished, failed or cancelled).
+pub fn record_job(kind: &'static str, elapsed: Duration) {
+    JOBS.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(kind)
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+}
+
+/// Gauges owned by the server, sampled when `/metrics` is scraped
+pub struct Gauges {
+    pub sessions: usize,
+    pub running_jobs: usize,
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Render everything in the Prometheus text exposition format.
+pub fn render(gauges: &Gauges) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "masterselects_helper_info", "gauge", "Helper version");
+    let _ = writeln!(
+        out,
+        "masterselects_helper_info{{version=\"{}\"}} 1",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    header(&mut out, "masterselects_sessions", "gauge", "Connected WebSocket sessions");
+    let _ = writeln!(out, "masterselects_sessions {}", gauges.sessions);
+    header(&mut out, "masterselects_jobs_running", "gauge", "Jobs currently running");
+    let _ = writeln!(out, "masterselects_jobs_running {}", gauges.running_jobs);
+
+    header(&mut out, "masterselects_decoded_frames_total", "counter", "Frames decoded for stills and contact sheets");
+    let _ = writeln!(
+        out,
+        "masterselects_decoded_frames_total {}",
+        DECODED_FRAMES.load(Ordering::Relaxed)
+    );
+    header(&mut out, "masterselects_downloads_total", "counter", "Completed downloads");
+    let _ = writeln!(out, "masterselects_downloads_total {}", DOWNLOADS.load(Ordering::Relaxed));
+    header(&mut out, "masterselects_download_bytes_total", "counter", "Bytes of completed downloads");
+    let _ = writeln!(
+        out,
+        "masterselects_download_bytes_total {}",
+        DOWNLOAD_BYTES.load(Ordering::Relaxed)
+    );
+
+    let caches = CACHE.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    header(&mut out, "masterselects_cache_hits_total", "counter", "Cache lookups answered from the cache");
+    for (cache, (hits, _)) in &caches {
+        let _ = writeln!(out, "masterselects_cache_hits_total{{cache=\"{}\"}} {}", cache, hits);
+    }
+    header(&mut out, "masterselects_cache_misses_total", "counter", "Cache lookups that had to compute the value");
+    for (cache, (_, misses)) in &caches {
+        let _ = writeln!(out, "masterselects_cache_misses_total{{cache=\"{}\"}} {}", cache, misses);
+    }
+
+    let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    header(&mut out, "masterselects_job_duration_seconds", "histogram", "Wall time of finished jobs");
+    for (kind, histogram) in &jobs {
+        let mut cumulative = 0;
+        for (le, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "masterselects_job_duration_seconds_bucket{{kind=\"{}\",le=\"{}\"}} {}",
+                kind, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "masterselects_job_duration_seconds_bucket{{kind=\"{}\",le=\"+Inf\"}} {}",
+            kind, histogram.count
+        );
+        let _ = writeln!(out, "masterselects_job_duration_seconds_sum{{kind=\"{}\"}} {}", kind, histogram.sum);
+        let _ = writeln!(out, "masterselects_job_duration_seconds_count{{kind=\"{}\"}} {}", kind, histogram.count);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.5);
+        histogram.observe(10.0);
+        histogram.observe(7200.0);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[2], 1);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), 2);
+        assert_eq!(histogram.count, 3);
+    }
+
+    #[test]
+    fn test_render() {
+        record_job("test_render", Duration::from_secs(3));
+        record_cache("test_render", true);
+        let text = render(&Gauges {
+            sessions: 2,
+            running_jobs: 0,
+        });
+        assert!(text.contains("masterselects_sessions 2\n"));
+        assert!(text.contains("masterselects_cache_hits_total{cache=\"test_render\"} 1\n"));
+        assert!(text.contains("masterselects_job_duration_seconds_bucket{kind=\"test_render\",le=\"1\"} 0\n"));
+        assert!(text.contains("masterselects_job_duration_seconds_bucket{kind=\"test_render\",le=\"5\"} 1\n"));
+        assert!(text.contains("masterselects_job_duration_seconds_count{kind=\"test_render\"} 1\n"));
+    }
+}
@@ -0,0 +1,518 @@
+//! Advisory locks for projects on shared drives
+//!
+//! `lock_project` puts a lock file next to the project so a second editor
+//! opening it sees who has it and can fall back to read-only:
+//! ```text
+//! Film.msp.lock
+//! {"owner":"anna@EDIT-02","user":"anna","host":"EDIT-02","pid":4242,
+//!  "acquired_at":1760000000,"heartbeat":1760000030}
+//! ```
+//! The helper rewrites the heartbeat while the lock is held. A lock whose
+//! heartbeat is older than `STALE_AFTER` belongs to a helper that crashed or
+//! lost the drive and is taken over. Locks are released by `unlock_project`,
+//! when the owning session disconnects and when the helper shuts down.
+//!
+//! While a project is locked its modification time is polled, and the
+//! owning session is told when someone else wrote the file or took the lock:
+//! ```text
+//! {"type":"project_changed","path":"…/Film.msp","modified":1760000100,"size":48213}
+//! {"type":"project_lock_lost","path":"…/Film.msp","holder":{…}}
+//! ```
+//! Saves through `write_file` do not count as changes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::session::AppState;
+
+/// How often held projects are checked for outside changes
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the heartbeat of a held lock is rewritten
+const HEARTBEAT_INTERVAL: u64 = 30;
+/// A lock without a heartbeat for this long is abandoned
+const STALE_AFTER: u64 = 120;
+/// Reads of a lock file another helper is still writing
+const CREATE_RETRIES: u32 = 5;
+
+/// Contents of a lock file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockInfo {
+    /// Name shown to other users (`user@host` unless the editor passed one)
+    pub owner: String,
+    pub user: String,
+    pub host: String,
+    pub pid: u32,
+    pub acquired_at: u64,
+    pub heartbeat: u64,
+}
+
+impl LockInfo {
+    fn is_ours(&self) -> bool {
+        self.host == host_name() && self.pid == std::process::id()
+    }
+
+    fn is_stale(&self, now: u64) -> bool {
+        now.saturating_sub(self.heartbeat) > STALE_AFTER
+    }
+}
+
+/// Result of `lock_project`
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockOutcome {
+    Acquired(LockInfo),
+    /// Someone else holds the project; open it read-only
+    HeldBy(LockInfo),
+}
+
+/// Something that happened to a held project since the last poll
+#[derive(Debug, Clone, PartialEq)]
+pub enum LockEvent {
+    Changed { modified: u64, size: u64 },
+    Lost(Option<LockInfo>),
+}
+
+struct HeldLock {
+    /// The project path as the editor sent it
+    project: PathBuf,
+    session_id: String,
+    info: LockInfo,
+    /// Modification time and size after the last load or save
+    known: Option<(SystemTime, u64)>,
+}
+
+/// Locks held by this helper, keyed by canonical project path
+#[derive(Default)]
+pub struct ProjectLocks {
+    held: Mutex<HashMap<PathBuf, HeldLock>>,
+}
+
+/// `Film.msp` -> `Film.msp.lock`
+pub fn lock_path(project: &Path) -> PathBuf {
+    let mut name = project.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    project.with_file_name(name)
+}
+
+/// Key of `project` in `ProjectLocks::held`, so every spelling of the same
+/// file (case on Windows, `.` segments, symlinked shares) finds one entry
+fn held_key(project: &Path) -> PathBuf {
+    if let Ok(canonical) = project.canonicalize() {
+        return canonical;
+    }
+    match (project.parent().and_then(|p| p.canonicalize().ok()), project.file_name()) {
+        (Some(parent), Some(name)) => parent.join(name),
+        _ => project.to_path_buf(),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn host_name() -> String {
+    let from_env = std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME"));
+    #[cfg(unix)]
+    let from_env = from_env.or_else(|_| {
+        std::fs::read_to_string("/etc/hostname").map(|name| name.trim().to_string())
+    });
+    from_env
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn user_name() -> String {
+    std::env::var("USERNAME")
+        .or_else(|_| std::env::var("USER"))
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn read_lock(path: &Path) -> Option<LockInfo> {
+    let text = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Create the lock file if there is none yet. Fails with `AlreadyExists`
+/// when another helper got there first, so two helpers opening a project at
+/// the same moment can't both take it.
+fn create_lock(path: &Path, info: &LockInfo) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let json = serde_json::to_string_pretty(info).map_err(std::io::Error::other)?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(json.as_bytes()).inspect_err(|_| {
+        let _ = std::fs::remove_file(path);
+    })
+}
+
+/// Read a lock file that may have just been created and not written yet.
+fn read_new_lock(path: &Path) -> Option<LockInfo> {
+    for _ in 0..CREATE_RETRIES {
+        if let Some(info) = read_lock(path) {
+            return Some(info);
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    read_lock(path)
+}
+
+/// Replace the lock file (refresh or takeover) through a temp file so
+/// readers never see half of it.
+fn write_lock(path: &Path, info: &LockInfo) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(info).map_err(std::io::Error::other)?;
+    let tmp = path.with_extension("lock.tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&tmp);
+    })
+}
+
+impl ProjectLocks {
+    /// Lock `project` for `session_id`. An existing lock is only replaced
+    /// when it is stale, held by this session, or `force` is set.
+    pub fn acquire(
+        &self,
+        project: &Path,
+        session_id: &str,
+        owner: Option<String>,
+        force: bool,
+    ) -> std::io::Result<LockOutcome> {
+        let key = held_key(project);
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        let held_here = match held.get(&key) {
+            Some(lock) if lock.session_id != session_id && !force => {
+                return Ok(LockOutcome::HeldBy(lock.info.clone()));
+            }
+            Some(lock) => lock.session_id == session_id,
+            None => false,
+        };
+
+        let path = lock_path(project);
+        let now = now();
+        let user = user_name();
+        let host = host_name();
+        let info = LockInfo {
+            owner: owner.unwrap_or_else(|| format!("{}@{}", user, host)),
+            user,
+            host,
+            pid: std::process::id(),
+            acquired_at: now,
+            heartbeat: now,
+        };
+        match create_lock(&path, &info) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                // An unreadable leftover (e.g. from a crash mid-write) is replaced
+                // A lock of this helper that no session here holds (e.g. taken
+                // through another path to the same file) counts as someone else's
+                if let Some(existing) = read_new_lock(&path) {
+                    if !held_here && !existing.is_stale(now) && !force {
+                        return Ok(LockOutcome::HeldBy(existing));
+                    }
+                    if !existing.is_ours() {
+                        warn!("Taking over the lock of {} from {}", project.display(), existing.owner);
+                    }
+                }
+                write_lock(&path, &info)?;
+            }
+            Err(e) => return Err(e),
+        }
+        info!("Locked project {}", project.display());
+        held.insert(
+            key,
+            HeldLock {
+                project: project.to_path_buf(),
+                session_id: session_id.to_string(),
+                info: info.clone(),
+                known: file_state(project),
+            },
+        );
+        Ok(LockOutcome::Acquired(info))
+    }
+
+    /// Release a lock held by `session_id`. Returns false if it held none.
+    pub fn release(&self, project: &Path, session_id: &str) -> bool {
+        let key = held_key(project);
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        match held.get(&key) {
+            Some(lock) if lock.session_id == session_id => {
+                if let Some(lock) = held.remove(&key) {
+                    remove_lock_file(&lock.project);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Release every lock of a disconnected session.
+    pub fn release_session(&self, session_id: &str) {
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        held.retain(|_, lock| {
+            if lock.session_id != session_id {
+                return true;
+            }
+            remove_lock_file(&lock.project);
+            false
+        });
+    }
+
+    /// Release all locks before the helper exits.
+    pub fn release_all(&self) {
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        for lock in held.values() {
+            remove_lock_file(&lock.project);
+        }
+        held.clear();
+    }
+
+    /// Remember the state of `project` after the editor saved it.
+    pub fn note_saved(&self, project: &Path) {
+        let key = held_key(project);
+        if let Some(lock) = self.held.lock().unwrap_or_else(|e| e.into_inner()).get_mut(&key) {
+            lock.known = file_state(project);
+        }
+    }
+
+    /// Check held projects for outside changes and refresh heartbeats.
+    /// Returns `(session_id, project, event)` for every project that needs
+    /// telling its session about.
+    pub fn poll(&self) -> Vec<(String, PathBuf, LockEvent)> {
+        let mut held = self.held.lock().unwrap_or_else(|e| e.into_inner());
+        let now = now();
+        let mut events = Vec::new();
+
+        held.retain(|_, lock| {
+            let project = lock.project.clone();
+            let path = lock_path(&project);
+            let current = read_lock(&path);
+            if current.as_ref() != Some(&lock.info) {
+                warn!("Lost the lock of {}", project.display());
+                events.push((lock.session_id.clone(), project, LockEvent::Lost(current)));
+                return false;
+            }
+
+            if now.saturating_sub(lock.info.heartbeat) >= HEARTBEAT_INTERVAL {
+                let mut info = lock.info.clone();
+                info.heartbeat = now;
+                match write_lock(&path, &info) {
+                    Ok(()) => lock.info = info,
+                    Err(e) => warn!("Cannot refresh the lock of {}: {}", project.display(), e),
+                }
+            }
+
+            let state = file_state(&project);
+            if state.is_some() && state != lock.known {
+                lock.known = state;
+                if let Some((modified, size)) = state {
+                    let modified = modified
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    events.push((
+                        lock.session_id.clone(),
+                        project,
+                        LockEvent::Changed { modified, size },
+                    ));
+                }
+            }
+            true
+        });
+        events
+    }
+}
+
+fn remove_lock_file(project: &Path) {
+    let path = lock_path(project);
+    // Never delete a lock someone else has taken over meanwhile
+    if read_lock(&path).is_some_and(|info| info.is_ours()) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Cannot remove {}: {}", path.display(), e);
+        }
+    }
+    info!("Unlocked project {}", project.display());
+}
+
+/// Start polling held projects.
+pub fn start(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let locks_state = state.clone();
+            let Ok(events) = tokio::task::spawn_blocking(move || locks_state.project_locks.poll()).await
+            else {
+                continue;
+            };
+            for (session_id, project, event) in events {
+                let message = match event {
+                    LockEvent::Changed { modified, size } => serde_json::json!({
+                        "type": "project_changed",
+                        "path": project,
+                        "modified": modified,
+                        "size": size,
+                    }),
+                    LockEvent::Lost(holder) => serde_json::json!({
+                        "type": "project_lock_lost",
+                        "path": project,
+                        "holder": holder,
+                    }),
+                };
+                state.send_to(&session_id, &message.to_string()).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_project() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ms-lock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let project = dir.join("Film.msp");
+        std::fs::write(&project, "{}").unwrap();
+        project
+    }
+
+    #[test]
+    fn test_lock_path() {
+        assert_eq!(lock_path(Path::new("/p/Film.msp")), PathBuf::from("/p/Film.msp.lock"));
+    }
+
+    #[test]
+    fn test_create_lock_is_exclusive() {
+        let project = temp_project();
+        let path = lock_path(&project);
+        let first = LockInfo {
+            owner: "anna@EDIT-01".to_string(),
+            user: "anna".to_string(),
+            host: "EDIT-01".to_string(),
+            pid: 1,
+            acquired_at: now(),
+            heartbeat: now(),
+        };
+        let second = LockInfo {
+            owner: "ben@EDIT-07".to_string(),
+            ..first.clone()
+        };
+
+        create_lock(&path, &first).unwrap();
+        let err = create_lock(&path, &second).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(read_new_lock(&path), Some(first));
+
+        std::fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let project = temp_project();
+        let locks = ProjectLocks::default();
+
+        let LockOutcome::Acquired(info) = locks.acquire(&project, "a", None, false).unwrap() else {
+            panic!("lock not acquired");
+        };
+        assert_eq!(read_lock(&lock_path(&project)), Some(info.clone()));
+        // A second session of this helper gets the project read-only
+        assert_eq!(
+            locks.acquire(&project, "b", None, false).unwrap(),
+            LockOutcome::HeldBy(info)
+        );
+        assert!(!locks.release(&project, "b"));
+        assert!(locks.release(&project, "a"));
+        assert!(!lock_path(&project).exists());
+
+        std::fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_other_spelling_of_held_project() {
+        let project = temp_project();
+        let dir = project.parent().unwrap();
+        let other = dir.join(".").join("Film.msp");
+        let locks = ProjectLocks::default();
+
+        let LockOutcome::Acquired(info) = locks.acquire(&project, "a", None, false).unwrap() else {
+            panic!("lock not acquired");
+        };
+        assert_eq!(locks.acquire(&other, "b", None, false).unwrap(), LockOutcome::HeldBy(info));
+        assert!(locks.release(&other, "a"));
+
+        // Our own lock file without a held entry is not taken over silently
+        let LockOutcome::Acquired(info) = locks.acquire(&project, "a", None, false).unwrap() else {
+            panic!("lock not acquired");
+        };
+        locks.held.lock().unwrap().clear();
+        assert_eq!(locks.acquire(&other, "b", None, false).unwrap(), LockOutcome::HeldBy(info));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_foreign_and_stale_locks() {
+        let project = temp_project();
+        let locks = ProjectLocks::default();
+        let mut foreign = LockInfo {
+            owner: "ben@EDIT-07".to_string(),
+            user: "ben".to_string(),
+            host: "EDIT-07".to_string(),
+            pid: 1,
+            acquired_at: now(),
+            heartbeat: now(),
+        };
+        write_lock(&lock_path(&project), &foreign).unwrap();
+        assert_eq!(
+            locks.acquire(&project, "a", None, false).unwrap(),
+            LockOutcome::HeldBy(foreign.clone())
+        );
+
+        foreign.heartbeat -= STALE_AFTER + 1;
+        write_lock(&lock_path(&project), &foreign).unwrap();
+        let outcome = locks.acquire(&project, "a", Some("Anna".to_string()), false).unwrap();
+        assert!(matches!(outcome, LockOutcome::Acquired(info) if info.owner == "Anna"));
+
+        std::fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_poll_reports_changes_and_lost_locks() {
+        let project = temp_project();
+        let locks = ProjectLocks::default();
+        locks.acquire(&project, "a", None, false).unwrap();
+        assert!(locks.poll().is_empty());
+
+        std::fs::write(&project, "{\"changed\":true}").unwrap();
+        let events = locks.poll();
+        assert!(matches!(events.as_slice(), [(_, _, LockEvent::Changed { size: 16, .. })]));
+        // Our own saves are not reported
+        std::fs::write(&project, "{}").unwrap();
+        locks.note_saved(&project);
+        assert!(locks.poll().is_empty());
+
+        std::fs::remove_file(lock_path(&project)).unwrap();
+        assert!(matches!(locks.poll().as_slice(), [(_, _, LockEvent::Lost(None))]));
+        assert!(!locks.release(&project, "a"));
+
+        std::fs::remove_dir_all(project.parent().unwrap()).unwrap();
+    }
+}
@@ -2,6 +2,13 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::cloud_sync::Resolve;
+use crate::config::Settings;
+use crate::download::{CookieOptions, DownloadExtras};
+use crate::media::{AudioFormat, EncoderPreference, RateControl, TranscodeCodec};
+use crate::quota::DownloadUsage;
+use crate::youtube::{Chapter, Privacy};
+
 /// Incoming commands from browser
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
@@ -41,6 +48,12 @@ pub enum Command {
         result: serde_json::Value,
     },
 
+    /// List the WebSocket sessions currently connected to the helper
+    ListSessions { id: String },
+
+    /// Cancel a running job started by this session
+    CancelJob { id: String, job_id: String },
+
     /// Download a YouTube video using yt-dlp (legacy command name)
     DownloadYoutube {
         id: String,
@@ -49,6 +62,12 @@ pub enum Command {
         format_id: Option<String>,
         #[serde(default)]
         output_dir: Option<String>,
+        /// Subtitles, thumbnail and chapters to fetch with the video
+        #[serde(flatten)]
+        extras: DownloadExtras,
+        /// Browser profile or cookies.txt for gated videos
+        #[serde(flatten)]
+        cookies: CookieOptions,
     },
 
     /// Generic download using yt-dlp (supports all platforms: YouTube, TikTok, Instagram, etc.)
@@ -59,12 +78,51 @@ pub enum Command {
         format_id: Option<String>,
         #[serde(default)]
         output_dir: Option<String>,
+        /// Subtitles, thumbnail and chapters to fetch with the video
+        #[serde(flatten)]
+        extras: DownloadExtras,
+        /// Browser profile or cookies.txt for gated videos
+        #[serde(flatten)]
+        cookies: CookieOptions,
+    },
+
+    /// Download only `start`..`end` seconds of a video (job with progress)
+    DownloadSection {
+        id: String,
+        url: String,
+        start: f64,
+        end: f64,
+        #[serde(default)]
+        format_id: Option<String>,
+        #[serde(default)]
+        output_dir: Option<String>,
+        #[serde(flatten)]
+        cookies: CookieOptions,
     },
 
     /// List available formats for a video URL
     ListFormats {
         id: String,
         url: String,
+        #[serde(flatten)]
+        cookies: CookieOptions,
+    },
+
+    /// Installed browsers yt-dlp can read cookies from
+    ListBrowsers { id: String },
+
+    /// yt-dlp/deno paths and versions, optionally with the latest releases
+    YtdlpStatus {
+        id: String,
+        #[serde(default)]
+        check_latest: bool,
+    },
+
+    /// Install or update the managed yt-dlp (and deno) (job with progress)
+    YtdlpUpdate {
+        id: String,
+        #[serde(default)]
+        include_deno: bool,
     },
 
     /// Get a file from local filesystem (for serving downloads)
@@ -137,6 +195,36 @@ pub enum Command {
         path: String,
     },
 
+    /// Take the advisory lock of a project file (read-only when someone else has it)
+    LockProject {
+        id: String,
+        path: String,
+        /// Name shown to others (default: `user@host`)
+        #[serde(default)]
+        owner: Option<String>,
+        /// Take the lock even if another editor holds it
+        #[serde(default)]
+        force: bool,
+    },
+
+    /// Release a project lock taken with `lock_project`
+    UnlockProject {
+        id: String,
+        path: String,
+    },
+
+    /// Sync a project file with its copy on the configured WebDAV server
+    SyncProject {
+        id: String,
+        path: String,
+        /// Path inside the WebDAV collection (default: the file name)
+        #[serde(default)]
+        remote_name: Option<String>,
+        /// Settle a conflict: `local` uploads, `remote` downloads
+        #[serde(default)]
+        resolve: Option<Resolve>,
+    },
+
     /// Open a native OS folder picker dialog
     PickFolder {
         id: String,
@@ -186,6 +274,291 @@ pub enum Command {
 
     /// Uninstall MatAnyone2 (remove venv, models, uv)
     MatAnyoneUninstall { id: String },
+
+    // ── Watch Folder Commands ──
+
+    /// Watch a directory and announce new media files (`watch_folder_file` messages)
+    AddWatchFolder {
+        id: String,
+        path: String,
+        /// Extensions to import (e.g. `["mov", "wav"]`); common media types when empty
+        #[serde(default)]
+        extensions: Vec<String>,
+        /// Media-panel bin new files go into
+        #[serde(default)]
+        bin: Option<String>,
+        #[serde(default)]
+        recursive: bool,
+        /// Ask the web app to generate proxies for new files
+        #[serde(default)]
+        generate_proxies: bool,
+    },
+
+    /// Stop watching a folder
+    RemoveWatchFolder { id: String, folder_id: String },
+
+    /// List watched folders
+    ListWatchFolders { id: String },
+
+    /// List the media in a folder, grouped into bins by subfolder, for a one-off import
+    ScanMediaFolder {
+        id: String,
+        path: String,
+        /// Include subfolders (default: true)
+        #[serde(default)]
+        recursive: Option<bool>,
+        /// Extensions to import; common media types when empty
+        #[serde(default)]
+        extensions: Vec<String>,
+    },
+
+    // ── Media Commands ──
+
+    /// Grab one frame as an encoded image (base64)
+    ExtractFrame {
+        id: String,
+        path: String,
+        /// Time in seconds
+        time: f64,
+        /// "jpeg" (default), "png", or "webp"
+        #[serde(default)]
+        format: Option<String>,
+        /// Output width in pixels (16-8192); source width when omitted (at
+        /// most 1280 for sessions on another machine)
+        #[serde(default)]
+        width: Option<u32>,
+    },
+
+    /// Render a grid of evenly spaced frames as one encoded image (base64)
+    RenderContactSheet {
+        id: String,
+        path: String,
+        #[serde(default)]
+        cols: Option<u32>,
+        #[serde(default)]
+        rows: Option<u32>,
+        /// Width of each tile in pixels (default 240)
+        #[serde(default)]
+        width: Option<u32>,
+        #[serde(default)]
+        format: Option<String>,
+    },
+
+    /// Align clips to a reference recording by their audio (job)
+    SyncAudio {
+        id: String,
+        reference: String,
+        clips: Vec<String>,
+        /// Largest offset searched, in seconds (default 60)
+        #[serde(default)]
+        max_offset: Option<f64>,
+    },
+
+    /// Find speech in a dialogue recording and return volume keyframes that
+    /// duck music under it (job)
+    AutoDuck {
+        id: String,
+        dialogue: String,
+        /// Dip in dB (default 12)
+        #[serde(default)]
+        duck_db: Option<f32>,
+        /// Dialogue level in dBFS counted as speech (default -38)
+        #[serde(default)]
+        threshold_db: Option<f32>,
+        /// Seconds to ramp down before speech (default 0.15)
+        #[serde(default)]
+        attack: Option<f64>,
+        /// Seconds to ramp back up after speech (default 0.6)
+        #[serde(default)]
+        release: Option<f64>,
+    },
+
+    /// Report usable video encoders (NVENC and software fallbacks) and the
+    /// ffmpeg arguments each codec would be exported with
+    ListEncoders {
+        id: String,
+        /// "auto", "hardware", or "software" (default: from settings)
+        #[serde(default)]
+        preference: Option<EncoderPreference>,
+        /// Rate control to build arguments for (default CRF 20)
+        #[serde(default)]
+        rate_control: RateControl,
+    },
+
+    /// Frame rate statistics of a clip, flagging variable frame rate
+    ProbeFrameTiming {
+        id: String,
+        path: String,
+        /// Also return every frame's presentation time
+        #[serde(default)]
+        include_pts: bool,
+    },
+
+    /// Re-encode a (VFR) clip at a constant frame rate (job with progress)
+    ConformCfr {
+        id: String,
+        path: String,
+        /// Target rate (default: nearest standard rate to the average)
+        #[serde(default)]
+        fps: Option<f64>,
+        /// Output `.mp4` file (default: `<name>_cfr<fps>.mp4` next to the source)
+        #[serde(default)]
+        output: Option<String>,
+    },
+
+    /// Re-encode a clip to H.264/HEVC or a mezzanine profile (job with progress)
+    Transcode {
+        id: String,
+        input: String,
+        /// Output file, `.mp4` for `h264`/`hevc` and `.mov` for mezzanine profiles
+        /// (default: `<name>_<codec>.mp4`/`.mov` next to the source)
+        #[serde(default)]
+        output: Option<String>,
+        /// `h264` (default), `hevc`, or a mezzanine profile such as `prores422_hq`
+        #[serde(default)]
+        codec: TranscodeCodec,
+        /// Constant quality; lower is better (default 20)
+        #[serde(default)]
+        crf: Option<u8>,
+        /// Average bitrate in kbit/s instead of `crf`
+        #[serde(default)]
+        bitrate_kbps: Option<u32>,
+        /// `1920x1080` (fit inside) or `720p`; never upscales
+        #[serde(default)]
+        resolution: Option<String>,
+    },
+
+    /// Write a clip's audio track to WAV/MP3/AAC (job with progress)
+    ExtractAudio {
+        id: String,
+        path: String,
+        /// `wav` (default), `mp3` or `aac`
+        #[serde(default)]
+        format: AudioFormat,
+        /// Output file (default: `<name>_audio.<ext>` next to the source)
+        #[serde(default)]
+        output: Option<String>,
+    },
+
+    /// Write a noise-reduced copy of a clip's audio (job with progress)
+    DenoiseAudio {
+        id: String,
+        path: String,
+        /// 0..1 (default 0.5)
+        #[serde(default)]
+        strength: Option<f32>,
+        /// Start of a noise-only stretch to learn the noise profile from, in seconds
+        #[serde(default)]
+        noise_start: Option<f64>,
+        #[serde(default)]
+        noise_end: Option<f64>,
+        #[serde(default)]
+        format: AudioFormat,
+        /// Output file (default: `<name>_denoised.<ext>` next to the source)
+        #[serde(default)]
+        output: Option<String>,
+    },
+
+    /// Hardware decode/encode support, ffmpeg version, GPUs, and free disk space
+    Capabilities { id: String },
+
+    /// NVIDIA GPUs with memory and NVDEC codecs, and the selected device
+    ListGpus { id: String },
+
+    // ── Transcription Commands ──
+
+    /// Transcribe a media file's audio with whisper.cpp (job with progress)
+    Transcribe {
+        id: String,
+        path: String,
+        /// Spoken language code (e.g. "en"); auto-detected when omitted
+        #[serde(default)]
+        language: Option<String>,
+        /// Model name (`base`, `small.en`, ...) or absolute path to a ggml model
+        #[serde(default)]
+        model: Option<String>,
+    },
+
+    // ── Publishing Commands ──
+
+    /// Whether YouTube publishing is configured and signed in
+    YoutubeStatus { id: String },
+
+    /// Sign in to YouTube with the OAuth device flow (job)
+    YoutubeLogin { id: String },
+
+    /// Revoke and remove the stored YouTube token
+    YoutubeLogout { id: String },
+
+    /// Upload a rendered file to YouTube (job with progress)
+    YoutubeUpload {
+        id: String,
+        path: String,
+        title: String,
+        #[serde(default)]
+        description: String,
+        #[serde(default)]
+        tags: Vec<String>,
+        /// "private" (default), "unlisted", or "public"
+        #[serde(default)]
+        privacy: Privacy,
+        /// Timeline markers, written into the description as chapters
+        #[serde(default)]
+        chapters: Vec<Chapter>,
+    },
+
+    // ── Diagnostics Commands ──
+
+    /// Delete least recently used downloads beyond the quota
+    Cleanup {
+        id: String,
+        /// Also delete downloads unused for this many days
+        #[serde(default)]
+        older_than_days: Option<u32>,
+        /// Report what would be deleted without deleting
+        #[serde(default)]
+        dry_run: bool,
+    },
+
+    /// Zip recent logs, crash reports and system info into the downloads dir
+    ExportDiagnostics {
+        id: String,
+        /// Project structure (tracks, clips, settings; no media) to include
+        #[serde(default)]
+        project: Option<serde_json::Value>,
+    },
+
+    // ── Preferences Commands ──
+
+    /// Read config.toml (defaults filled in)
+    GetSettings { id: String },
+
+    /// Replace config.toml; `[helper]` changes apply after a restart
+    SetSettings { id: String, settings: Settings },
+
+    // ── Self-update Commands ──
+
+    /// Check the release manifest for a newer helper build
+    UpdateCheck { id: String },
+
+    /// Download, verify, and install the latest helper build
+    UpdateInstall { id: String },
+
+    // ── Lifecycle Commands ──
+
+    /// Exit the helper once running jobs finish (`wait_for_jobs`) or are cancelled
+    Shutdown {
+        id: String,
+        #[serde(default)]
+        wait_for_jobs: bool,
+    },
+
+    /// Shut down like `shutdown`, then start again with the same arguments
+    Restart {
+        id: String,
+        #[serde(default)]
+        wait_for_jobs: bool,
+    },
 }
 
 /// Response types
@@ -208,6 +581,8 @@ pub struct OkResponse {
 pub struct ErrorResponse {
     pub id: String,
     pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job_id: Option<String>,
     pub error: ErrorInfo,
 }
 
@@ -221,6 +596,9 @@ pub struct ErrorInfo {
 #[derive(Debug, Clone, Serialize)]
 pub struct SystemInfo {
     pub version: String,
+    /// Ports the helper actually listens on (may differ from the default)
+    pub ws_port: Option<u16>,
+    pub http_port: Option<u16>,
     pub ytdlp_available: bool,
     pub download_dir: String,
     pub project_root: String,
@@ -229,6 +607,8 @@ pub struct SystemInfo {
     pub editor_connected: bool,
     pub matanyone_available: bool,
     pub matanyone_status: String,
+    /// Size of the downloads dir against its quota
+    pub download_usage: DownloadUsage,
 }
 
 // Helper functions for creating responses
@@ -249,6 +629,7 @@ impl Response {
         Response::Error(ErrorResponse {
             id: id.into(),
             ok: false,
+            job_id: None,
             error: ErrorInfo {
                 code: code.into(),
                 message: message.into(),
@@ -291,6 +672,7 @@ impl Response {
 pub mod error_codes {
     pub const AUTH_REQUIRED: &str = "AUTH_REQUIRED";
     pub const INVALID_TOKEN: &str = "INVALID_TOKEN";
+    pub const RATE_LIMITED: &str = "RATE_LIMITED";
     pub const FILE_NOT_FOUND: &str = "FILE_NOT_FOUND";
     pub const PERMISSION_DENIED: &str = "PERMISSION_DENIED";
     pub const INVALID_PATH: &str = "INVALID_PATH";
@@ -306,4 +688,22 @@ pub mod error_codes {
     pub const MATANYONE_NOT_RUNNING: &str = "MATANYONE_NOT_RUNNING";
     pub const MATANYONE_INFERENCE_FAILED: &str = "MATANYONE_INFERENCE_FAILED";
     pub const PYTHON_NOT_FOUND: &str = "PYTHON_NOT_FOUND";
+    pub const UPDATE_FAILED: &str = "UPDATE_FAILED";
+    pub const JOB_NOT_FOUND: &str = "JOB_NOT_FOUND";
+    pub const CANCELLED: &str = "CANCELLED";
+    pub const FFMPEG_NOT_FOUND: &str = "FFMPEG_NOT_FOUND";
+    pub const MEDIA_FAILED: &str = "MEDIA_FAILED";
+    pub const INVALID_ARGUMENT: &str = "INVALID_ARGUMENT";
+    pub const WHISPER_NOT_FOUND: &str = "WHISPER_NOT_FOUND";
+    pub const TRANSCRIBE_FAILED: &str = "TRANSCRIBE_FAILED";
+    pub const YOUTUBE_NOT_CONFIGURED: &str = "YOUTUBE_NOT_CONFIGURED";
+    pub const YOUTUBE_AUTH_REQUIRED: &str = "YOUTUBE_AUTH_REQUIRED";
+    pub const YOUTUBE_AUTH_FAILED: &str = "YOUTUBE_AUTH_FAILED";
+    pub const UPLOAD_FAILED: &str = "UPLOAD_FAILED";
+    pub const SHUTTING_DOWN: &str = "SHUTTING_DOWN";
+    pub const WATCH_FAILED: &str = "WATCH_FAILED";
+    pub const DIAGNOSTICS_FAILED: &str = "DIAGNOSTICS_FAILED";
+    pub const SETTINGS_FAILED: &str = "SETTINGS_FAILED";
+    pub const SYNC_NOT_CONFIGURED: &str = "SYNC_NOT_CONFIGURED";
+    pub const SYNC_FAILED: &str = "SYNC_FAILED";
 }
@@ -0,0 +1,127 @@
+//! Long-running job messages
+//!
+//! Commands that take a while (downloads, matting, ...) run as jobs. Every
+//! message for a job carries the command `id` and a helper-assigned `job_id`:
+//!
+//! ```text
+//! {"id":"7","ok":true,"type":"progress","job_id":"…","stage":"started","percent":0}
+//! {"id":"7","ok":true,"type":"progress","job_id":"…","stage":"downloading","percent":42,
+//!  "eta_secs":12,"bytes_done":1048576,"bytes_total":2490368,"speed":"1.2MiB/s","eta":"00:12"}
+//! {"id":"7","ok":true,"type":"complete","job_id":"…","path":"…"}
+//! {"id":"7","ok":false,"job_id":"…","error":{"code":"CANCELLED","message":"…"}}
+//! ```
+//!
+//! The first `started` event tells the client which `job_id` to pass to
+//! `cancel_job`. `speed`/`eta` are display strings kept for older clients.
+
+use serde::Serialize;
+
+use super::Response;
+
+/// Stage names used in progress events
+pub mod job_stages {
+    pub const STARTED: &str = "started";
+    pub const DOWNLOADING: &str = "downloading";
+    pub const MERGING: &str = "merging";
+    pub const PROCESSING: &str = "processing";
+    pub const EXTRACTING_AUDIO: &str = "extracting_audio";
+    pub const TRANSCRIBING: &str = "transcribing";
+    pub const ANALYZING: &str = "analyzing";
+    pub const ENCODING: &str = "encoding";
+    pub const AWAITING_AUTHORIZATION: &str = "awaiting_authorization";
+    pub const UPLOADING: &str = "uploading";
+}
+
+/// A typed progress update for a job
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobProgress {
+    pub stage: String,
+    pub percent: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_done: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_total: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<String>,
+}
+
+impl JobProgress {
+    pub fn stage(stage: &str, percent: f32) -> Self {
+        Self {
+            stage: stage.to_string(),
+            percent,
+            ..Default::default()
+        }
+    }
+}
+
+impl Response {
+    /// Progress event for a job
+    pub fn job_progress(id: impl Into<String>, job_id: &str, progress: &JobProgress) -> Self {
+        let mut data = serde_json::to_value(progress).unwrap_or_default();
+        data["type"] = serde_json::json!("progress");
+        Response::ok(id, data).with_job_id(job_id)
+    }
+
+    /// Successful completion of a job; `result` fields are merged into the message
+    pub fn job_complete(id: impl Into<String>, job_id: &str, result: serde_json::Value) -> Self {
+        let mut data = match result {
+            serde_json::Value::Object(_) => result,
+            other => serde_json::json!({ "result": other }),
+        };
+        data["type"] = serde_json::json!("complete");
+        Response::ok(id, data).with_job_id(job_id)
+    }
+
+    /// Attach a job ID to a response
+    pub fn with_job_id(self, job_id: &str) -> Self {
+        match self {
+            Response::Ok(mut ok) => {
+                ok.data["job_id"] = serde_json::json!(job_id);
+                Response::Ok(ok)
+            }
+            Response::Error(mut err) => {
+                err.job_id = Some(job_id.to_string());
+                Response::Error(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_job_messages() {
+        let mut progress = JobProgress::stage(job_stages::DOWNLOADING, 42.0);
+        progress.bytes_total = Some(100);
+        let json = serde_json::to_value(Response::job_progress("7", "job-1", &progress)).unwrap();
+        assert_eq!(json["id"], "7");
+        assert_eq!(json["type"], "progress");
+        assert_eq!(json["job_id"], "job-1");
+        assert_eq!(json["stage"], "downloading");
+        assert_eq!(json["bytes_total"], 100);
+        assert!(json.get("eta_secs").is_none());
+
+        let done = serde_json::to_value(Response::job_complete(
+            "7",
+            "job-1",
+            serde_json::json!({ "path": "/tmp/a.mp4" }),
+        ))
+        .unwrap();
+        assert_eq!(done["type"], "complete");
+        assert_eq!(done["path"], "/tmp/a.mp4");
+
+        let err = serde_json::to_value(
+            Response::error("7", "CANCELLED", "Job cancelled").with_job_id("job-1"),
+        )
+        .unwrap();
+        assert_eq!(err["ok"], false);
+        assert_eq!(err["job_id"], "job-1");
+    }
+}
@@ -1,5 +1,7 @@
 //! Protocol module - message types
 
 mod commands;
+mod jobs;
 
 pub use commands::*;
+pub use jobs::*;
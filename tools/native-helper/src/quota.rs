@@ -0,0 +1,318 @@
+//! Downloads directory quota
+//!
+//! Downloads and exports collect in the downloads dir. Once it grows past
+//! `[cache] downloads_gb` the least recently used files are deleted. A file
+//! counts as used when the helper downloads it or serves it to the editor
+//! (`/file`, `get_file`); those times are kept in
+//! ```text
+//! {data_local_dir}/MasterSelects/download-usage.json
+//! ```
+//! because file access times are unreliable (`noatime` mounts, Windows).
+//!
+//! Only files the helper has recorded are ever deleted, plus every file in
+//! its own default `masterselects-downloads` temp dir. A downloads dir
+//! pointed at e.g. `~/Downloads` never loses files the helper did not put
+//! there.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{config, utils};
+
+/// Files used this recently are never deleted (running downloads, fresh imports)
+const MIN_IDLE: Duration = Duration::from_secs(10 * 60);
+
+/// Repeated uses of a file within this window are not written to disk again
+const RECORD_RESOLUTION: u64 = 60;
+
+const BYTES_PER_GB: u64 = 1024 * 1024 * 1024;
+
+/// Current size of the downloads dir
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadUsage {
+    pub dir: PathBuf,
+    pub bytes: u64,
+    pub files: usize,
+    /// `None` when no quota is set
+    pub quota_bytes: Option<u64>,
+    /// Free space on the volume
+    pub available_bytes: Option<u64>,
+}
+
+/// Result of a cleanup run
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupReport {
+    pub removed: Vec<RemovedFile>,
+    pub freed_bytes: u64,
+    pub dry_run: bool,
+    pub usage: DownloadUsage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemovedFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+/// A file in the downloads dir
+#[derive(Debug, Clone)]
+struct Entry {
+    path: PathBuf,
+    bytes: u64,
+    /// Seconds since the epoch
+    last_used: u64,
+    /// Recorded by the helper, or in the helper's own dir
+    deletable: bool,
+}
+
+/// Return the path of the usage file.
+pub fn get_usage_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("download-usage.json")
+}
+
+static USAGE: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+
+fn store() -> &'static Mutex<HashMap<PathBuf, u64>> {
+    USAGE.get_or_init(|| {
+        let used = std::fs::read_to_string(get_usage_path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Mutex::new(used)
+    })
+}
+
+fn save(used: &HashMap<PathBuf, u64>) {
+    let path = get_usage_path();
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    match serde_json::to_string(used) {
+        Ok(json) => {
+            if let Err(e) = std::fs::write(&path, json) {
+                warn!("Cannot save download usage to {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Cannot serialize download usage: {}", e),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Quota from the settings; 0 means unlimited.
+fn quota_bytes() -> Option<u64> {
+    match config::current().cache.downloads_gb {
+        0 => None,
+        gb => Some(gb as u64 * BYTES_PER_GB),
+    }
+}
+
+/// Note that `path` was downloaded or read; ignored outside the downloads dir.
+pub fn record_use(path: &Path) {
+    if !path.starts_with(utils::get_download_dir()) {
+        return;
+    }
+    let now = unix_secs(SystemTime::now());
+    let mut used = store().lock().unwrap_or_else(|e| e.into_inner());
+    let previous = used.insert(path.to_path_buf(), now);
+    if previous.is_none_or(|t| now.saturating_sub(t) >= RECORD_RESOLUTION) {
+        save(&used);
+    }
+}
+
+/// Write uses not yet saved (throttled by `RECORD_RESOLUTION`) before exit.
+pub fn flush() {
+    if let Some(used) = USAGE.get() {
+        save(&used.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+/// All files below `dir` (symlinks are not followed).
+fn walk(dir: &Path, files: &mut Vec<(PathBuf, std::fs::Metadata)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(meta) = entry.metadata() else { continue };
+        if meta.is_dir() {
+            walk(&entry.path(), files);
+        } else if meta.is_file() {
+            files.push((entry.path(), meta));
+        }
+    }
+}
+
+fn scan(dir: &Path) -> Vec<Entry> {
+    let owned = dir == utils::default_download_dir();
+    let used = store().lock().unwrap_or_else(|e| e.into_inner()).clone();
+    let mut files = Vec::new();
+    walk(dir, &mut files);
+    files
+        .into_iter()
+        .map(|(path, meta)| {
+            let recorded = used.get(&path).copied();
+            let modified = meta.modified().map(unix_secs).unwrap_or(0);
+            Entry {
+                last_used: recorded.unwrap_or(0).max(modified),
+                bytes: meta.len(),
+                deletable: owned || recorded.is_some(),
+                path,
+            }
+        })
+        .collect()
+}
+
+fn summarize(dir: PathBuf, entries: &[Entry]) -> DownloadUsage {
+    DownloadUsage {
+        bytes: entries.iter().map(|e| e.bytes).sum(),
+        files: entries.len(),
+        quota_bytes: quota_bytes(),
+        available_bytes: utils::disk_space(&dir).map(|(available, _)| available),
+        dir,
+    }
+}
+
+/// Size of the downloads dir and its quota.
+pub fn usage() -> DownloadUsage {
+    let dir = utils::get_download_dir();
+    let entries = scan(&dir);
+    summarize(dir, &entries)
+}
+
+/// Pick files to delete, least recently used first: everything idle since
+/// `older_than`, then more until the total fits `quota`.
+fn plan(entries: &[Entry], quota: Option<u64>, older_than: Option<u64>, now: u64) -> Vec<usize> {
+    let mut total: u64 = entries.iter().map(|e| e.bytes).sum();
+    let mut candidates: Vec<usize> = (0..entries.len())
+        .filter(|&i| entries[i].deletable && entries[i].last_used + MIN_IDLE.as_secs() <= now)
+        .collect();
+    candidates.sort_by_key(|&i| entries[i].last_used);
+
+    let mut chosen = Vec::new();
+    for i in candidates {
+        let expired = older_than.is_some_and(|cutoff| entries[i].last_used < cutoff);
+        let over_quota = quota.is_some_and(|quota| total > quota);
+        if !expired && !over_quota {
+            continue;
+        }
+        total -= entries[i].bytes;
+        chosen.push(i);
+    }
+    chosen
+}
+
+/// Delete least recently used downloads until the quota is met, plus any
+/// unused for `older_than_days`. With `dry_run` nothing is deleted.
+pub fn cleanup(older_than_days: Option<u32>, dry_run: bool) -> CleanupReport {
+    let dir = utils::get_download_dir();
+    let mut entries = scan(&dir);
+    let now = unix_secs(SystemTime::now());
+    let older_than = older_than_days.map(|days| now.saturating_sub(days as u64 * 24 * 60 * 60));
+
+    let mut removed = Vec::new();
+    let mut gone = Vec::new();
+    for i in plan(&entries, quota_bytes(), older_than, now) {
+        let entry = &entries[i];
+        if !dry_run {
+            if let Err(e) = std::fs::remove_file(&entry.path) {
+                warn!("Cannot remove {}: {}", entry.path.display(), e);
+                continue;
+            }
+            info!("Removed download {} ({} bytes)", entry.path.display(), entry.bytes);
+            gone.push(i);
+        }
+        removed.push(RemovedFile {
+            path: entry.path.clone(),
+            bytes: entry.bytes,
+        });
+    }
+
+    if !dry_run {
+        let mut used = store().lock().unwrap_or_else(|e| e.into_inner());
+        used.retain(|path, _| path.exists());
+        save(&used);
+        drop(used);
+        gone.sort_unstable();
+        for i in gone.into_iter().rev() {
+            entries.remove(i);
+        }
+    }
+
+    CleanupReport {
+        freed_bytes: removed.iter().map(|r| r.bytes).sum(),
+        removed,
+        dry_run,
+        usage: summarize(dir, &entries),
+    }
+}
+
+/// Enforce the quota in the background (after a download, at startup).
+pub fn enforce_in_background() {
+    if quota_bytes().is_none() {
+        return;
+    }
+    tokio::task::spawn_blocking(|| {
+        let report = cleanup(None, false);
+        if report.freed_bytes > 0 {
+            info!(
+                "Download quota: removed {} files ({} bytes)",
+                report.removed.len(),
+                report.freed_bytes
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, bytes: u64, last_used: u64, deletable: bool) -> Entry {
+        Entry {
+            path: PathBuf::from(name),
+            bytes,
+            last_used,
+            deletable,
+        }
+    }
+
+    #[test]
+    fn test_plan_lru_to_quota() {
+        let now = 1_000_000;
+        let entries = vec![
+            entry("new", 40, now - 60, true),
+            entry("old", 30, now - 90_000, true),
+            entry("older", 30, now - 180_000, true),
+            entry("foreign", 50, now - 900_000, false),
+        ];
+        // 150 bytes against a quota of 120: the oldest deletable file goes
+        let chosen = plan(&entries, Some(120), None, now);
+        assert_eq!(chosen, vec![2]);
+
+        // A tighter quota cannot touch the fresh or foreign files
+        let chosen = plan(&entries, Some(10), None, now);
+        assert_eq!(chosen, vec![2, 1]);
+
+        assert!(plan(&entries, None, None, now).is_empty());
+    }
+
+    #[test]
+    fn test_plan_older_than() {
+        let now = 1_000_000;
+        let entries = vec![
+            entry("a", 10, now - 100_000, true),
+            entry("b", 10, now - 10_000, true),
+        ];
+        assert_eq!(plan(&entries, None, Some(now - 50_000), now), vec![0]);
+    }
+}
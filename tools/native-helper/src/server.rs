@@ -3,61 +3,145 @@
 use anyhow::Result;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
+use tokio_rustls::TlsAcceptor;
 use tokio_tungstenite::WebSocketStream;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use warp::Filter;
 
 #[cfg(windows)]
 use std::sync::atomic::Ordering;
 
+use crate::discovery::{self, BoundPorts};
 use crate::download;
 use crate::matanyone;
-use crate::protocol::{error_codes, Command, Response};
-use crate::session::{AppState, Session};
+use crate::media;
+use crate::metrics;
+use crate::jobs::{JobKind, JobReporter};
+use crate::project_lock;
+use crate::protocol::{error_codes, job_stages, Command, JobProgress, Response};
+use crate::quota;
+use crate::session::{self, AppState, ClientSession, RateLimiter, Session};
+use crate::tls::{self, HelperStream, TlsSettings};
+use crate::transcribe;
 use crate::utils;
+use crate::watch_folders;
+use crate::youtube;
+
+/// Sustained WebSocket commands per second allowed per connection
+const RATE_LIMIT_PER_SEC: f64 = 100.0;
+
+/// Burst of WebSocket commands allowed before the rate limit kicks in
+const RATE_LIMIT_BURST: f64 = 200.0;
+
+/// Failed auth attempts (or unauthenticated commands) before a connection is dropped
+const MAX_AUTH_FAILURES: u32 = 5;
+
+/// Longest `shutdown`/`restart` with `wait_for_jobs` waits before cancelling jobs
+const SHUTDOWN_JOB_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Time cancelled jobs and closing sessions get to report back on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// What to do once the server has stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitAction {
+    Exit,
+    Restart,
+}
 
 /// Server configuration
+#[derive(Clone)]
 pub struct ServerConfig {
+    /// Address both servers listen on (loopback unless `--bind`)
+    pub bind: IpAddr,
     pub port: u16,
+    /// Port pairs to try from `port` when it is taken (1 = only `port`)
+    pub port_attempts: u16,
+    /// Advertise the helper over mDNS
+    pub mdns: bool,
+    /// Serve `/metrics`
+    pub metrics: bool,
     pub allowed_origins: Vec<String>,
     pub auth_token: Option<String>,
+    /// Serve wss:// and https:// when set
+    pub tls: Option<TlsSettings>,
 }
 
-/// Run the WebSocket server and HTTP file server
-pub async fn run(config: ServerConfig) -> Result<()> {
-    let ws_addr = format!("127.0.0.1:{}", config.port);
-    let http_port = config.port + 1;
+/// Build the TLS acceptor shared by both servers, if TLS is configured
+fn build_tls_acceptor(config: &ServerConfig) -> Result<Option<TlsAcceptor>> {
+    match &config.tls {
+        Some(settings) => Ok(Some(tls::load_acceptor(settings)?)),
+        None => Ok(None),
+    }
+}
 
-    let listener = TcpListener::bind(&ws_addr).await?;
-    info!("WebSocket server listening on ws://{}", ws_addr);
+/// Run the WebSocket server and HTTP file server
+pub async fn run(config: ServerConfig) -> Result<ExitAction> {
+    let tls_acceptor = build_tls_acceptor(&config)?;
+    let (listener, http_listener, ports) =
+        discovery::bind(config.bind, config.port, config.port_attempts).await?;
+    info!(
+        "WebSocket server listening on {}://{}",
+        if tls_acceptor.is_some() { "wss" } else { "ws" },
+        SocketAddr::new(config.bind, ports.ws_port)
+    );
+    let _mdns = config
+        .mdns
+        .then(|| discovery::advertise(ports, !config.bind.is_loopback(), config.tls.is_some()))
+        .flatten();
 
     let state = Arc::new(AppState::new(config.auth_token.clone()));
+    watch_folders::start(state.clone());
+    project_lock::start(state.clone());
+    download::start_auto_update();
+    quota::enforce_in_background();
     let allowed_origins = Arc::new(config.allowed_origins.clone());
 
     let http_state = state.clone();
     let http_origins = allowed_origins.clone();
+    let http_tls = tls_acceptor.clone().zip(config.tls.clone());
+    let metrics = config.metrics;
+    let bind = config.bind;
     tokio::spawn(async move {
-        run_http_server(http_port, http_state, http_origins).await;
+        run_http_server(http_listener, bind, ports, http_state, http_origins, http_tls, metrics)
+            .await;
     });
 
-    while let Ok((stream, addr)) = listener.accept().await {
+    tokio::spawn(watch_signals(state.clone()));
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            result = listener.accept() => match result {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                    break;
+                }
+            },
+            _ = state.shutdown.cancelled() => break,
+        };
         let state = state.clone();
         let allowed_origins = allowed_origins.clone();
+        let tls_acceptor = tls_acceptor.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, addr, state, allowed_origins).await {
+            if let Err(e) =
+                handle_connection(stream, addr, state, allowed_origins, tls_acceptor).await
+            {
                 error!("Connection error from {}: {}", addr, e);
             }
         });
     }
 
-    Ok(())
+    Ok(finish_shutdown(&state).await)
 }
 
 /// Run the server with graceful shutdown support (Windows tray mode).
@@ -65,22 +149,38 @@ pub async fn run(config: ServerConfig) -> Result<()> {
 pub async fn run_with_shutdown(
     config: ServerConfig,
     tray_state: Arc<crate::tray::TrayState>,
-) -> Result<()> {
-    let ws_addr = format!("127.0.0.1:{}", config.port);
-    let http_port = config.port + 1;
-
-    let listener = TcpListener::bind(&ws_addr).await?;
-    info!("WebSocket server listening on ws://{}", ws_addr);
+) -> Result<ExitAction> {
+    let tls_acceptor = build_tls_acceptor(&config)?;
+    let (listener, http_listener, ports) =
+        discovery::bind(config.bind, config.port, config.port_attempts).await?;
+    info!(
+        "WebSocket server listening on {}://{}",
+        if tls_acceptor.is_some() { "wss" } else { "ws" },
+        SocketAddr::new(config.bind, ports.ws_port)
+    );
+    let _mdns = config
+        .mdns
+        .then(|| discovery::advertise(ports, !config.bind.is_loopback(), config.tls.is_some()))
+        .flatten();
 
     let state = Arc::new(AppState::new(config.auth_token.clone()));
+    watch_folders::start(state.clone());
+    project_lock::start(state.clone());
+    download::start_auto_update();
+    quota::enforce_in_background();
     let allowed_origins = Arc::new(config.allowed_origins.clone());
 
     tray_state.running.store(true, Ordering::Relaxed);
+    tokio::spawn(watch_signals(state.clone()));
 
     let http_state = state.clone();
     let http_origins = allowed_origins.clone();
+    let http_tls = tls_acceptor.clone().zip(config.tls.clone());
+    let metrics = config.metrics;
+    let bind = config.bind;
     tokio::spawn(async move {
-        run_http_server(http_port, http_state, http_origins).await;
+        run_http_server(http_listener, bind, ports, http_state, http_origins, http_tls, metrics)
+            .await;
     });
 
     loop {
@@ -90,12 +190,13 @@ pub async fn run_with_shutdown(
                     Ok((stream, addr)) => {
                         let state = state.clone();
                         let allowed_origins = allowed_origins.clone();
+                        let tls_acceptor = tls_acceptor.clone();
                         let ts = tray_state.clone();
 
                         ts.connection_count.fetch_add(1, Ordering::Relaxed);
 
                         tokio::spawn(async move {
-                            if let Err(e) = handle_connection(stream, addr, state, allowed_origins).await {
+                            if let Err(e) = handle_connection(stream, addr, state, allowed_origins, tls_acceptor).await {
                                 error!("Connection error from {}: {}", addr, e);
                             }
                             ts.connection_count.fetch_sub(1, Ordering::Relaxed);
@@ -108,12 +209,18 @@ pub async fn run_with_shutdown(
             }
             _ = wait_for_quit(&tray_state) => {
                 info!("Shutdown requested, stopping server...");
+                shut_down(state.clone(), false, false).await;
+                break;
+            }
+            _ = state.shutdown.cancelled() => {
+                // `shutdown`/`restart` command: close the tray as well
+                tray_state.quit_requested.store(true, Ordering::Relaxed);
                 break;
             }
         }
     }
 
-    Ok(())
+    Ok(finish_shutdown(&state).await)
 }
 
 #[cfg(windows)]
@@ -126,6 +233,110 @@ async fn wait_for_quit(tray_state: &Arc<crate::tray::TrayState>) {
     }
 }
 
+/// Poll `done` until it holds or `timeout` passes.
+async fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while !done() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Stop the helper: let running jobs finish (`wait_for_jobs`) or cancel
+/// them, stop the MatAnyone2 server and save pending state, then close all
+/// sessions. Only the first call does anything.
+pub async fn shut_down(state: Arc<AppState>, restart: bool, wait_for_jobs: bool) {
+    if !state.begin_shutdown(restart) {
+        return;
+    }
+    info!(
+        "{} ({} running jobs)",
+        if restart { "Restarting helper" } else { "Shutting down helper" },
+        state.jobs.count()
+    );
+
+    if wait_for_jobs {
+        wait_until(SHUTDOWN_JOB_TIMEOUT, || state.jobs.count() == 0).await;
+    }
+    if state.jobs.cancel_all() > 0 {
+        wait_until(SHUTDOWN_GRACE, || state.jobs.count() == 0).await;
+    }
+
+    if let Err(e) = state.matanyone_process.lock().await.stop().await {
+        warn!("Failed to stop MatAnyone2 server: {}", e);
+    }
+    quota::flush();
+    state.project_locks.release_all();
+    state.shutdown.cancel();
+}
+
+/// Give sessions time to send their close frames once the accept loop has ended.
+async fn finish_shutdown(state: &AppState) -> ExitAction {
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE;
+    while state.session_count().await > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    if state.restart_requested() {
+        ExitAction::Restart
+    } else {
+        ExitAction::Exit
+    }
+}
+
+/// Shut down cleanly on CTRL+C or SIGTERM.
+async fn watch_signals(state: Arc<AppState>) {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Cannot listen for CTRL+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c => info!("CTRL+C received"),
+        _ = terminate => info!("SIGTERM received"),
+    }
+    shut_down(state, false, false).await;
+}
+
+/// `shutdown` / `restart`: acknowledge, then shut down in the background so
+/// this session still receives job results and the close frame.
+async fn request_shutdown(
+    state: &Arc<AppState>,
+    write: &download::WsSender,
+    id: &str,
+    restart: bool,
+    wait_for_jobs: bool,
+) -> Result<()> {
+    let response = Response::ok(
+        id,
+        serde_json::json!({
+            "restart": restart,
+            "wait_for_jobs": wait_for_jobs,
+            "running_jobs": state.jobs.count(),
+        }),
+    );
+    let json = serde_json::to_string(&response)?;
+    write.lock().await.send(Message::Text(json)).await?;
+    tokio::spawn(shut_down(state.clone(), restart, wait_for_jobs));
+    Ok(())
+}
+
 #[derive(Debug, Deserialize)]
 struct AiToolHttpRequest {
     tool: String,
@@ -150,7 +361,7 @@ fn check_http_auth(auth_header: Option<String>, expected_token: &Option<String>)
         None => true, // No auth required
         Some(expected) => match auth_header {
             Some(header) => extract_bearer_token(&header)
-                .map(|t| t == expected)
+                .map(|t| session::tokens_match(t, expected))
                 .unwrap_or(false),
             None => false,
         },
@@ -163,7 +374,35 @@ fn is_cloudflare_pages_origin(origin: &str) -> bool {
         || (origin.starts_with("https://") && origin.ends_with(".masterselects.pages.dev"))
 }
 
-async fn run_http_server(port: u16, state: Arc<AppState>, allowed_origins: Arc<Vec<String>>) {
+/// Check a browser Origin against the pinned allow-list.
+///
+/// Only exact matches from `--allowed-origins` (or the built-in defaults) and
+/// the project's own Cloudflare Pages previews are accepted. Arbitrary
+/// `localhost` ports are rejected so other local web apps can't drive the helper.
+fn is_origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    let origin = origin.trim_end_matches('/');
+    is_cloudflare_pages_origin(origin) || allowed_origins.iter().any(|o| o == origin)
+}
+
+/// Extract the `token` query parameter from a WebSocket upgrade URI
+fn extract_query_token(uri: &http::Uri) -> Option<String> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "token")
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+async fn run_http_server(
+    listener: TcpListener,
+    bind: IpAddr,
+    ports: BoundPorts,
+    state: Arc<AppState>,
+    allowed_origins: Arc<Vec<String>>,
+    tls: Option<(TlsAcceptor, TlsSettings)>,
+    metrics: bool,
+) {
     // CORS setup: static origins from config + Cloudflare Pages production domain.
     // For preview deployments (*.masterselects.pages.dev), use --allowed-origins CLI flag.
     // WebSocket handler has dynamic pattern matching for CF Pages subdomains.
@@ -178,7 +417,8 @@ async fn run_http_server(port: u16, state: Arc<AppState>, allowed_origins: Arc<V
                 .collect::<Vec<&str>>(),
         )
         .allow_methods(vec!["GET", "POST", "OPTIONS"])
-        .allow_headers(vec!["Content-Type", "Authorization"]);
+        .allow_headers(vec!["Content-Type", "Authorization", "Range"])
+        .expose_headers(vec!["Accept-Ranges", "Content-Range", "Content-Length"]);
 
     // Auth filter: extracts Authorization header and validates against state token
     let state_for_auth = state.clone();
@@ -195,13 +435,14 @@ async fn run_http_server(port: u16, state: Arc<AppState>, allowed_origins: Arc<V
         )
         .untuple_one();
 
-    // GET /file?path=... — serve a file (AUTH REQUIRED)
+    // GET /file?path=... — serve a file, honoring Range requests (AUTH REQUIRED)
     let state_for_file = state.clone();
     let require_auth_file = require_auth.clone();
     let file_route = warp::path("file")
         .and(warp::get())
         .and(require_auth_file)
         .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(warp::header::optional::<String>("range"))
         .and(with_state(state_for_file))
         .and_then(serve_file);
 
@@ -221,6 +462,20 @@ async fn run_http_server(port: u16, state: Arc<AppState>, allowed_origins: Arc<V
         .and(warp::get())
         .and_then(get_project_root);
 
+    // GET /healthz — version, ports and capabilities for discovery (NO AUTH - safe metadata)
+    let health = discovery::health(ports, bind, tls.is_some(), state.auth_token.is_some());
+    let healthz_route = warp::path("healthz")
+        .and(warp::get())
+        .map(move || warp::reply::json(&health));
+
+    // GET /metrics — Prometheus text format, only with --metrics (NO AUTH - counters only)
+    let state_for_metrics = state.clone();
+    let metrics_route = warp::path("metrics")
+        .and(warp::get())
+        .and(warp::any().map(move || metrics))
+        .and(with_state(state_for_metrics))
+        .and_then(get_metrics);
+
     let state_for_status = state.clone();
     let state_for_api_status = state.clone();
     let state_for_post = state.clone();
@@ -253,25 +508,64 @@ async fn run_http_server(port: u16, state: Arc<AppState>, allowed_origins: Arc<V
         .and(with_state(state_for_api_post))
         .and_then(handle_ai_tools_request);
 
-    // GET /startup-token — returns the auth token for local discovery (localhost only, no auth)
+    // GET /startup-token — returns the auth token for local discovery (loopback bind only, no auth)
     let startup_token_route = warp::path("startup-token")
         .and(warp::get())
+        .and(warp::any().map(move || bind.is_loopback()))
         .and(with_state(state_for_startup_token))
         .and_then(get_startup_token);
 
+    // GET /tls-cert — download the helper's certificate so users can trust it (NO AUTH - public cert)
+    let tls_cert_path = tls.as_ref().map(|(_, settings)| settings.cert_path.clone());
+    let tls_cert_route = warp::path("tls-cert")
+        .and(warp::get())
+        .and(warp::any().map(move || tls_cert_path.clone()))
+        .and_then(get_tls_cert);
+
     let routes = file_route
         .or(upload_route)
         .or(project_root_route)
+        .or(healthz_route)
+        .or(metrics_route)
         .or(ai_tools_status_route)
         .or(api_ai_tools_status_route)
         .or(ai_tools_route)
         .or(api_ai_tools_route)
         .or(startup_token_route)
+        .or(tls_cert_route)
         .recover(handle_rejection)
         .with(cors);
 
-    info!("HTTP file server listening on http://127.0.0.1:{}", port);
-    warp::serve(routes).run(([127, 0, 0, 1], port)).await;
+    let port = ports.http_port;
+    match tls {
+        Some((acceptor, _)) => {
+            info!("HTTPS file server listening on https://{}", SocketAddr::new(bind, port));
+            warp::serve(routes)
+                .run_incoming(tls::incoming(listener, acceptor))
+                .await;
+        }
+        None => {
+            info!("HTTP file server listening on http://{}", SocketAddr::new(bind, port));
+            let incoming = futures_util::stream::unfold(listener, |listener| async move {
+                let conn = listener.accept().await.map(|(stream, _)| stream);
+                Some((conn, listener))
+            });
+            warp::serve(routes).run_incoming(incoming).await;
+        }
+    }
+}
+
+/// GET /tls-cert — return the PEM certificate in use (404 when TLS is off)
+async fn get_tls_cert(cert_path: Option<PathBuf>) -> Result<impl warp::Reply, warp::Rejection> {
+    let cert_path = cert_path.ok_or_else(warp::reject::not_found)?;
+    match tokio::fs::read(&cert_path).await {
+        Ok(pem) => Ok(warp::reply::with_header(
+            warp::reply::with_header(pem, "Content-Type", "application/x-pem-file"),
+            "Content-Disposition",
+            "attachment; filename=\"masterselects-helper.pem\"",
+        )),
+        Err(_) => Err(warp::reject::not_found()),
+    }
 }
 
 /// Custom rejection for auth failures
@@ -302,19 +596,34 @@ async fn handle_rejection(
     }
 }
 
-/// GET /startup-token — returns the auth token for localhost clients to discover
-async fn get_startup_token(state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
-    match &state.auth_token {
-        Some(token) => Ok(warp::reply::json(&serde_json::json!({
+/// GET /startup-token — returns the auth token for localhost clients to discover.
+/// Refused when the helper listens beyond loopback, where anyone on the
+/// network could read it; remote editors are given the token by hand.
+async fn get_startup_token(
+    loopback: bool,
+    state: Arc<AppState>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let body = match &state.auth_token {
+        _ if !loopback => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "ok": false,
+                    "error": "The token is not served when the helper listens on the network",
+                })),
+                warp::http::StatusCode::FORBIDDEN,
+            ))
+        }
+        Some(token) => serde_json::json!({
             "ok": true,
             "token": token,
-        }))),
-        None => Ok(warp::reply::json(&serde_json::json!({
+        }),
+        None => serde_json::json!({
             "ok": true,
             "token": null,
             "auth_disabled": true,
-        }))),
-    }
+        }),
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::OK))
 }
 
 async fn get_ai_tools_status(state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
@@ -430,10 +739,51 @@ fn guess_content_type(path: &std::path::Path) -> &'static str {
     }
 }
 
+/// Parse a single `Range: bytes=...` header against a file length.
+///
+/// Returns `None` when the header should be ignored (malformed or multi-range,
+/// in which case the whole file is served) and `Some(Err(()))` when the range
+/// is unsatisfiable.
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = if start.is_empty() {
+        // Suffix range: last N bytes
+        let suffix: u64 = end.parse().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        (len.saturating_sub(suffix), len - 1)
+    } else {
+        let start: u64 = start.parse().ok()?;
+        let end: u64 = if end.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            end.parse::<u64>().ok()?.min(len.saturating_sub(1))
+        };
+        if start >= len || start > end {
+            return Some(Err(()));
+        }
+        (start, end)
+    };
+    Some(Ok(range))
+}
+
 async fn serve_file(
     params: std::collections::HashMap<String, String>,
+    range: Option<String>,
     state: Arc<AppState>,
-) -> Result<impl warp::Reply, warp::Rejection> {
+) -> Result<warp::reply::Response, warp::Rejection> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    use tokio_util::io::ReaderStream;
+    use warp::http::{header, Response as HttpResponse, StatusCode};
+    use warp::hyper::Body;
+
     let path = params.get("path").ok_or_else(warp::reject::not_found)?;
     let path = PathBuf::from(path);
 
@@ -449,19 +799,58 @@ async fn serve_file(
     if !path.exists() {
         return Err(warp::reject::not_found());
     }
+    quota::record_use(&path);
 
     let content_type = guess_content_type(&path);
-
-    match tokio::fs::read(&path).await {
-        Ok(data) => {
-            info!(
-                "HTTP: Serving file: {} ({} bytes)",
-                path.display(),
-                data.len()
+    let len = tokio::fs::metadata(&path)
+        .await
+        .map_err(|_| warp::reject::not_found())?
+        .len();
+
+    // Bodies are streamed from disk: media elements open with `bytes=0-`,
+    // which would otherwise buffer the whole file in memory.
+    match range.as_deref().and_then(|r| parse_range(r, len)) {
+        Some(Ok((start, end))) => {
+            let mut file = tokio::fs::File::open(&path)
+                .await
+                .map_err(|_| warp::reject::not_found())?;
+            file.seek(std::io::SeekFrom::Start(start))
+                .await
+                .map_err(|_| warp::reject::not_found())?;
+
+            debug!(
+                "HTTP: Serving range {}-{}/{} of {}",
+                start,
+                end,
+                len,
+                path.display()
             );
-            Ok(warp::reply::with_header(data, "Content-Type", content_type))
+            Ok(HttpResponse::builder()
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::ACCEPT_RANGES, "bytes")
+                .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len))
+                .header(header::CONTENT_LENGTH, end - start + 1)
+                .body(Body::wrap_stream(ReaderStream::new(file.take(end - start + 1))))
+                .unwrap())
         }
-        Err(_) => Err(warp::reject::not_found()),
+        Some(Err(())) => Ok(HttpResponse::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Body::empty())
+            .unwrap()),
+        None => match tokio::fs::File::open(&path).await {
+            Ok(file) => {
+                info!("HTTP: Serving file: {} ({} bytes)", path.display(), len);
+                Ok(HttpResponse::builder()
+                    .header(header::CONTENT_TYPE, content_type)
+                    .header(header::ACCEPT_RANGES, "bytes")
+                    .header(header::CONTENT_LENGTH, len)
+                    .body(Body::wrap_stream(ReaderStream::new(file)))
+                    .unwrap())
+            }
+            Err(_) => Err(warp::reject::not_found()),
+        },
     }
 }
 
@@ -526,6 +915,22 @@ async fn handle_upload(
     }
 }
 
+/// GET /metrics — Prometheus metrics (404 unless enabled)
+async fn get_metrics(enabled: bool, state: Arc<AppState>) -> Result<impl warp::Reply, warp::Rejection> {
+    if !enabled {
+        return Err(warp::reject::not_found());
+    }
+    let gauges = metrics::Gauges {
+        sessions: state.session_count().await,
+        running_jobs: state.jobs.count(),
+    };
+    Ok(warp::reply::with_header(
+        metrics::render(&gauges),
+        "Content-Type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
 /// GET /project-root — return the default project root path
 async fn get_project_root() -> Result<impl warp::Reply, warp::Rejection> {
     let root = utils::get_project_root();
@@ -540,20 +945,22 @@ async fn handle_connection(
     addr: SocketAddr,
     state: Arc<AppState>,
     allowed_origins: Arc<Vec<String>>,
+    tls_acceptor: Option<TlsAcceptor>,
 ) -> Result<()> {
     info!("New connection from {}", addr);
 
+    let stream = match tls_acceptor {
+        Some(acceptor) => HelperStream::Tls(Box::new(tls::accept(&acceptor, stream).await?)),
+        None => HelperStream::Plain(stream),
+    };
+
+    let mut query_token: Option<String> = None;
     let ws =
         tokio_tungstenite::accept_hdr_async(stream, |request: &http::Request<()>, response| {
+            query_token = extract_query_token(request.uri());
             if let Some(origin) = request.headers().get("Origin") {
                 let origin_str = origin.to_str().unwrap_or("");
-                let allowed = origin_str.starts_with("http://localhost")
-                    || origin_str.starts_with("http://127.0.0.1")
-                    || origin_str.starts_with("https://localhost")
-                    || origin_str.starts_with("https://127.0.0.1")
-                    || is_cloudflare_pages_origin(origin_str)
-                    || allowed_origins.iter().any(|o| o == origin_str);
-                if !allowed {
+                if !is_origin_allowed(origin_str, &allowed_origins) {
                     warn!(
                         "Rejected WebSocket connection from disallowed origin: {}",
                         origin_str
@@ -570,7 +977,111 @@ async fn handle_connection(
         })
         .await?;
 
-    handle_websocket(ws, addr, state).await
+    handle_websocket(ws, addr, state, query_token).await
+}
+
+/// Cancel a matte job in the MatAnyone2 sidecar, then stop the sidecar so GPU
+/// work, worker threads, and any subprocesses are stopped.
+/// Returns whether a running server was stopped.
+async fn hard_cancel_matte(state: &AppState, job_id: &str) -> Result<bool, String> {
+    let port = {
+        let proc = state.matanyone_process.lock().await;
+        proc.port()
+    };
+    if port != 0 && !job_id.is_empty() {
+        let _ = tokio::time::timeout(
+            Duration::from_millis(750),
+            crate::matanyone::inference::cancel_job(port, job_id),
+        )
+        .await;
+    }
+
+    let mut proc = state.matanyone_process.lock().await;
+    if proc.port() == 0 {
+        Ok(false)
+    } else {
+        proc.stop().await.map(|_| true)
+    }
+}
+
+/// Send one response on a session's socket.
+async fn send_response(write: &download::WsSender, response: &Response) -> Result<()> {
+    let json = serde_json::to_string(response)?;
+    write.lock().await.send(Message::Text(json)).await?;
+    Ok(())
+}
+
+/// Run `job` as a job owned by `session_id`: register it, report that it
+/// started, and send its final response once it returns. The reporter and
+/// cancellation token passed to `job` belong to the new job.
+async fn spawn_job<F, Fut>(
+    state: &Arc<AppState>,
+    kind: JobKind,
+    session_id: &str,
+    id: &str,
+    write: &download::WsSender,
+    job: F,
+) where
+    F: FnOnce(JobReporter, CancellationToken) -> Fut,
+    Fut: std::future::Future<Output = Response> + Send + 'static,
+{
+    let (job_id, cancel) = state.jobs.start(kind, session_id);
+    let reporter = JobReporter::new(id, &job_id, Some(write.clone()));
+    reporter
+        .progress(&JobProgress::stage(job_stages::STARTED, 0.0))
+        .await;
+
+    let response = job(reporter.clone(), cancel);
+    let state = state.clone();
+    tokio::spawn(async move {
+        let response = response.await;
+        state.jobs.finish(&reporter.job_id);
+        reporter.send(&response).await;
+    });
+}
+
+/// Resolve a media path argument, rejecting paths outside the allowed roots.
+fn check_media_path(state: &AppState, id: &str, path: &str) -> Result<PathBuf, Response> {
+    let path = PathBuf::from(path);
+    if !path.is_absolute() || !state.is_path_allowed(&path) {
+        return Err(Response::error(
+            id,
+            error_codes::PERMISSION_DENIED,
+            "Path is outside the allowed directories",
+        ));
+    }
+    if !path.is_file() {
+        return Err(Response::error(
+            id,
+            error_codes::FILE_NOT_FOUND,
+            format!("File not found: {}", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+/// Check an optional output file against the allowed directories.
+fn check_output_path(state: &AppState, id: &str, output: Option<String>) -> Result<Option<PathBuf>, Response> {
+    match output.map(PathBuf::from) {
+        Some(out) if !out.is_absolute() || !state.is_path_allowed(&out) => Err(Response::error(
+            id,
+            error_codes::PERMISSION_DENIED,
+            "Output is outside the allowed directories",
+        )),
+        output => Ok(output),
+    }
+}
+
+/// Validate download cookie options; a cookies file must be readable by
+/// the helper like any other file it is handed.
+fn check_cookies(state: &AppState, id: &str, cookies: &download::CookieOptions) -> Result<(), Response> {
+    cookies
+        .validate()
+        .map_err(|message| Response::error(id, error_codes::INVALID_ARGUMENT, message))?;
+    if let Some(file) = &cookies.cookies_file {
+        check_media_path(state, id, file)?;
+    }
+    Ok(())
 }
 
 /// Extract the `id` field from any Command variant for error responses
@@ -581,9 +1092,15 @@ fn get_command_id(cmd: &Command) -> &str {
         | Command::Ping { id }
         | Command::RegisterClient { id, .. }
         | Command::AiToolResult { id, .. }
+        | Command::ListSessions { id }
+        | Command::CancelJob { id, .. }
         | Command::DownloadYoutube { id, .. }
         | Command::Download { id, .. }
+        | Command::DownloadSection { id, .. }
         | Command::ListFormats { id, .. }
+        | Command::ListBrowsers { id }
+        | Command::YtdlpStatus { id, .. }
+        | Command::YtdlpUpdate { id, .. }
         | Command::GetFile { id, .. }
         | Command::Locate { id, .. }
         | Command::WriteFile { id, .. }
@@ -592,6 +1109,9 @@ fn get_command_id(cmd: &Command) -> &str {
         | Command::Delete { id, .. }
         | Command::Exists { id, .. }
         | Command::Rename { id, .. }
+        | Command::LockProject { id, .. }
+        | Command::UnlockProject { id, .. }
+        | Command::SyncProject { id, .. }
         | Command::GrantPath { id, .. }
         | Command::PickFolder { id, .. }
         | Command::MatAnyoneStatus { id }
@@ -601,25 +1121,94 @@ fn get_command_id(cmd: &Command) -> &str {
         | Command::MatAnyoneStop { id }
         | Command::MatAnyoneMatte { id, .. }
         | Command::MatAnyoneCancel { id, .. }
-        | Command::MatAnyoneUninstall { id } => id,
+        | Command::MatAnyoneUninstall { id }
+        | Command::Transcribe { id, .. }
+        | Command::ExtractFrame { id, .. }
+        | Command::RenderContactSheet { id, .. }
+        | Command::ListEncoders { id, .. }
+        | Command::ListGpus { id }
+        | Command::Capabilities { id }
+        | Command::ProbeFrameTiming { id, .. }
+        | Command::ConformCfr { id, .. }
+        | Command::Transcode { id, .. }
+        | Command::ExtractAudio { id, .. }
+        | Command::DenoiseAudio { id, .. }
+        | Command::SyncAudio { id, .. }
+        | Command::AutoDuck { id, .. }
+        | Command::AddWatchFolder { id, .. }
+        | Command::RemoveWatchFolder { id, .. }
+        | Command::ListWatchFolders { id }
+        | Command::ScanMediaFolder { id, .. }
+        | Command::YoutubeStatus { id }
+        | Command::YoutubeLogin { id }
+        | Command::YoutubeLogout { id }
+        | Command::YoutubeUpload { id, .. }
+        | Command::Cleanup { id, .. }
+        | Command::ExportDiagnostics { id, .. }
+        | Command::GetSettings { id }
+        | Command::SetSettings { id, .. }
+        | Command::UpdateCheck { id }
+        | Command::UpdateInstall { id }
+        | Command::Shutdown { id, .. }
+        | Command::Restart { id, .. } => id,
     }
 }
 
 async fn handle_websocket(
-    ws: WebSocketStream<TcpStream>,
+    ws: WebSocketStream<HelperStream>,
     addr: SocketAddr,
     state: Arc<AppState>,
+    query_token: Option<String>,
 ) -> Result<()> {
     let (write, mut read) = ws.split();
     let write = Arc::new(tokio::sync::Mutex::new(write));
     let session_id = uuid::Uuid::new_v4().to_string();
-    let mut session = Session::new(state.clone());
+    let mut session = Session::new(state.clone(), session_id.clone());
+    let mut rate_limiter = RateLimiter::new(RATE_LIMIT_BURST, RATE_LIMIT_PER_SEC);
+    let mut auth_failures: u32 = 0;
 
     // Track authentication state for this connection.
     // If no auth token is configured, all connections are pre-authenticated.
-    let mut authenticated = state.auth_token.is_none();
+    // Otherwise the token may arrive as `?token=` on the upgrade URL or via an `auth` command.
+    let mut authenticated = match (&state.auth_token, &query_token) {
+        (None, _) => true,
+        (Some(expected), Some(provided)) => session::tokens_match(provided, expected),
+        (Some(_), None) => false,
+    };
+    state
+        .register_session(ClientSession::new(session_id.clone(), addr, write.clone()))
+        .await;
+    if authenticated {
+        state.update_session(&session_id, |s| s.authenticated = true).await;
+    }
+    if authenticated && query_token.is_some() {
+        session.set_authenticated(true);
+        info!("Client {} authenticated via query token", addr);
+    } else if query_token.is_some() {
+        warn!("Invalid query token from {}", addr);
+        auth_failures += 1;
+    }
 
-    while let Some(msg) = read.next().await {
+    loop {
+        let msg = tokio::select! {
+            msg = read.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            _ = state.shutdown.cancelled() => {
+                let reason = if state.restart_requested() {
+                    "Helper restarting"
+                } else {
+                    "Helper shutting down"
+                };
+                let frame = CloseFrame {
+                    code: CloseCode::Away,
+                    reason: reason.into(),
+                };
+                let _ = write.lock().await.send(Message::Close(Some(frame))).await;
+                break;
+            }
+        };
         let msg = match msg {
             Ok(m) => m,
             Err(e) => {
@@ -630,13 +1219,22 @@ async fn handle_websocket(
 
         match msg {
             Message::Text(text) => {
+                if !rate_limiter.try_acquire() {
+                    warn!("Rate limit exceeded for {}", addr);
+                    let response = Response::error(
+                        "",
+                        error_codes::RATE_LIMITED,
+                        "Too many requests. Slow down and retry.",
+                    );
+                    send_response(&write, &response).await?;
+                    continue;
+                }
+
                 let cmd: Command = match serde_json::from_str(&text) {
                     Ok(c) => c,
                     Err(e) => {
                         let response = Response::error("", "PARSE_ERROR", e.to_string());
-                        let json = serde_json::to_string(&response)?;
-                        let mut w = write.lock().await;
-                        w.send(Message::Text(json)).await?;
+                        send_response(&write, &response).await?;
                         continue;
                     }
                 };
@@ -648,14 +1246,16 @@ async fn handle_websocket(
                 match &cmd {
                     Command::Auth { id, token } => {
                         let response = match &state.auth_token {
-                            Some(expected) if expected == token => {
+                            Some(expected) if session::tokens_match(token, expected) => {
                                 authenticated = true;
                                 session.set_authenticated(true);
+                                state.update_session(&session_id, |s| s.authenticated = true).await;
                                 info!("Client {} authenticated via WebSocket", addr);
                                 Response::ok(id, serde_json::json!({"authenticated": true}))
                             }
                             Some(_) => {
                                 warn!("Invalid auth token from {}", addr);
+                                auth_failures += 1;
                                 Response::error(id, error_codes::INVALID_TOKEN, "Invalid token")
                             }
                             None => {
@@ -667,6 +1267,10 @@ async fn handle_websocket(
                         let json = serde_json::to_string(&response)?;
                         let mut w = write.lock().await;
                         w.send(Message::Text(json)).await?;
+                        if auth_failures >= MAX_AUTH_FAILURES {
+                            warn!("Closing connection from {} after repeated auth failures", addr);
+                            break;
+                        }
                         continue;
                     }
                     Command::Ping { .. } => {
@@ -684,15 +1288,51 @@ async fn handle_websocket(
                             let json = serde_json::to_string(&response)?;
                             let mut w = write.lock().await;
                             w.send(Message::Text(json)).await?;
+                            auth_failures += 1;
+                            if auth_failures >= MAX_AUTH_FAILURES {
+                                warn!(
+                                    "Closing unauthenticated connection from {} after repeated commands",
+                                    addr
+                                );
+                                break;
+                            }
                             continue;
                         }
                     }
                 }
 
+                // ── Shutdown gate ──
+                // While the helper drains its jobs, only status and cancel commands are served.
+                if state.is_stopping()
+                    && !matches!(
+                        cmd,
+                        Command::Ping { .. }
+                            | Command::Info { .. }
+                            | Command::ListSessions { .. }
+                            | Command::CancelJob { .. }
+                    )
+                {
+                    let response = Response::error(
+                        get_command_id(&cmd),
+                        error_codes::SHUTTING_DOWN,
+                        "The helper is shutting down",
+                    );
+                    send_response(&write, &response).await?;
+                    continue;
+                }
+
                 match cmd {
                     // Auth is handled above in the auth gate
                     Command::Auth { .. } => unreachable!(),
 
+                    Command::Shutdown { id, wait_for_jobs } => {
+                        request_shutdown(&state, &write, &id, false, wait_for_jobs).await?;
+                    }
+
+                    Command::Restart { id, wait_for_jobs } => {
+                        request_shutdown(&state, &write, &id, true, wait_for_jobs).await?;
+                    }
+
                     Command::RegisterClient {
                         id,
                         role,
@@ -700,6 +1340,12 @@ async fn handle_websocket(
                         session_name,
                         app_version,
                     } => {
+                        state
+                            .update_session(&session_id, |s| {
+                                s.role = Some(role.clone());
+                                s.session_name = session_name.clone();
+                            })
+                            .await;
                         if role == "editor" {
                             state
                                 .register_editor_client(crate::session::EditorClient {
@@ -748,31 +1394,527 @@ async fn handle_websocket(
                         url,
                         format_id,
                         output_dir,
+                        extras,
+                        cookies,
                     }
                     | Command::Download {
                         id,
                         url,
                         format_id,
                         output_dir,
+                        extras,
+                        cookies,
                     } => {
-                        let response = download::handle_download(
+                        if let Err(response) = check_cookies(&state, &id, &cookies) {
+                            send_response(&write, &response).await?;
+                            continue;
+                        }
+
+                        // Run as a job so the connection keeps serving commands
+                        // (including `cancel_job`) while yt-dlp runs.
+                        spawn_job(
+                            &state,
+                            JobKind::Download,
+                            &session_id,
                             &id,
-                            &url,
-                            format_id.as_deref(),
-                            output_dir.as_deref(),
-                            Some(write.clone()),
+                            &write,
+                            move |reporter, cancel| async move {
+                                download::handle_download(
+                                    &url,
+                                    format_id.as_deref(),
+                                    output_dir.as_deref(),
+                                    None,
+                                    &extras,
+                                    &cookies,
+                                    &reporter,
+                                    &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::DownloadSection {
+                        id,
+                        url,
+                        start,
+                        end,
+                        format_id,
+                        output_dir,
+                        cookies,
+                    } => {
+                        if let Err(response) = check_cookies(&state, &id, &cookies) {
+                            send_response(&write, &response).await?;
+                            continue;
+                        }
+
+                        spawn_job(
+                            &state,
+                            JobKind::Download,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                download::handle_download(
+                                    &url,
+                                    format_id.as_deref(),
+                                    output_dir.as_deref(),
+                                    Some(download::Section { start, end }),
+                                    &download::DownloadExtras::default(),
+                                    &cookies,
+                                    &reporter,
+                                    &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::Transcribe {
+                        id,
+                        path,
+                        language,
+                        model,
+                    } => {
+                        let path = match check_media_path(&state, &id, &path) {
+                            Ok(path) => path,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::Transcribe,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                transcribe::handle_transcribe(
+                                    &path,
+                                    language.as_deref(),
+                                    model.as_deref(),
+                                    &reporter,
+                                    &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::YoutubeLogin { id } => {
+                        spawn_job(
+                            &state,
+                            JobKind::YoutubeLogin,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                youtube::handle_login(&reporter, &cancel).await
+                            },
                         )
                         .await;
-                        let json = serde_json::to_string(&response)?;
-                        let mut w = write.lock().await;
-                        w.send(Message::Text(json)).await?;
                     }
-                    Command::ListFormats { id, url } => {
-                        let response = download::handle_list_formats(&id, &url).await;
+                    Command::YoutubeUpload {
+                        id,
+                        path,
+                        title,
+                        description,
+                        tags,
+                        privacy,
+                        chapters,
+                    } => {
+                        let path = match check_media_path(&state, &id, &path) {
+                            Ok(path) => path,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::Upload,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                youtube::handle_upload(
+                                    &path,
+                                    &title,
+                                    &description,
+                                    &tags,
+                                    privacy,
+                                    &chapters,
+                                    &reporter,
+                                    &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::SyncAudio {
+                        id,
+                        reference,
+                        clips,
+                        max_offset,
+                    } => {
+                        let paths: Result<Vec<PathBuf>, Response> = std::iter::once(&reference)
+                            .chain(&clips)
+                            .map(|path| check_media_path(&state, &id, path))
+                            .collect();
+                        let mut paths = match paths {
+                            Ok(paths) if paths.len() > 1 => paths,
+                            result => {
+                                let response = result.err().unwrap_or_else(|| {
+                                    Response::error(
+                                        &id,
+                                        error_codes::INVALID_ARGUMENT,
+                                        "No clips to synchronize",
+                                    )
+                                });
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+                        let reference = paths.remove(0);
+                        let max_offset = max_offset.unwrap_or(60.0).clamp(1.0, 600.0);
+
+                        spawn_job(
+                            &state,
+                            JobKind::AudioSync,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_sync_audio(
+                                    &reference,
+                                    &paths,
+                                    max_offset,
+                                    &reporter,
+                                    &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::AutoDuck {
+                        id,
+                        dialogue,
+                        duck_db,
+                        threshold_db,
+                        attack,
+                        release,
+                    } => {
+                        let dialogue = match check_media_path(&state, &id, &dialogue) {
+                            Ok(path) => path,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+                        let defaults = media::DuckOptions::default();
+                        let options = media::DuckOptions {
+                            duck_db: duck_db.unwrap_or(defaults.duck_db),
+                            threshold_db: threshold_db.unwrap_or(defaults.threshold_db),
+                            attack: attack.unwrap_or(defaults.attack),
+                            release: release.unwrap_or(defaults.release),
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::AutoDuck,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_auto_duck(&dialogue, options, &reporter, &cancel).await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::ProbeFrameTiming {
+                        id,
+                        path,
+                        include_pts,
+                    } => {
+                        let ws_sender = write.clone();
+                        let path = check_media_path(&state, &id, &path);
+                        tokio::spawn(async move {
+                            let response = match path {
+                                Ok(path) => {
+                                    media::handle_probe_frame_timing(&id, &path, include_pts).await
+                                }
+                                Err(response) => response,
+                            };
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::ConformCfr {
+                        id,
+                        path,
+                        fps,
+                        output,
+                    } => {
+                        let checked = check_media_path(&state, &id, &path).and_then(|path| {
+                            Ok((path, check_output_path(&state, &id, output)?))
+                        });
+                        let (path, output) = match checked {
+                            Ok(paths) => paths,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::Conform,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_conform_cfr(&path, fps, output, &reporter, &cancel)
+                                    .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::Transcode {
+                        id,
+                        input,
+                        output,
+                        codec,
+                        crf,
+                        bitrate_kbps,
+                        resolution,
+                    } => {
+                        let checked = check_media_path(&state, &id, &input).and_then(|path| {
+                            Ok((path, check_output_path(&state, &id, output)?))
+                        });
+                        let (path, output) = match checked {
+                            Ok(paths) => paths,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+                        let options = media::TranscodeOptions {
+                            codec,
+                            crf,
+                            bitrate_kbps,
+                            resolution,
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::Transcode,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_transcode(&path, output, options, &reporter, &cancel)
+                                    .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::ExtractAudio {
+                        id,
+                        path,
+                        format,
+                        output,
+                    } => {
+                        let checked = check_media_path(&state, &id, &path).and_then(|path| {
+                            Ok((path, check_output_path(&state, &id, output)?))
+                        });
+                        let (path, output) = match checked {
+                            Ok(paths) => paths,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::AudioExtract,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_extract_audio(&path, format, output, &reporter, &cancel)
+                                    .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::DenoiseAudio {
+                        id,
+                        path,
+                        strength,
+                        noise_start,
+                        noise_end,
+                        format,
+                        output,
+                    } => {
+                        let checked = check_media_path(&state, &id, &path).and_then(|path| {
+                            Ok((path, check_output_path(&state, &id, output)?))
+                        });
+                        let (path, output) = match checked {
+                            Ok(paths) => paths,
+                            Err(response) => {
+                                send_response(&write, &response).await?;
+                                continue;
+                            }
+                        };
+                        let options = media::DenoiseOptions {
+                            strength: strength.unwrap_or(media::DenoiseOptions::default().strength),
+                            noise_sample: noise_start.zip(noise_end),
+                        };
+
+                        spawn_job(
+                            &state,
+                            JobKind::AudioDenoise,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                media::handle_denoise_audio(
+                                    &path, options, format, output, &reporter, &cancel,
+                                )
+                                .await
+                            },
+                        )
+                        .await;
+                    }
+                    Command::ExtractFrame {
+                        id,
+                        path,
+                        time,
+                        format,
+                        width,
+                    } => {
+                        let ws_sender = write.clone();
+                        let path = check_media_path(&state, &id, &path);
+                        // Stills for another machine travel over the LAN
+                        let max_width =
+                            (!addr.ip().is_loopback()).then_some(media::REMOTE_FRAME_WIDTH);
+                        tokio::spawn(async move {
+                            let response = match path {
+                                Ok(path) => {
+                                    media::handle_extract_frame(
+                                        &id,
+                                        &path,
+                                        time,
+                                        format.as_deref(),
+                                        width,
+                                        max_width,
+                                    )
+                                    .await
+                                }
+                                Err(response) => response,
+                            };
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::RenderContactSheet {
+                        id,
+                        path,
+                        cols,
+                        rows,
+                        width,
+                        format,
+                    } => {
+                        let ws_sender = write.clone();
+                        let path = check_media_path(&state, &id, &path);
+                        tokio::spawn(async move {
+                            let response = match path {
+                                Ok(path) => {
+                                    media::handle_render_contact_sheet(
+                                        &id,
+                                        &path,
+                                        cols.unwrap_or(4),
+                                        rows.unwrap_or(4),
+                                        width.unwrap_or(240),
+                                        format.as_deref(),
+                                    )
+                                    .await
+                                }
+                                Err(response) => response,
+                            };
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::Capabilities { id } => {
+                        let ws_sender = write.clone();
+                        tokio::spawn(async move {
+                            let response = media::handle_capabilities(&id).await;
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::ListEncoders {
+                        id,
+                        preference,
+                        rate_control,
+                    } => {
+                        let ws_sender = write.clone();
+                        tokio::spawn(async move {
+                            let response =
+                                media::handle_list_encoders(&id, preference, rate_control).await;
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::ListFormats { id, url, cookies } => {
+                        let response = match check_cookies(&state, &id, &cookies) {
+                            Ok(()) => download::handle_list_formats(&id, &url, &cookies).await,
+                            Err(response) => response,
+                        };
                         let json = serde_json::to_string(&response)?;
                         let mut w = write.lock().await;
                         w.send(Message::Text(json)).await?;
                     }
+                    Command::YtdlpStatus { id, check_latest } => {
+                        let ws_sender = write.clone();
+                        tokio::spawn(async move {
+                            let response = download::handle_ytdlp_status(&id, check_latest).await;
+                            if let Ok(json) = serde_json::to_string(&response) {
+                                let mut w = ws_sender.lock().await;
+                                let _ = w.send(Message::Text(json)).await;
+                            }
+                        });
+                    }
+                    Command::YtdlpUpdate { id, include_deno } => {
+                        spawn_job(
+                            &state,
+                            JobKind::ToolInstall,
+                            &session_id,
+                            &id,
+                            &write,
+                            move |reporter, cancel| async move {
+                                download::handle_ytdlp_update(include_deno, &reporter, &cancel).await
+                            },
+                        )
+                        .await;
+                    }
 
                     // ── MatAnyone2 streaming commands ──
                     Command::MatAnyoneSetup { id, python_path: _ } => {
@@ -879,6 +2021,7 @@ async fn handle_websocket(
                     Command::MatAnyoneStart { id } => {
                         let ws_sender = write.clone();
                         let state_clone = state.clone();
+                        let session_id_clone = session_id.clone();
                         let id_clone = id.clone();
                         tokio::spawn(async move {
                             // Send starting progress
@@ -911,9 +2054,22 @@ async fn handle_websocket(
                                 }
                             };
 
-                            let mut proc = state_clone.matanyone_process.lock().await;
-                            let result =
-                                proc.start(&python_path, &server_script, &models_dir).await;
+                            let result = {
+                                let mut proc = state_clone.matanyone_process.lock().await;
+                                proc.start(&python_path, &server_script, &models_dir).await
+                            };
+
+                            if let Ok(port) = result {
+                                // The sidecar is shared; let the other tabs know it is up.
+                                let event = serde_json::json!({
+                                    "type": "matanyone_status",
+                                    "status": "running",
+                                    "port": port,
+                                });
+                                state_clone
+                                    .broadcast(&event.to_string(), Some(&session_id_clone))
+                                    .await;
+                            }
 
                             let response = match result {
                                 Ok(port) => Response::ok(
@@ -948,6 +2104,7 @@ async fn handle_websocket(
                     } => {
                         let ws_sender = write.clone();
                         let state_clone = state.clone();
+                        let session_id_clone = session_id.clone();
                         let id_clone = id.clone();
                         tokio::spawn(async move {
                             // Get the port from the running process
@@ -979,22 +2136,44 @@ async fn handle_websocket(
 
                             let ws = ws_sender.clone();
                             let id_ref = id_clone.clone();
+                            let owner_state = state_clone.clone();
+                            let cancel = CancellationToken::new();
+                            let job_cancel = cancel.clone();
+                            let matte_job_id = Arc::new(std::sync::Mutex::new(None::<String>));
+                            let matte_job_id_ref = matte_job_id.clone();
 
-                            let result = crate::matanyone::inference::run_matte_job(
+                            let job = crate::matanyone::inference::run_matte_job(
                                 port,
                                 request,
                                 move |progress| {
-                                    let response = Response::ok(
+                                    // The sidecar assigns the job ID; register the job
+                                    // for this session on the first progress update.
+                                    let mut known = matte_job_id_ref
+                                        .lock()
+                                        .unwrap_or_else(|e| e.into_inner());
+                                    if known.is_none() {
+                                        *known = Some(progress.job_id.clone());
+                                        owner_state.jobs.register(
+                                            &progress.job_id,
+                                            JobKind::Matte,
+                                            &session_id_clone,
+                                            job_cancel.clone(),
+                                        );
+                                    }
+                                    drop(known);
+
+                                    let mut response = Response::job_progress(
                                         &id_ref,
-                                        serde_json::json!({
-                                            "type": "progress",
-                                            "job_id": progress.job_id,
-                                            "status": progress.status,
-                                            "current_frame": progress.current_frame,
-                                            "total_frames": progress.total_frames,
-                                            "percent": progress.percent,
-                                        }),
+                                        &progress.job_id,
+                                        &JobProgress::stage(job_stages::PROCESSING, progress.percent),
                                     );
+                                    if let Response::Ok(ref mut ok) = response {
+                                        ok.data["status"] = serde_json::json!(progress.status);
+                                        ok.data["current_frame"] =
+                                            serde_json::json!(progress.current_frame);
+                                        ok.data["total_frames"] =
+                                            serde_json::json!(progress.total_frames);
+                                    }
                                     if let Ok(json) = serde_json::to_string(&response) {
                                         let ws_inner = ws.clone();
                                         tokio::spawn(async move {
@@ -1003,24 +2182,46 @@ async fn handle_websocket(
                                         });
                                     }
                                 },
-                            )
-                            .await;
+                            );
+
+                            let result = tokio::select! {
+                                result = job => Some(result),
+                                _ = cancel.cancelled() => None,
+                            };
+
+                            let job_id = matte_job_id
+                                .lock()
+                                .unwrap_or_else(|e| e.into_inner())
+                                .take();
+                            if let Some(ref job_id) = job_id {
+                                state_clone.jobs.finish(job_id);
+                            }
+                            let job_id = job_id.unwrap_or_default();
 
                             let response = match result {
-                                Ok(matte_result) => Response::ok(
+                                Some(Ok(matte_result)) => Response::job_complete(
                                     &id_clone,
+                                    &matte_result.job_id,
                                     serde_json::json!({
-                                        "type": "complete",
-                                        "job_id": matte_result.job_id,
                                         "foreground_path": matte_result.foreground_path,
                                         "alpha_path": matte_result.alpha_path,
                                     }),
                                 ),
-                                Err(e) => Response::error(
+                                Some(Err(e)) => Response::error(
                                     &id_clone,
                                     error_codes::MATANYONE_INFERENCE_FAILED,
                                     e,
-                                ),
+                                )
+                                .with_job_id(&job_id),
+                                None => {
+                                    let _ = hard_cancel_matte(&state_clone, &job_id).await;
+                                    Response::error(
+                                        &id_clone,
+                                        error_codes::CANCELLED,
+                                        "Matte job cancelled",
+                                    )
+                                    .with_job_id(&job_id)
+                                }
                             };
 
                             if let Ok(json) = serde_json::to_string(&response) {
@@ -1031,34 +2232,24 @@ async fn handle_websocket(
                     }
 
                     Command::MatAnyoneCancel { id, job_id } => {
+                        if !state.jobs.may_control(&job_id, &session_id) {
+                            let response = Response::error(
+                                &id,
+                                error_codes::PERMISSION_DENIED,
+                                format!("No running job {} of this session", job_id),
+                            );
+                            send_response(&write, &response).await?;
+                            continue;
+                        }
+
+                        // Ends the job's own task, which reports CANCELLED for the matte command
+                        let _ = state.jobs.cancel(&job_id, &session_id);
+
                         let ws_sender = write.clone();
                         let state_clone = state.clone();
                         let id_clone = id.clone();
                         tokio::spawn(async move {
-                            let port = {
-                                let proc = state_clone.matanyone_process.lock().await;
-                                proc.port()
-                            };
-                            if port != 0 {
-                                let _ = tokio::time::timeout(
-                                    Duration::from_millis(750),
-                                    crate::matanyone::inference::cancel_job(port, &job_id),
-                                )
-                                .await;
-                            }
-
-                            // Hard-cancel the sidecar process so GPU work,
-                            // worker threads, and any subprocesses are stopped.
-                            let result = {
-                                let mut proc = state_clone.matanyone_process.lock().await;
-                                if proc.port() == 0 {
-                                    Ok(false)
-                                } else {
-                                    proc.stop().await.map(|_| true)
-                                }
-                            };
-
-                            let response = match result {
+                            let response = match hard_cancel_matte(&state_clone, &job_id).await {
                                 Ok(server_stopped) => Response::ok(
                                     &id_clone,
                                     serde_json::json!({
@@ -1107,7 +2298,55 @@ async fn handle_websocket(
         }
     }
 
-    state.unregister_client(&session_id).await;
+    state.unregister_session(&session_id).await;
     info!("Connection closed: {}", addr);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse_range("bytes=500-", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=50-10", 1000), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn test_origin_allow_list_is_pinned() {
+        let allowed = vec![
+            "https://masterselects.app".to_string(),
+            "http://localhost:5173".to_string(),
+        ];
+
+        assert!(is_origin_allowed("https://masterselects.app", &allowed));
+        assert!(is_origin_allowed("http://localhost:5173", &allowed));
+        assert!(is_origin_allowed("https://feature-x.masterselects.pages.dev", &allowed));
+        assert!(!is_origin_allowed("http://localhost:8080", &allowed));
+        assert!(!is_origin_allowed("http://127.0.0.1:5173", &allowed));
+        assert!(!is_origin_allowed("https://evil.example", &allowed));
+        assert!(!is_origin_allowed("https://masterselects.pages.dev.evil.example", &allowed));
+    }
+
+    #[test]
+    fn test_extract_query_token() {
+        let uri: http::Uri = "/?token=abc123&client=web".parse().unwrap();
+        assert_eq!(extract_query_token(&uri).as_deref(), Some("abc123"));
+
+        let uri: http::Uri = "/?client=web".parse().unwrap();
+        assert_eq!(extract_query_token(&uri), None);
+
+        let uri: http::Uri = "/?token=".parse().unwrap();
+        assert_eq!(extract_query_token(&uri), None);
+
+        let uri: http::Uri = "/".parse().unwrap();
+        assert_eq!(extract_query_token(&uri), None);
+    }
+}
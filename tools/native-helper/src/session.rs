@@ -1,15 +1,32 @@
 //! Per-connection session management
 
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use futures_util::SinkExt;
 use tokio::sync::{oneshot, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
+use crate::cloud_sync::{self, SyncError};
+use crate::config::{self, Settings};
+use crate::diagnostics;
+use crate::discovery;
 use crate::download::{self, WsSender};
+use crate::gpu;
+use crate::jobs::{CancelError, JobRegistry};
 use crate::matanyone;
+use crate::project_lock::{self, LockOutcome, ProjectLocks};
 use crate::protocol::{error_codes, Command, Response, SystemInfo};
+use crate::quota;
+use crate::updater;
 use crate::utils;
+use crate::watch_folders::{self, WatchFolder, WatchFolders};
+use crate::youtube;
 
 /// Open native folder picker. On Windows uses RFD; on macOS uses osascript
 /// (avoids RFD's main-thread requirement in terminal/non-windowed env).
@@ -83,6 +100,52 @@ pub fn generate_auth_token() -> String {
         .collect()
 }
 
+/// Compare a provided token against the expected one in constant time
+pub fn tokens_match(provided: &str, expected: &str) -> bool {
+    let (a, b) = (provided.as_bytes(), expected.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Token-bucket rate limiter for a single connection
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: f64, refill_per_sec: f64) -> Self {
+        Self {
+            capacity,
+            refill_per_sec,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take one token, returning false if the bucket is empty
+    pub fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct EditorClient {
     pub session_id: String,
@@ -93,26 +156,96 @@ pub struct EditorClient {
     pub app_version: Option<String>,
 }
 
+/// A connected WebSocket client
+#[derive(Clone)]
+pub struct ClientSession {
+    pub session_id: String,
+    pub addr: SocketAddr,
+    pub sender: WsSender,
+    /// Unix timestamp (seconds) of when the connection was accepted
+    pub connected_at: u64,
+    pub authenticated: bool,
+    /// Role from `register_client` ("editor", "viewer", ...)
+    pub role: Option<String>,
+    pub session_name: Option<String>,
+}
+
+impl ClientSession {
+    pub fn new(session_id: String, addr: SocketAddr, sender: WsSender) -> Self {
+        let connected_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            session_id,
+            addr,
+            sender,
+            connected_at,
+            authenticated: false,
+            role: None,
+            session_name: None,
+        }
+    }
+}
+
 /// Shared application state
 pub struct AppState {
     pub auth_token: Option<String>,
+    sessions: Mutex<HashMap<String, ClientSession>>,
+    pub jobs: JobRegistry,
+    pub watch_folders: WatchFolders,
+    pub project_locks: ProjectLocks,
     editor_client: Mutex<Option<EditorClient>>,
     pending_ai_requests: Mutex<HashMap<String, oneshot::Sender<serde_json::Value>>>,
     granted_paths: RwLock<Vec<PathBuf>>,
     pub matanyone_process: Mutex<matanyone::process::MatAnyoneProcess>,
+    /// Cancelled once the helper is about to exit; sessions then close
+    pub shutdown: CancellationToken,
+    stopping: AtomicBool,
+    restart: AtomicBool,
 }
 
 impl AppState {
     pub fn new(auth_token: Option<String>) -> Self {
         Self {
             auth_token,
+            sessions: Mutex::new(HashMap::new()),
+            jobs: JobRegistry::new(),
+            watch_folders: WatchFolders::load(),
+            project_locks: ProjectLocks::default(),
             editor_client: Mutex::new(None),
             pending_ai_requests: Mutex::new(HashMap::new()),
             granted_paths: RwLock::new(Vec::new()),
             matanyone_process: Mutex::new(matanyone::process::MatAnyoneProcess::new()),
+            shutdown: CancellationToken::new(),
+            stopping: AtomicBool::new(false),
+            restart: AtomicBool::new(false),
         }
     }
 
+    /// Mark the helper as stopping. Returns false if a shutdown is already
+    /// under way.
+    pub fn begin_shutdown(&self, restart: bool) -> bool {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        self.restart.store(restart, Ordering::SeqCst);
+        true
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// Whether the helper should start again after shutting down.
+    pub fn restart_requested(&self) -> bool {
+        self.restart.load(Ordering::SeqCst)
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.sessions.lock().await.len()
+    }
+
     pub fn grant_path(&self, path: PathBuf) {
         if !path.is_absolute() {
             return;
@@ -130,6 +263,95 @@ impl AppState {
         utils::is_path_allowed_with_extra(path, &granted)
     }
 
+    pub async fn register_session(&self, session: ClientSession) {
+        info!("Session {} opened from {}", session.session_id, session.addr);
+        self.sessions
+            .lock()
+            .await
+            .insert(session.session_id.clone(), session);
+    }
+
+    pub async fn update_session(&self, session_id: &str, update: impl FnOnce(&mut ClientSession)) {
+        if let Some(session) = self.sessions.lock().await.get_mut(session_id) {
+            update(session);
+        }
+    }
+
+    /// Remove a session and cancel the jobs it owned.
+    pub async fn unregister_session(&self, session_id: &str) {
+        self.sessions.lock().await.remove(session_id);
+        self.jobs.cancel_session(session_id);
+        self.project_locks.release_session(session_id);
+        self.unregister_client(session_id).await;
+    }
+
+    /// Snapshot of all connected sessions for `list_sessions`.
+    pub async fn list_sessions(&self, current_session_id: &str) -> Vec<serde_json::Value> {
+        let sessions = self.sessions.lock().await;
+        let mut list: Vec<serde_json::Value> = sessions
+            .values()
+            .map(|s| {
+                let jobs: Vec<serde_json::Value> = self
+                    .jobs
+                    .owned_by(&s.session_id)
+                    .into_iter()
+                    .map(|(job_id, kind)| {
+                        serde_json::json!({ "job_id": job_id, "kind": kind.as_str() })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "session_id": s.session_id,
+                    "addr": s.addr.to_string(),
+                    "connected_at": s.connected_at,
+                    "authenticated": s.authenticated,
+                    "role": s.role,
+                    "session_name": s.session_name,
+                    "jobs": jobs,
+                    "current": s.session_id == current_session_id,
+                })
+            })
+            .collect();
+        list.sort_by_key(|s| s["connected_at"].as_u64().unwrap_or(0));
+        list
+    }
+
+    /// Send a message to every authenticated session except `except`.
+    /// Returns the number of sessions reached.
+    pub async fn broadcast(&self, text: &str, except: Option<&str>) -> usize {
+        let senders: Vec<WsSender> = self
+            .sessions
+            .lock()
+            .await
+            .values()
+            .filter(|s| s.authenticated && Some(s.session_id.as_str()) != except)
+            .map(|s| s.sender.clone())
+            .collect();
+
+        let mut reached = 0;
+        for sender in senders {
+            let mut w = sender.lock().await;
+            if w.send(Message::Text(text.to_string())).await.is_ok() {
+                reached += 1;
+            }
+        }
+        reached
+    }
+
+    /// Send a message to one authenticated session. Returns false if it is gone.
+    pub async fn send_to(&self, session_id: &str, text: &str) -> bool {
+        let sender = self
+            .sessions
+            .lock()
+            .await
+            .get(session_id)
+            .filter(|s| s.authenticated)
+            .map(|s| s.sender.clone());
+        match sender {
+            Some(sender) => sender.lock().await.send(Message::Text(text.to_string())).await.is_ok(),
+            None => false,
+        }
+    }
+
     pub async fn register_editor_client(&self, client: EditorClient) {
         let mut editor = self.editor_client.lock().await;
         *editor = Some(client);
@@ -184,15 +406,17 @@ impl AppState {
 /// Per-connection session
 pub struct Session {
     state: Arc<AppState>,
+    session_id: String,
     authenticated: bool,
 }
 
 impl Session {
-    pub fn new(state: Arc<AppState>) -> Self {
+    pub fn new(state: Arc<AppState>, session_id: String) -> Self {
         let authenticated = state.auth_token.is_none();
 
         Self {
             state,
+            session_id,
             authenticated,
         }
     }
@@ -227,6 +451,8 @@ impl Session {
                 Some(Response::ok(&id, serde_json::json!({"pong": true})))
             }
 
+            Command::ListBrowsers { id } => Some(download::handle_list_browsers(&id)),
+
             Command::GetFile { id, path } => Some(self.handle_get_file(&id, &path)),
 
             Command::Locate {
@@ -251,6 +477,24 @@ impl Session {
                 Some(self.handle_rename(&id, &old_path, &new_path))
             }
 
+            Command::LockProject {
+                id,
+                path,
+                owner,
+                force,
+            } => Some(self.handle_lock_project(&id, &path, owner, force).await),
+            Command::UnlockProject { id, path } => {
+                let released = self.state.project_locks.release(Path::new(&path), &self.session_id);
+                Some(Response::ok(&id, serde_json::json!({ "released": released })))
+            }
+
+            Command::SyncProject {
+                id,
+                path,
+                remote_name,
+                resolve,
+            } => Some(self.handle_sync_project(&id, &path, remote_name, resolve).await),
+
             Command::GrantPath { id, path } => {
                 let path = PathBuf::from(path);
                 if !path.is_absolute() {
@@ -265,6 +509,48 @@ impl Session {
                 }
             }
 
+            Command::AddWatchFolder {
+                id,
+                path,
+                extensions,
+                bin,
+                recursive,
+                generate_proxies,
+            } => Some(self.handle_add_watch_folder(
+                &id,
+                &path,
+                &extensions,
+                bin,
+                recursive,
+                generate_proxies,
+            )),
+
+            Command::ScanMediaFolder {
+                id,
+                path,
+                recursive,
+                extensions,
+            } => Some(
+                self.handle_scan_media_folder(&id, &path, recursive.unwrap_or(true), &extensions)
+                    .await,
+            ),
+
+            Command::RemoveWatchFolder { id, folder_id } => {
+                match self.state.watch_folders.remove(&folder_id) {
+                    Some(folder) => Some(Response::ok(&id, serde_json::json!({ "removed": folder }))),
+                    None => Some(Response::error(
+                        &id,
+                        error_codes::INVALID_ARGUMENT,
+                        format!("No watch folder {}", folder_id),
+                    )),
+                }
+            }
+
+            Command::ListWatchFolders { id } => Some(Response::ok(
+                &id,
+                serde_json::json!({ "folders": self.state.watch_folders.list() }),
+            )),
+
             Command::PickFolder { id, title, default_path } => {
                 let title = title.unwrap_or_else(|| "Select folder".to_string());
                 let default_path = default_path.clone();
@@ -306,6 +592,19 @@ impl Session {
                 Some(self.handle_matanyone_status(&id).await)
             }
 
+            Command::ListSessions { id } => {
+                let sessions = self.state.list_sessions(&self.session_id).await;
+                Some(Response::ok(
+                    &id,
+                    serde_json::json!({
+                        "session_id": self.session_id,
+                        "sessions": sessions,
+                    }),
+                ))
+            }
+
+            Command::CancelJob { id, job_id } => Some(self.handle_cancel_job(&id, &job_id)),
+
             Command::MatAnyoneStop { id } => {
                 Some(self.handle_matanyone_stop(&id).await)
             }
@@ -314,17 +613,79 @@ impl Session {
                 Some(self.handle_matanyone_uninstall(&id).await)
             }
 
+            Command::YoutubeStatus { id } => Some(youtube::handle_status(&id)),
+
+            Command::YoutubeLogout { id } => Some(youtube::handle_logout(&id).await),
+
+            Command::ExportDiagnostics { id, project } => {
+                Some(self.handle_export_diagnostics(&id, project).await)
+            }
+
+            Command::ListGpus { id } => Some(Self::handle_list_gpus(&id).await),
+
+            Command::Cleanup {
+                id,
+                older_than_days,
+                dry_run,
+            } => {
+                let result =
+                    tokio::task::spawn_blocking(move || quota::cleanup(older_than_days, dry_run)).await;
+                Some(match result {
+                    Ok(report) => Response::ok(&id, serde_json::json!(report)),
+                    Err(e) => Response::error(
+                        &id,
+                        error_codes::INTERNAL_ERROR,
+                        format!("Cleanup task failed: {}", e),
+                    ),
+                })
+            }
+
+            Command::GetSettings { id } => Some(Response::ok(
+                &id,
+                serde_json::json!({
+                    "settings": config::current(),
+                    "path": config::get_config_path(),
+                }),
+            )),
+
+            Command::SetSettings { id, settings } => Some(self.handle_set_settings(&id, settings)),
+
+            // ── Self-update commands ──
+
+            Command::UpdateCheck { id } => Some(self.handle_update_check(&id).await),
+
+            Command::UpdateInstall { id } => Some(self.handle_update_install(&id).await),
+
             // Download and streaming MatAnyone2 commands are handled in server.rs with WsSender
             Command::DownloadYoutube { id, .. }
             | Command::Download { id, .. }
+            | Command::DownloadSection { id, .. }
             | Command::ListFormats { id, .. }
+            | Command::YtdlpStatus { id, .. }
+            | Command::YtdlpUpdate { id, .. }
             | Command::RegisterClient { id, .. }
             | Command::AiToolResult { id, .. }
             | Command::MatAnyoneSetup { id, .. }
             | Command::MatAnyoneDownloadModel { id, .. }
             | Command::MatAnyoneStart { id, .. }
             | Command::MatAnyoneMatte { id, .. }
-            | Command::MatAnyoneCancel { id, .. } => Some(Response::error(
+            | Command::MatAnyoneCancel { id, .. }
+            | Command::Transcribe { id, .. }
+            | Command::ExtractFrame { id, .. }
+            | Command::RenderContactSheet { id, .. }
+            | Command::ListEncoders { id, .. }
+            | Command::Capabilities { id }
+            | Command::SyncAudio { id, .. }
+            | Command::AutoDuck { id, .. }
+            | Command::ProbeFrameTiming { id, .. }
+            | Command::ConformCfr { id, .. }
+            | Command::Transcode { id, .. }
+            | Command::ExtractAudio { id, .. }
+            | Command::DenoiseAudio { id, .. }
+            | Command::YoutubeLogin { id }
+            | Command::YoutubeUpload { id, .. }
+            | Command::Shutdown { id, .. }
+            | Command::Restart { id, .. } => Some(Response::error(
                 &id,
                 error_codes::INTERNAL_ERROR,
                 "This command should be handled by server",
@@ -332,9 +693,127 @@ impl Session {
         }
     }
 
+    fn handle_add_watch_folder(
+        &self,
+        id: &str,
+        path: &str,
+        extensions: &[String],
+        bin: Option<String>,
+        recursive: bool,
+        generate_proxies: bool,
+    ) -> Response {
+        let path = PathBuf::from(path);
+        if !path.is_absolute() {
+            return Response::error(id, error_codes::INVALID_PATH, "Path must be absolute");
+        }
+        if !path.is_dir() {
+            return Response::error(
+                id,
+                error_codes::FILE_NOT_FOUND,
+                format!("Not a directory: {}", path.display()),
+            );
+        }
+        if !self.state.is_path_allowed(&path) {
+            return Response::error(
+                id,
+                error_codes::PERMISSION_DENIED,
+                format!(
+                    "{} is not an allowed directory; choose it with pick_folder first",
+                    path.display()
+                ),
+            );
+        }
+
+        let folder = WatchFolder {
+            folder_id: uuid::Uuid::new_v4().to_string(),
+            path,
+            extensions: watch_folders::normalize_extensions(extensions),
+            bin,
+            recursive,
+            generate_proxies,
+        };
+        match self.state.watch_folders.add(folder) {
+            Ok(folder) => Response::ok(id, serde_json::json!({ "folder": folder })),
+            Err(e) => Response::error(id, error_codes::WATCH_FAILED, e),
+        }
+    }
+
+    async fn handle_scan_media_folder(
+        &self,
+        id: &str,
+        path: &str,
+        recursive: bool,
+        extensions: &[String],
+    ) -> Response {
+        let path = PathBuf::from(path);
+        if !path.is_absolute() {
+            return Response::error(id, error_codes::INVALID_PATH, "Path must be absolute");
+        }
+        if !path.is_dir() {
+            return Response::error(
+                id,
+                error_codes::FILE_NOT_FOUND,
+                format!("Not a directory: {}", path.display()),
+            );
+        }
+        if !self.state.is_path_allowed(&path) {
+            return Response::error(
+                id,
+                error_codes::PERMISSION_DENIED,
+                format!(
+                    "{} is not an allowed directory; choose it with pick_folder first",
+                    path.display()
+                ),
+            );
+        }
+
+        let extensions = watch_folders::normalize_extensions(extensions);
+        let scan = tokio::task::spawn_blocking(move || {
+            watch_folders::scan_folder(&path, recursive, &extensions)
+        })
+        .await;
+        match scan {
+            Ok(Ok(scan)) => Response::ok(
+                id,
+                serde_json::json!({
+                    "count": scan.files.len(),
+                    "files": scan.files,
+                    "skipped": scan.skipped,
+                    "truncated": scan.truncated,
+                }),
+            ),
+            Ok(Err(e)) => Response::error(
+                id,
+                error_codes::INTERNAL_ERROR,
+                format!("Cannot read directory: {}", e),
+            ),
+            Err(e) => Response::error(id, error_codes::INTERNAL_ERROR, e.to_string()),
+        }
+    }
+
+    fn handle_cancel_job(&self, id: &str, job_id: &str) -> Response {
+        // The job's own task sends the final CANCELLED message once it has stopped.
+        match self.state.jobs.cancel(job_id, &self.session_id) {
+            Ok(kind) => Response::ok(
+                id,
+                serde_json::json!({ "cancelling": true, "job_id": job_id, "kind": kind.as_str() }),
+            ),
+            Err(CancelError::NotFound) => Response::error(
+                id,
+                error_codes::JOB_NOT_FOUND,
+                format!("No running job {}", job_id),
+            ),
+            Err(CancelError::NotOwner) => Response::error(
+                id,
+                error_codes::PERMISSION_DENIED,
+                "Job belongs to another session",
+            ),
+        }
+    }
+
     fn handle_auth(&mut self, id: &str, token: &str) -> Response {
         match &self.state.auth_token {
-            Some(expected) if expected == token => {
+            Some(expected) if tokens_match(token, expected) => {
                 self.authenticated = true;
                 info!("Client authenticated");
                 Response::ok(id, serde_json::json!({"authenticated": true}))
@@ -380,8 +859,11 @@ impl Session {
             "installed".to_string()
         };
 
+        let ports = discovery::bound_ports();
         let info = SystemInfo {
             version: env!("CARGO_PKG_VERSION").to_string(),
+            ws_port: ports.map(|p| p.ws_port),
+            http_port: ports.map(|p| p.http_port),
             ytdlp_available,
             download_dir: utils::get_download_dir().to_string_lossy().to_string(),
             project_root: utils::get_project_root().to_string_lossy().to_string(),
@@ -390,6 +872,7 @@ impl Session {
             editor_connected,
             matanyone_available,
             matanyone_status,
+            download_usage: quota::usage(),
         };
 
         Response::ok(id, serde_json::to_value(info).unwrap())
@@ -524,6 +1007,7 @@ impl Session {
             );
         }
 
+        quota::record_use(path);
         match std::fs::read(path) {
             Ok(data) => {
                 info!("Serving file: {} ({} bytes)", path.display(), data.len());
@@ -599,10 +1083,93 @@ impl Session {
             }
         }
 
+        self.state.project_locks.note_saved(path);
         info!("Wrote file: {} ({} bytes)", path.display(), size);
         Response::ok(id, serde_json::json!({ "written": true, "size": size }))
     }
 
+    async fn handle_lock_project(
+        &self,
+        id: &str,
+        path: &str,
+        owner: Option<String>,
+        force: bool,
+    ) -> Response {
+        let path = PathBuf::from(path);
+        if !path.is_absolute() {
+            return Response::error(id, error_codes::INVALID_PATH, "Path must be absolute");
+        }
+        if !self.state.is_path_allowed(&path) {
+            return Response::error(id, error_codes::PERMISSION_DENIED, "Path not in allowed directory");
+        }
+
+        // Acquiring waits for a lock file another helper is still writing
+        let state = self.state.clone();
+        let session_id = self.session_id.clone();
+        let project = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            state.project_locks.acquire(&project, &session_id, owner, force)
+        })
+        .await;
+        match result {
+            Ok(Ok(LockOutcome::Acquired(lock))) => Response::ok(
+                id,
+                serde_json::json!({
+                    "locked": true,
+                    "read_only": false,
+                    "lock": lock,
+                    "lock_path": project_lock::lock_path(&path),
+                }),
+            ),
+            Ok(Ok(LockOutcome::HeldBy(holder))) => Response::ok(
+                id,
+                serde_json::json!({ "locked": false, "read_only": true, "holder": holder }),
+            ),
+            Ok(Err(e)) => {
+                Response::error(id, error_codes::WRITE_FAILED, format!("Cannot write lock file: {}", e))
+            }
+            Err(e) => Response::error(id, error_codes::INTERNAL_ERROR, e.to_string()),
+        }
+    }
+
+    async fn handle_sync_project(
+        &self,
+        id: &str,
+        path: &str,
+        remote_name: Option<String>,
+        resolve: Option<cloud_sync::Resolve>,
+    ) -> Response {
+        let path = PathBuf::from(path);
+        if !path.is_absolute() {
+            return Response::error(id, error_codes::INVALID_PATH, "Path must be absolute");
+        }
+        if !self.state.is_path_allowed(&path) {
+            return Response::error(id, error_codes::PERMISSION_DENIED, "Path not in allowed directory");
+        }
+
+        let project = path.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            cloud_sync::sync_project(&project, remote_name.as_deref(), resolve)
+        })
+        .await;
+        match result {
+            Ok(Ok(result)) => {
+                if result.status == "downloaded" {
+                    self.state.project_locks.note_saved(&path);
+                }
+                Response::ok(id, serde_json::json!(result))
+            }
+            Ok(Err(SyncError::NotConfigured)) => Response::error(
+                id,
+                error_codes::SYNC_NOT_CONFIGURED,
+                "Project sync is not configured. Set [sync] webdav_url in config.toml.",
+            ),
+            Ok(Err(SyncError::InvalidName(e))) => Response::error(id, error_codes::INVALID_ARGUMENT, e),
+            Ok(Err(SyncError::Failed(e))) => Response::error(id, error_codes::SYNC_FAILED, e),
+            Err(e) => Response::error(id, error_codes::INTERNAL_ERROR, e.to_string()),
+        }
+    }
+
     fn handle_create_dir(&self, id: &str, path: &str, recursive: bool) -> Response {
         let path = std::path::Path::new(path);
 
@@ -786,6 +1353,12 @@ impl Session {
         match proc.stop().await {
             Ok(()) => {
                 info!("MatAnyone2 server stopped");
+                drop(proc);
+                // The sidecar is shared; let the other tabs know it went away.
+                let event = serde_json::json!({ "type": "matanyone_status", "status": "stopped" });
+                self.state
+                    .broadcast(&event.to_string(), Some(&self.session_id))
+                    .await;
                 Response::ok(id, serde_json::json!({ "stopped": true }))
             }
             Err(e) => {
@@ -826,6 +1399,147 @@ impl Session {
         Response::ok(id, serde_json::json!({ "uninstalled": true }))
     }
 
+    async fn handle_export_diagnostics(
+        &self,
+        id: &str,
+        project: Option<serde_json::Value>,
+    ) -> Response {
+        let sessions = self.state.list_sessions(&self.session_id).await;
+        let result = tokio::task::spawn_blocking(move || {
+            let mut system = diagnostics::system_report();
+            system["sessions"] = serde_json::json!(sessions);
+            diagnostics::export_bundle(&utils::get_download_dir(), &system, project.as_ref())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(path)) => {
+                info!("Diagnostic bundle written to {}", path.display());
+                Response::ok(id, serde_json::json!({ "path": path }))
+            }
+            Ok(Err(e)) => Response::error(
+                id,
+                error_codes::DIAGNOSTICS_FAILED,
+                format!("Cannot write diagnostic bundle: {}", e),
+            ),
+            Err(e) => Response::error(
+                id,
+                error_codes::INTERNAL_ERROR,
+                format!("Diagnostics task failed: {}", e),
+            ),
+        }
+    }
+
+    async fn handle_list_gpus(id: &str) -> Response {
+        let gpus = match tokio::task::spawn_blocking(gpu::list).await {
+            Ok(gpus) => gpus,
+            Err(e) => {
+                return Response::error(
+                    id,
+                    error_codes::INTERNAL_ERROR,
+                    format!("GPU query task failed: {}", e),
+                )
+            }
+        };
+        let selected = config::current().media.gpu;
+        let active = gpu::resolve(&gpus, selected.as_deref()).map(|gpu| gpu.index);
+        Response::ok(
+            id,
+            serde_json::json!({
+                "gpus": gpus,
+                "selected": selected,
+                // Index work runs on; `null` means the driver's default device
+                "active_index": active,
+                "selected_missing": selected.is_some() && active.is_none(),
+            }),
+        )
+    }
+
+    fn handle_set_settings(&self, id: &str, settings: Settings) -> Response {
+        let current = config::current();
+        let invalid = |message: String| Response::error(id, error_codes::INVALID_ARGUMENT, message);
+
+        // The HTTP server takes the port after the WebSocket one
+        if settings.helper.port < 1024 || settings.helper.port == u16::MAX {
+            return invalid(format!("Port {} is not usable", settings.helper.port));
+        }
+        // Who may connect is only set by flags or a hand-edited config.toml, so
+        // a page can't open the helper to the network or to other origins
+        if settings.helper.bind != current.helper.bind
+            || settings.helper.allowed_origins != current.helper.allowed_origins
+        {
+            return invalid(
+                "helper.bind and helper.allowed_origins can only be changed in config.toml".to_string(),
+            );
+        }
+        // The WebDAV password goes to whatever server [sync] names
+        if settings.sync != current.sync {
+            return invalid("[sync] settings can only be changed in config.toml".to_string());
+        }
+        // New directories must be ones the user already gave the helper access to,
+        // otherwise a page could widen file access by editing preferences
+        for path in settings.paths() {
+            let unchanged = current.paths().any(|p| p == path);
+            if !path.is_absolute() || (!unchanged && !self.state.is_path_allowed(path)) {
+                return invalid(format!(
+                    "{} is not an allowed directory; choose it with pick_folder first",
+                    path.display()
+                ));
+            }
+        }
+
+        if let Err(e) = config::save(&settings) {
+            return Response::error(
+                id,
+                error_codes::SETTINGS_FAILED,
+                format!("Cannot save settings: {:#}", e),
+            );
+        }
+        info!("Settings saved to {}", config::get_config_path().display());
+        Response::ok(
+            id,
+            serde_json::json!({
+                "settings": settings,
+                "restart_required": settings.helper != current.helper,
+            }),
+        )
+    }
+
+    // ── Self-update handlers ──
+
+    async fn handle_update_check(&self, id: &str) -> Response {
+        match tokio::task::spawn_blocking(updater::check_manifest_update).await {
+            Ok(Ok(check)) => Response::ok(id, serde_json::to_value(check).unwrap_or_default()),
+            Ok(Err(e)) => {
+                warn!("Update check failed: {}", e);
+                Response::error(id, error_codes::UPDATE_FAILED, format!("Update check failed: {}", e))
+            }
+            Err(e) => Response::error(
+                id,
+                error_codes::INTERNAL_ERROR,
+                format!("Update check task failed: {}", e),
+            ),
+        }
+    }
+
+    async fn handle_update_install(&self, id: &str) -> Response {
+        match tokio::task::spawn_blocking(updater::install_manifest_update).await {
+            Ok(Ok(outcome)) => {
+                info!("Helper update v{} installed", outcome.version);
+                Response::ok(id, serde_json::to_value(outcome).unwrap_or_default())
+            }
+            Ok(Err(e)) => {
+                warn!("Update install failed: {}", e);
+                Response::error(id, error_codes::UPDATE_FAILED, format!("Update install failed: {}", e))
+            }
+            Err(e) => Response::error(
+                id,
+                error_codes::INTERNAL_ERROR,
+                format!("Update install task failed: {}", e),
+            ),
+        }
+    }
+
     fn handle_rename(&self, id: &str, old_path: &str, new_path: &str) -> Response {
         let old = std::path::Path::new(old_path);
         let new = std::path::Path::new(new_path);
@@ -864,3 +1578,39 @@ impl Session {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("abc123", "abc123"));
+        assert!(!tokens_match("abc124", "abc123"));
+        assert!(!tokens_match("abc12", "abc123"));
+        assert!(!tokens_match("", "abc123"));
+    }
+
+    #[test]
+    fn test_rate_limiter_burst_and_refill() {
+        let mut limiter = RateLimiter::new(3.0, 2.0);
+        let start = limiter.last_refill;
+
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start));
+        assert!(limiter.try_acquire_at(start));
+        assert!(!limiter.try_acquire_at(start), "burst should be exhausted");
+
+        // 2 tokens/sec → one token after 500ms
+        assert!(limiter.try_acquire_at(start + Duration::from_millis(500)));
+        assert!(!limiter.try_acquire_at(start + Duration::from_millis(500)));
+
+        // Refill never exceeds capacity
+        let later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.try_acquire_at(later));
+        }
+        assert!(!limiter.try_acquire_at(later));
+    }
+}
@@ -0,0 +1,285 @@
+//! Optional TLS for the WebSocket and HTTP servers
+//!
+//! Browsers on https origins increasingly refuse `ws://` connections to
+//! localhost. With `--tls` the helper serves `wss://` and `https://` using a
+//! self-signed certificate for `localhost`/`127.0.0.1` that is generated once
+//! and stored in the helper data dir. `--tls-cert`/`--tls-key` use an existing
+//! PEM certificate and private key instead (e.g. one issued by mkcert).
+//!
+//! Storage layout for the generated certificate:
+//! ```text
+//! {data_local_dir}/MasterSelects/tls/
+//! ├── localhost-cert.pem
+//! └── localhost-key.pem
+//! ```
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context as _, Result};
+use futures_util::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+const CERT_FILENAME: &str = "localhost-cert.pem";
+const KEY_FILENAME: &str = "localhost-key.pem";
+
+/// Handshakes that take longer than this are dropped so a stalled client
+/// can't hold up the accept loop.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Certificate and key used by both servers
+#[derive(Debug, Clone)]
+pub struct TlsSettings {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Whether the certificate was generated by the helper (self-signed)
+    pub self_signed: bool,
+}
+
+/// Return the directory holding the generated localhost certificate.
+pub fn get_tls_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("tls")
+}
+
+/// Use the given certificate and key files.
+pub fn settings_from_files(cert_path: &Path, key_path: &Path) -> Result<TlsSettings> {
+    for path in [cert_path, key_path] {
+        if !path.is_file() {
+            return Err(anyhow!("TLS file not found: {}", path.display()));
+        }
+    }
+    Ok(TlsSettings {
+        cert_path: cert_path.to_path_buf(),
+        key_path: key_path.to_path_buf(),
+        self_signed: false,
+    })
+}
+
+/// Reuse the generated self-signed certificate, creating it on first use.
+pub fn ensure_self_signed() -> Result<TlsSettings> {
+    let dir = get_tls_dir();
+    let cert_path = dir.join(CERT_FILENAME);
+    let key_path = dir.join(KEY_FILENAME);
+
+    if !cert_path.is_file() || !key_path.is_file() {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Cannot create TLS dir {}", dir.display()))?;
+
+        let (cert_pem, key_pem) = generate_self_signed()?;
+        write_key(&key_path, &key_pem)
+            .with_context(|| format!("Cannot write TLS key {}", key_path.display()))?;
+        std::fs::write(&cert_path, cert_pem)
+            .with_context(|| format!("Cannot write TLS certificate {}", cert_path.display()))?;
+
+        info!("Generated self-signed TLS certificate: {}", cert_path.display());
+    }
+
+    Ok(TlsSettings {
+        cert_path,
+        key_path,
+        self_signed: true,
+    })
+}
+
+/// Write the private key to a new file only the user can read, so it is
+/// never readable by others, not even between creating and chmod-ing it.
+fn write_key(path: &Path, pem: &str) -> io::Result<()> {
+    use std::io::Write;
+
+    // A leftover key may have other permissions; start from a new file
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path).and_then(|mut file| file.write_all(pem.as_bytes()))
+}
+
+/// Generate a PEM certificate/key pair valid for the loopback host names.
+fn generate_self_signed() -> Result<(String, String)> {
+    let names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| anyhow!("Certificate generation failed: {}", e))?;
+    Ok((certified.cert.pem(), certified.key_pair.serialize_pem()))
+}
+
+/// Build a TLS acceptor from PEM files.
+pub fn load_acceptor(settings: &TlsSettings) -> Result<TlsAcceptor> {
+    let cert_file = std::fs::File::open(&settings.cert_path)
+        .with_context(|| format!("Cannot open {}", settings.cert_path.display()))?;
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(cert_file))
+        .collect::<Result<Vec<_>, _>>()
+        .context("Invalid TLS certificate PEM")?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates in {}", settings.cert_path.display()));
+    }
+
+    let key_file = std::fs::File::open(&settings.key_path)
+        .with_context(|| format!("Cannot open {}", settings.key_path.display()))?;
+    let key = rustls_pemfile::private_key(&mut io::BufReader::new(key_file))
+        .context("Invalid TLS key PEM")?
+        .ok_or_else(|| anyhow!("No private key in {}", settings.key_path.display()))?;
+
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_no_client_auth()
+    .with_single_cert(certs, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Run the TLS handshake with a timeout.
+pub async fn accept(acceptor: &TlsAcceptor, stream: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")),
+    }
+}
+
+/// Stream of TLS connections for `warp::serve(..).run_incoming(..)`.
+///
+/// Each handshake runs in its own task, so a client that connects and stays
+/// silent (or a browser waiting on the certificate warning) does not hold up
+/// other connections. Failed handshakes are logged and skipped rather than
+/// ending the stream.
+pub fn incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            let (stream, addr) = tokio::select! {
+                result = listener.accept() => match result {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        if tx.send(Err(e)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                },
+                // The server stopped polling the stream
+                _ = tx.closed() => return,
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match accept(&acceptor, stream).await {
+                    Ok(tls) => {
+                        let _ = tx.send(Ok(tls)).await;
+                    }
+                    Err(e) => warn!("HTTPS handshake with {} failed: {}", addr, e),
+                }
+            });
+        }
+    });
+    futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|conn| (conn, rx)) })
+}
+
+/// A WebSocket transport that is either plain TCP or TLS.
+pub enum HelperStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for HelperStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HelperStream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            HelperStream::Tls(s) => Pin::new(s.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for HelperStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            HelperStream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            HelperStream::Tls(s) => Pin::new(s.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HelperStream::Plain(s) => Pin::new(s).poll_flush(cx),
+            HelperStream::Tls(s) => Pin::new(s.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            HelperStream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            HelperStream::Tls(s) => Pin::new(s.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_cert_loads() {
+        let dir = std::env::temp_dir().join(format!("masterselects-tls-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join(CERT_FILENAME);
+        let key_path = dir.join(KEY_FILENAME);
+
+        let (cert_pem, key_pem) = generate_self_signed().unwrap();
+        assert!(cert_pem.contains("BEGIN CERTIFICATE"));
+        std::fs::write(&cert_path, cert_pem).unwrap();
+        std::fs::write(&key_path, "old key").unwrap();
+        write_key(&key_path, &key_pem).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let settings = settings_from_files(&cert_path, &key_path).unwrap();
+        assert!(!settings.self_signed);
+        assert!(load_acceptor(&settings).is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_missing_files_rejected() {
+        let missing = std::env::temp_dir().join("masterselects-tls-missing.pem");
+        assert!(settings_from_files(&missing, &missing).is_err());
+    }
+}
@@ -0,0 +1,412 @@
+//! Speech-to-text transcription using whisper.cpp
+//!
+//! The clip's audio is extracted to 16 kHz mono WAV with ffmpeg and passed to
+//! the whisper.cpp CLI, which writes timestamped segments as JSON.
+//!
+//! Lookup order for the whisper.cpp binary: `MASTERSELECTS_WHISPER`, then
+//! `whisper-cli`/`whisper` next to the helper executable, then PATH.
+//! Models are ggml files in:
+//! ```text
+//! {data_local_dir}/MasterSelects/whisper/models/
+//! └── ggml-base.bin   (ggml-<name>.bin, selected by the `model` argument)
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command as TokioCommand;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::jobs::JobReporter;
+use crate::media;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+
+const WHISPER_ENV: &str = "MASTERSELECTS_WHISPER";
+const DEFAULT_MODEL: &str = "base";
+
+#[cfg(windows)]
+const WHISPER_NAMES: &[&str] = &["whisper-cli.exe", "whisper.exe", "main.exe"];
+#[cfg(not(windows))]
+const WHISPER_NAMES: &[&str] = &["whisper-cli", "whisper-cpp", "whisper"];
+
+/// A timestamped piece of the transcript
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Segment {
+    /// Start time in seconds
+    pub start: f64,
+    /// End time in seconds
+    pub end: f64,
+    pub text: String,
+}
+
+// whisper.cpp `-oj` output
+#[derive(Deserialize)]
+struct WhisperOutput {
+    #[serde(default)]
+    result: Option<WhisperResult>,
+    transcription: Vec<WhisperSegment>,
+}
+
+#[derive(Deserialize)]
+struct WhisperResult {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WhisperSegment {
+    offsets: WhisperOffsets,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct WhisperOffsets {
+    from: u64,
+    to: u64,
+}
+
+/// Return the directory holding ggml whisper models.
+pub fn get_models_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("whisper")
+        .join("models")
+}
+
+/// Find the whisper.cpp CLI.
+pub fn find_whisper() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var(WHISPER_ENV) {
+        let path = PathBuf::from(path);
+        if path.is_file() {
+            return Some(path);
+        }
+        warn!("{} points to a missing file: {}", WHISPER_ENV, path.display());
+    }
+
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+    {
+        for name in WHISPER_NAMES {
+            let bundled = exe_dir.join(name);
+            if bundled.is_file() {
+                return Some(bundled);
+            }
+        }
+    }
+
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var)
+        .flat_map(|dir| WHISPER_NAMES.iter().map(move |name| dir.join(name)))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Resolve a model name (`base`, `small.en`, ...) or an absolute path to a ggml file.
+pub fn resolve_model(model: Option<&str>) -> Option<PathBuf> {
+    let model = model.unwrap_or(DEFAULT_MODEL);
+    let as_path = Path::new(model);
+    if as_path.is_absolute() {
+        return as_path.is_file().then(|| as_path.to_path_buf());
+    }
+
+    let models_dir = get_models_dir();
+    let named = models_dir.join(format!("ggml-{}.bin", model));
+    if named.is_file() {
+        return Some(named);
+    }
+
+    // Fall back to any installed model when the default isn't present
+    if model == DEFAULT_MODEL {
+        let mut installed: Vec<PathBuf> = std::fs::read_dir(&models_dir)
+            .ok()?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with("ggml-") && n.ends_with(".bin"))
+                    .unwrap_or(false)
+            })
+            .collect();
+        installed.sort();
+        return installed.into_iter().next();
+    }
+
+    None
+}
+
+/// Parse a whisper.cpp `-pp` line such as `whisper_print_progress_callback: progress =  42%`.
+fn parse_progress(line: &str) -> Option<f32> {
+    let rest = line.split("progress =").nth(1)?;
+    rest.trim().trim_end_matches('%').trim().parse().ok()
+}
+
+fn parse_output(json: &str) -> Result<(Option<String>, Vec<Segment>), String> {
+    let output: WhisperOutput =
+        serde_json::from_str(json).map_err(|e| format!("Invalid whisper output: {}", e))?;
+    let segments = output
+        .transcription
+        .into_iter()
+        .map(|s| Segment {
+            start: s.offsets.from as f64 / 1000.0,
+            end: s.offsets.to as f64 / 1000.0,
+            text: s.text.trim().to_string(),
+        })
+        .filter(|s| !s.text.is_empty())
+        .collect();
+    Ok((output.result.and_then(|r| r.language), segments))
+}
+
+/// Transcribe a media file as a job. The returned response is the job's final message.
+pub async fn handle_transcribe(
+    path: &Path,
+    language: Option<&str>,
+    model: Option<&str>,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+    let cancelled = || fail(error_codes::CANCELLED, "Transcription cancelled".to_string());
+
+    let Some(whisper) = find_whisper() else {
+        return fail(
+            error_codes::WHISPER_NOT_FOUND,
+            format!(
+                "whisper.cpp not found. Put whisper-cli next to the helper, on PATH, or set {}.",
+                WHISPER_ENV
+            ),
+        );
+    };
+    let Some(model_path) = resolve_model(model) else {
+        return fail(
+            error_codes::WHISPER_NOT_FOUND,
+            format!(
+                "Whisper model '{}' not found in {}",
+                model.unwrap_or(DEFAULT_MODEL),
+                get_models_dir().display()
+            ),
+        );
+    };
+    let Some(ffmpeg) = media::find_ffmpeg() else {
+        return fail(
+            error_codes::FFMPEG_NOT_FOUND,
+            "Transcription requires ffmpeg on PATH or next to the helper.".to_string(),
+        );
+    };
+
+    let work_dir = std::env::temp_dir()
+        .join("masterselects-transcribe")
+        .join(job_id);
+    if let Err(e) = tokio::fs::create_dir_all(&work_dir).await {
+        return fail(error_codes::TRANSCRIBE_FAILED, format!("Cannot create temp dir: {}", e));
+    }
+
+    let response = transcribe_in(
+        &work_dir, path, language, &whisper, &model_path, &ffmpeg, reporter, cancel,
+    )
+    .await
+    .unwrap_or_else(cancelled);
+
+    let _ = tokio::fs::remove_dir_all(&work_dir).await;
+    response
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn transcribe_in(
+    work_dir: &Path,
+    path: &Path,
+    language: Option<&str>,
+    whisper: &Path,
+    model_path: &Path,
+    ffmpeg: &Path,
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Option<Response> {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |message: String| {
+        Response::error(id, error_codes::TRANSCRIBE_FAILED, message).with_job_id(job_id)
+    };
+
+    // 1. Extract audio as 16 kHz mono WAV (what whisper.cpp expects)
+    reporter
+        .progress(&JobProgress::stage(job_stages::EXTRACTING_AUDIO, 0.0))
+        .await;
+    let wav_path = work_dir.join("audio.wav");
+    let mut ffmpeg_cmd = media::ffmpeg_command(ffmpeg);
+    ffmpeg_cmd
+        .args(["-y", "-i"])
+        .arg(path)
+        .args(["-vn", "-ac", "1", "-ar", "16000", "-c:a", "pcm_s16le"])
+        .arg(&wav_path);
+    match media::run_cancellable(&mut ffmpeg_cmd, cancel).await? {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            return Some(fail(format!(
+                "ffmpeg could not extract audio: {}",
+                media::stderr_tail(&output)
+            )));
+        }
+        Err(e) => return Some(fail(format!("Failed to run ffmpeg: {}", e))),
+    }
+
+    // 2. Run whisper.cpp, streaming its progress
+    reporter
+        .progress(&JobProgress::stage(job_stages::TRANSCRIBING, 5.0))
+        .await;
+    let output_base = work_dir.join("transcript");
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get().min(8))
+        .unwrap_or(4);
+
+    let mut whisper_cmd = TokioCommand::new(whisper);
+    crate::utils::no_window(&mut whisper_cmd);
+    whisper_cmd
+        .arg("-m")
+        .arg(model_path)
+        .arg("-f")
+        .arg(&wav_path)
+        .args(["-l", language.unwrap_or("auto")])
+        .args(["-t", &threads.to_string()])
+        .args(["-oj", "-pp", "-of"])
+        .arg(&output_base)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    info!(
+        "Transcribing {} with {} (model {})",
+        path.display(),
+        whisper.display(),
+        model_path.display()
+    );
+
+    let mut child = match whisper_cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => return Some(fail(format!("Failed to run whisper.cpp: {}", e))),
+    };
+
+    let mut last_lines: Vec<String> = Vec::new();
+    if let Some(stderr) = child.stderr.take() {
+        let mut lines = BufReader::new(stderr).lines();
+        loop {
+            let line = tokio::select! {
+                line = lines.next_line() => match line {
+                    Ok(Some(line)) => line,
+                    _ => break,
+                },
+                _ = cancel.cancelled() => {
+                    let _ = child.kill().await;
+                    return None;
+                }
+            };
+            if let Some(percent) = parse_progress(&line) {
+                let overall = 5.0 + percent.clamp(0.0, 100.0) * 0.94;
+                reporter
+                    .progress(&JobProgress::stage(job_stages::TRANSCRIBING, overall))
+                    .await;
+            } else if !line.trim().is_empty() {
+                if last_lines.len() == 5 {
+                    last_lines.remove(0);
+                }
+                last_lines.push(line);
+            }
+        }
+    }
+
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = cancel.cancelled() => {
+            let _ = child.kill().await;
+            return None;
+        }
+    };
+    match status {
+        Ok(s) if s.success() => {}
+        Ok(s) => {
+            return Some(fail(format!(
+                "whisper.cpp exited with code {}: {}",
+                s.code().unwrap_or(-1),
+                last_lines.join("\n")
+            )));
+        }
+        Err(e) => return Some(fail(format!("whisper.cpp failed: {}", e))),
+    }
+
+    // 3. Read the JSON transcript
+    let json_path = output_base.with_extension("json");
+    let json = match tokio::fs::read_to_string(&json_path).await {
+        Ok(json) => json,
+        Err(e) => return Some(fail(format!("whisper.cpp wrote no transcript: {}", e))),
+    };
+    let (detected_language, segments) = match parse_output(&json) {
+        Ok(parsed) => parsed,
+        Err(e) => return Some(fail(e)),
+    };
+
+    let text = segments
+        .iter()
+        .map(|s| s.text.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    info!(
+        "Transcription complete: {} ({} segments)",
+        path.display(),
+        segments.len()
+    );
+
+    Some(Response::job_complete(
+        id,
+        job_id,
+        serde_json::json!({
+            "path": path.to_string_lossy(),
+            "language": detected_language.or_else(|| language.map(str::to_string)),
+            "segments": segments,
+            "text": text,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress("whisper_print_progress_callback: progress =  42%"),
+            Some(42.0)
+        );
+        assert_eq!(parse_progress("whisper_init_from_file: loading model"), None);
+    }
+
+    #[test]
+    fn test_parse_output() {
+        let json = r#"{
+            "result": { "language": "en" },
+            "transcription": [
+                { "timestamps": { "from": "00:00:00,000", "to": "00:00:02,500" },
+                  "offsets": { "from": 0, "to": 2500 }, "text": " Hello there." },
+                { "offsets": { "from": 2500, "to": 2600 }, "text": " " },
+                { "offsets": { "from": 2600, "to": 4000 }, "text": " General Kenobi." }
+            ]
+        }"#;
+        let (language, segments) = parse_output(json).unwrap();
+        assert_eq!(language.as_deref(), Some("en"));
+        assert_eq!(segments.len(), 2);
+        assert_eq!(
+            segments[0],
+            Segment {
+                start: 0.0,
+                end: 2.5,
+                text: "Hello there.".to_string()
+            }
+        );
+        assert_eq!(segments[1].start, 2.6);
+    }
+}
@@ -13,9 +13,9 @@ use std::time::{Duration, Instant};
 use anyhow::Result;
 use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
 use tray_icon::{Icon, TrayIconBuilder};
-use windows_sys::Win32::Foundation::GetLastError;
+use windows_sys::Win32::Foundation::{CloseHandle, GetLastError};
 use windows_sys::Win32::System::Console::GetConsoleWindow;
-use windows_sys::Win32::System::Threading::CreateMutexW;
+use windows_sys::Win32::System::Threading::{CreateMutexW, ReleaseMutex};
 use windows_sys::Win32::UI::WindowsAndMessaging::*;
 
 use crate::updater;
@@ -91,6 +91,8 @@ pub fn run_tray(state: Arc<TrayState>, port: u16) -> Result<()> {
 
     let open_downloads = MenuItem::new("Open Downloads Folder", true, None);
 
+    let diagnostics_item = MenuItem::new("Export Diagnostic Bundle", true, None);
+
     let update_item = MenuItem::new("Check for Updates", true, None);
 
     let quit_item = MenuItem::new("Quit", true, None);
@@ -101,6 +103,7 @@ pub fn run_tray(state: Arc<TrayState>, port: u16) -> Result<()> {
     menu.append(&PredefinedMenuItem::separator())?;
     menu.append(&autostart_item)?;
     menu.append(&open_downloads)?;
+    menu.append(&diagnostics_item)?;
     menu.append(&update_item)?;
     menu.append(&PredefinedMenuItem::separator())?;
     menu.append(&quit_item)?;
@@ -115,6 +118,7 @@ pub fn run_tray(state: Arc<TrayState>, port: u16) -> Result<()> {
     // Capture menu item IDs for event matching
     let autostart_id = autostart_item.id().clone();
     let open_downloads_id = open_downloads.id().clone();
+    let diagnostics_id = diagnostics_item.id().clone();
     let update_id = update_item.id().clone();
     let quit_id = quit_item.id().clone();
 
@@ -172,6 +176,19 @@ pub fn run_tray(state: Arc<TrayState>, port: u16) -> Result<()> {
                 let dir = crate::utils::get_download_dir();
                 let _ = std::fs::create_dir_all(&dir);
                 let _ = std::process::Command::new("explorer").arg(&dir).spawn();
+            } else if event.id == diagnostics_id {
+                std::thread::spawn(|| {
+                    let system = crate::diagnostics::system_report();
+                    let dir = crate::utils::get_download_dir();
+                    match crate::diagnostics::export_bundle(&dir, &system, None) {
+                        Ok(path) => {
+                            let _ = std::process::Command::new("explorer")
+                                .arg(format!("/select,{}", path.display()))
+                                .spawn();
+                        }
+                        Err(e) => eprintln!("Failed to export diagnostic bundle: {}", e),
+                    }
+                });
             } else if event.id == update_id {
                 handle_update_click(&state);
             }
@@ -183,6 +200,8 @@ pub fn run_tray(state: Arc<TrayState>, port: u16) -> Result<()> {
             let running = state.running.load(Ordering::Relaxed);
 
             let status_str = if running {
+                // The server may have fallen back to another port pair
+                let port = crate::discovery::bound_ports().map_or(port, |ports| ports.ws_port);
                 if conns > 0 {
                     format!(
                         "Running (port {}) \u{2014} {} client{}",
@@ -492,12 +511,21 @@ pub fn hide_console_window() {
 // Single-instance mutex
 // ---------------------------------------------------------------------------
 
-/// Opaque wrapper for the Win32 mutex handle. Prevents the handle from being
-/// dropped while the program runs (which would release the mutex).
-#[allow(dead_code)]
+/// Opaque wrapper for the Win32 mutex handle. Keep it alive while the
+/// program runs; dropping it releases the mutex (so a restarted helper can
+/// take it over).
 pub struct MutexLock(*mut std::ffi::c_void);
 unsafe impl Send for MutexLock {}
 
+impl Drop for MutexLock {
+    fn drop(&mut self) {
+        unsafe {
+            ReleaseMutex(self.0);
+            CloseHandle(self.0);
+        }
+    }
+}
+
 /// Acquire a system-wide named mutex to prevent duplicate instances.
 /// Returns a `MutexLock` on success, `None` if another instance already holds it.
 pub fn acquire_single_instance_lock() -> Option<MutexLock> {
@@ -1,28 +1,292 @@
-//! Self-update via GitHub Releases API
+//! Self-update via GitHub Releases
 //!
-//! Checks for newer `native-helper-v*` releases, downloads the MSI asset,
-//! and launches `msiexec /i` to upgrade in-place.
-
-#![cfg(windows)]
+//! Two update paths share this module:
+//!
+//! * **Manifest updates (all platforms):** a `native-helper-manifest.json`
+//!   release asset lists one artifact per platform with its SHA-256. The
+//!   helper downloads the artifact next to its own executable, verifies the
+//!   digest, and atomically swaps the binary. Exposed over the WebSocket as
+//!   `update_check` / `update_install`.
+//! * **MSI updates (Windows tray):** checks for newer `native-helper-v*`
+//!   releases, downloads the MSI asset, and launches `msiexec /i` to upgrade
+//!   in-place.
+//!
+//! Manifest layout:
+//! ```text
+//! {
+//!   "version": "0.3.16",
+//!   "notes": "optional release notes",
+//!   "platforms": {
+//!     "linux-x86_64":   { "url": "https://...", "sha256": "<hex>" },
+//!     "macos-aarch64":  { "url": "https://...", "sha256": "<hex>" },
+//!     "windows-x86_64": { "url": "https://....msi", "sha256": "<hex>" }
+//!   }
+//! }
+//! ```
 
+use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
 
+#[cfg(windows)]
 const GITHUB_API_RELEASES: &str =
     "https://api.github.com/repos/Sportinger/MasterSelects/releases";
-const USER_AGENT: &str = "MasterSelects-Helper";
+pub const USER_AGENT: &str = "MasterSelects-Helper";
+#[cfg(windows)]
 const TAG_PREFIX: &str = "native-helper-v";
 
+/// Default manifest location (latest helper release asset).
+/// Can be overridden via the MASTERSELECTS_UPDATE_MANIFEST env var.
+const DEFAULT_MANIFEST_URL: &str =
+    "https://github.com/Sportinger/MasterSelects/releases/latest/download/native-helper-manifest.json";
+
+/// Chunk size for streaming artifact downloads and hashing.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
 /// Information about an available update
+#[cfg(windows)]
 #[derive(Clone, Debug)]
 pub struct UpdateInfo {
     pub version: String,
     pub download_url: String,
 }
 
+/// Release manifest published alongside each helper release
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReleaseManifest {
+    pub version: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    pub platforms: HashMap<String, PlatformArtifact>,
+}
+
+/// Downloadable artifact for a single platform
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PlatformArtifact {
+    pub url: String,
+    pub sha256: String,
+}
+
+/// Result of a manifest-based update check, sent to the web app as-is
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateCheck {
+    pub current_version: String,
+    pub latest_version: String,
+    pub update_available: bool,
+    pub platform: String,
+    pub notes: Option<String>,
+    pub artifact: Option<PlatformArtifact>,
+}
+
+/// Outcome of installing a manifest update
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallOutcome {
+    pub version: String,
+    /// Path of the binary (or installer) that was written
+    pub path: String,
+    /// The new binary takes effect after the helper restarts
+    pub restart_required: bool,
+}
+
+/// Return the manifest URL, honoring the MASTERSELECTS_UPDATE_MANIFEST override.
+/// Only https is accepted: the manifest carries the SHA-256 the download is
+/// checked against, so it must not be replaceable in transit.
+pub fn get_manifest_url() -> String {
+    std::env::var("MASTERSELECTS_UPDATE_MANIFEST")
+        .ok()
+        .filter(|url| url.starts_with("https://"))
+        .unwrap_or_else(|| DEFAULT_MANIFEST_URL.to_string())
+}
+
+/// Manifest key for the running platform, e.g. `linux-x86_64` or `macos-aarch64`
+pub fn platform_key() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Download and parse the release manifest.
+pub fn fetch_manifest(url: &str) -> Result<ReleaseManifest> {
+    let body = ureq::AgentBuilder::new()
+        .redirects(10)
+        .build()
+        .get(url)
+        .set("User-Agent", USER_AGENT)
+        .call()?
+        .into_string()?;
+
+    parse_manifest(&body)
+}
+
+fn parse_manifest(body: &str) -> Result<ReleaseManifest> {
+    let manifest: ReleaseManifest =
+        serde_json::from_str(body).context("Invalid update manifest")?;
+    if manifest.version.trim().is_empty() {
+        bail!("Update manifest has no version");
+    }
+    Ok(manifest)
+}
+
+/// Compare a manifest against the running version for the given platform.
+fn evaluate_manifest(manifest: &ReleaseManifest, current: &str, platform: &str) -> UpdateCheck {
+    let artifact = manifest.platforms.get(platform).cloned();
+    UpdateCheck {
+        current_version: current.to_string(),
+        latest_version: manifest.version.clone(),
+        update_available: artifact.is_some() && is_newer(&manifest.version, current),
+        platform: platform.to_string(),
+        notes: manifest.notes.clone(),
+        artifact,
+    }
+}
+
+/// Check the release manifest for a newer helper build for this platform.
+pub fn check_manifest_update() -> Result<UpdateCheck> {
+    let manifest = fetch_manifest(&get_manifest_url())?;
+    Ok(evaluate_manifest(
+        &manifest,
+        env!("CARGO_PKG_VERSION"),
+        &platform_key(),
+    ))
+}
+
+/// Download, verify, and install the latest manifest update for this platform.
+///
+/// On Linux/macOS the running executable is replaced atomically via rename;
+/// the old inode stays valid until the process exits. On Windows the artifact
+/// is the MSI installer, which is verified and then handed to `msiexec`.
+pub fn install_manifest_update() -> Result<InstallOutcome> {
+    let check = check_manifest_update()?;
+    let artifact = check
+        .artifact
+        .clone()
+        .ok_or_else(|| anyhow!("No update artifact published for {}", check.platform))?;
+
+    if !check.update_available {
+        bail!(
+            "Already up to date (v{} >= v{})",
+            check.current_version,
+            check.latest_version
+        );
+    }
+
+    #[cfg(windows)]
+    {
+        let msi_path = std::env::temp_dir().join("MasterSelects-Helper-update.msi");
+        download_verified(&artifact, &msi_path)?;
+        install_update(&msi_path)?;
+        Ok(InstallOutcome {
+            version: check.latest_version,
+            path: msi_path.to_string_lossy().to_string(),
+            restart_required: true,
+        })
+    }
+
+    #[cfg(not(windows))]
+    {
+        let exe = std::env::current_exe().context("Cannot locate helper executable")?;
+        let exe = exe.canonicalize().unwrap_or(exe);
+        let staged = staged_binary_path(&exe);
+
+        download_verified(&artifact, &staged)?;
+        if let Err(e) = swap_binary(&staged, &exe) {
+            let _ = std::fs::remove_file(&staged);
+            return Err(e);
+        }
+
+        info!("Installed helper v{} to {}", check.latest_version, exe.display());
+        Ok(InstallOutcome {
+            version: check.latest_version,
+            path: exe.to_string_lossy().to_string(),
+            restart_required: true,
+        })
+    }
+}
+
+/// Staging path next to the executable so the final rename stays on one filesystem
+#[cfg(not(windows))]
+fn staged_binary_path(exe: &Path) -> PathBuf {
+    let mut s = exe.as_os_str().to_os_string();
+    s.push(".update");
+    PathBuf::from(s)
+}
+
+/// Make the staged binary executable and rename it over the current one.
+#[cfg(not(windows))]
+fn swap_binary(staged: &Path, exe: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(staged, std::fs::Permissions::from_mode(0o755))
+        .context("Cannot mark update as executable")?;
+    std::fs::rename(staged, exe)
+        .with_context(|| format!("Cannot replace {}", exe.display()))?;
+    Ok(())
+}
+
+/// Download an artifact to `dest`, verifying its SHA-256 before it is kept.
+fn download_verified(artifact: &PlatformArtifact, dest: &Path) -> Result<()> {
+    if !artifact.url.starts_with("https://") {
+        bail!("Refusing to download update over insecure URL: {}", artifact.url);
+    }
+
+    let resp = ureq::AgentBuilder::new()
+        .redirects(10)
+        .build()
+        .get(&artifact.url)
+        .set("User-Agent", USER_AGENT)
+        .call()?;
+
+    let tmp_path = {
+        let mut s = dest.as_os_str().to_os_string();
+        s.push(".tmp");
+        PathBuf::from(s)
+    };
+
+    let result = (|| -> Result<()> {
+        let mut reader = resp.into_reader();
+        let mut file = std::fs::File::create(&tmp_path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; READ_CHUNK_SIZE];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n])?;
+        }
+        file.sync_all()?;
+
+        let actual = format!("{:x}", hasher.finalize());
+        if !digest_matches(&artifact.sha256, &actual) {
+            bail!(
+                "SHA-256 mismatch for update: expected {}, got {}",
+                artifact.sha256,
+                actual
+            );
+        }
+
+        std::fs::rename(&tmp_path, dest)?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+pub fn digest_matches(expected: &str, actual: &str) -> bool {
+    let expected = expected.trim();
+    !expected.is_empty() && expected.eq_ignore_ascii_case(actual)
+}
+
 /// Query the GitHub Releases API for a newer native-helper release.
 /// Returns `Some(UpdateInfo)` if a newer version with an MSI asset exists.
+#[cfg(windows)]
 pub fn check_for_update() -> Result<Option<UpdateInfo>> {
     let body = ureq::get(GITHUB_API_RELEASES)
         .set("User-Agent", USER_AGENT)
@@ -71,6 +335,7 @@ pub fn check_for_update() -> Result<Option<UpdateInfo>> {
 }
 
 /// Download the MSI from `url` to a temp file. Returns the path.
+#[cfg(windows)]
 pub fn download_update(url: &str) -> Result<PathBuf> {
     let temp_path = std::env::temp_dir().join("MasterSelects-Helper-update.msi");
 
@@ -86,6 +351,7 @@ pub fn download_update(url: &str) -> Result<PathBuf> {
 
 /// Launch `msiexec /i <msi>` to install the update.
 /// The MSI's MajorUpgrade handles removing the old version.
+#[cfg(windows)]
 pub fn install_update(msi_path: &Path) -> Result<()> {
     // Launch msiexec detached — it will show the installer UI
     std::process::Command::new("msiexec")
@@ -98,7 +364,10 @@ pub fn install_update(msi_path: &Path) -> Result<()> {
 /// Simple semver comparison: is `remote` strictly newer than `local`?
 fn is_newer(remote: &str, local: &str) -> bool {
     let parse = |s: &str| -> (u32, u32, u32) {
-        let mut parts = s.split('.').filter_map(|p| p.parse::<u32>().ok());
+        let mut parts = s
+            .trim_start_matches('v')
+            .split('.')
+            .filter_map(|p| p.parse::<u32>().ok());
         (
             parts.next().unwrap_or(0),
             parts.next().unwrap_or(0),
@@ -112,12 +381,62 @@ fn is_newer(remote: &str, local: &str) -> bool {
 mod tests {
     use super::*;
 
+    const MANIFEST: &str = r#"{
+        "version": "9.9.9",
+        "notes": "Fixes",
+        "platforms": {
+            "linux-x86_64": { "url": "https://example.com/helper-linux", "sha256": "ABCDEF" },
+            "macos-aarch64": { "url": "https://example.com/helper-macos", "sha256": "123456" }
+        }
+    }"#;
+
     #[test]
     fn test_is_newer() {
         assert!(is_newer("0.3.0", "0.2.0"));
         assert!(is_newer("1.0.0", "0.9.9"));
         assert!(is_newer("0.2.1", "0.2.0"));
+        assert!(is_newer("v0.2.1", "0.2.0"));
         assert!(!is_newer("0.2.0", "0.2.0"));
         assert!(!is_newer("0.1.0", "0.2.0"));
     }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+        assert_eq!(manifest.version, "9.9.9");
+        assert_eq!(manifest.platforms.len(), 2);
+        assert!(parse_manifest(r#"{"version": "", "platforms": {}}"#).is_err());
+        assert!(parse_manifest("not json").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_manifest() {
+        let manifest = parse_manifest(MANIFEST).unwrap();
+
+        let check = evaluate_manifest(&manifest, "0.3.15", "linux-x86_64");
+        assert!(check.update_available);
+        assert_eq!(check.artifact.unwrap().url, "https://example.com/helper-linux");
+
+        let up_to_date = evaluate_manifest(&manifest, "9.9.9", "linux-x86_64");
+        assert!(!up_to_date.update_available);
+
+        let missing_platform = evaluate_manifest(&manifest, "0.3.15", "freebsd-x86_64");
+        assert!(!missing_platform.update_available);
+        assert!(missing_platform.artifact.is_none());
+    }
+
+    #[test]
+    fn test_platform_key() {
+        let key = platform_key();
+        assert!(key.ends_with(std::env::consts::ARCH));
+        assert!(!key.starts_with('-'));
+    }
+
+    #[test]
+    fn test_digest_matches() {
+        assert!(digest_matches("ABCDEF", "abcdef"));
+        assert!(digest_matches(" abcdef\n", "abcdef"));
+        assert!(!digest_matches("", ""));
+        assert!(!digest_matches("abcdef", "abcdee"));
+    }
 }
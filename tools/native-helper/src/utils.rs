@@ -27,14 +27,50 @@ pub fn no_window_std(cmd: &mut std::process::Command) -> &mut std::process::Comm
     cmd
 }
 
-/// Get the default download directory for videos
+/// Check that an executable exists and answers `--version`.
+pub fn executable_works(path: &Path) -> bool {
+    if !path.exists() || !path.is_file() {
+        return false;
+    }
+
+    no_window_std(std::process::Command::new(path).arg("--version"))
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Check that a command on PATH answers `--version`.
+pub fn command_works(command: &str) -> bool {
+    no_window_std(std::process::Command::new(command).arg("--version"))
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Free and total bytes on the volume holding `path`. The path itself need
+/// not exist yet; its nearest existing ancestor is measured.
+pub fn disk_space(path: &Path) -> Option<(u64, u64)> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let available = fs4::available_space(existing).ok()?;
+    let total = fs4::total_space(existing).ok()?;
+    Some((available, total))
+}
+
+/// Get the download directory for videos (configurable in config.toml)
 pub fn get_download_dir() -> PathBuf {
-    let base = std::env::temp_dir();
-    base.join("masterselects-downloads")
+    crate::config::current()
+        .paths
+        .downloads
+        .unwrap_or_else(default_download_dir)
+}
+
+/// The helper's own download directory, used when none is configured
+pub fn default_download_dir() -> PathBuf {
+    std::env::temp_dir().join("masterselects-downloads")
 }
 
 /// Get the default project root directory
-/// Can be overridden via MASTERSELECTS_PROJECT_ROOT env var
+/// Can be overridden via MASTERSELECTS_PROJECT_ROOT env var or config.toml
 pub fn get_project_root() -> PathBuf {
     if let Ok(custom) = std::env::var("MASTERSELECTS_PROJECT_ROOT") {
         let p = PathBuf::from(custom);
@@ -43,6 +79,10 @@ pub fn get_project_root() -> PathBuf {
         }
     }
 
+    if let Some(projects) = crate::config::current().paths.projects {
+        return projects;
+    }
+
     if let Some(docs) = dirs::document_dir() {
         return docs.join("MasterSelects");
     }
@@ -84,6 +124,14 @@ pub fn get_allowed_prefixes() -> Vec<PathBuf> {
         prefixes.push(project_root);
     }
 
+    // Download dir and scratch disks from config.toml
+    let settings = crate::config::current();
+    for dir in std::iter::once(get_download_dir()).chain(settings.paths.scratch) {
+        if !prefixes.iter().any(|p| dir.starts_with(p)) {
+            prefixes.push(dir);
+        }
+    }
+
     // User's Videos folder (for media file serving)
     if let Some(videos) = dirs::video_dir() {
         prefixes.push(videos);
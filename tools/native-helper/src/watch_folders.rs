@@ -0,0 +1,466 @@
+//! Watch folders for automatic media import, and one-off folder imports
+//!
+//! Directories registered with `add_watch_folder` are monitored with the
+//! `notify` crate. Once a new media file has stopped growing it is announced
+//! to every session as an unsolicited message, and the web app imports it
+//! into the folder's bin:
+//!
+//! ```text
+//! {"type":"watch_folder_file","folder_id":"…","path":"/…/clip.mp4","size":1234,
+//!  "bin":"Camera A","generate_proxy":true}
+//! ```
+//!
+//! Folders persist across restarts in:
+//! ```text
+//! {data_local_dir}/MasterSelects/watch-folders.json
+//! ```
+//!
+//! `scan_media_folder` walks a folder once and returns its media grouped by
+//! subfolder, so the web app can import a whole shoot with bins mirroring
+//! the directory tree instead of picking files one at a time.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::session::AppState;
+
+/// Extensions watched when a folder has no filter of its own
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "mp4", "mov", "m4v", "mkv", "webm", "avi", "mxf", "mts", "wav", "mp3", "flac", "aac", "m4a",
+    "ogg", "png", "jpg", "jpeg", "webp", "gif", "tif", "tiff", "exr",
+];
+
+/// Upper bound of files returned by one folder scan
+const MAX_SCAN_FILES: usize = 20_000;
+
+/// A file is announced once its size has not changed for this long
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const SETTLE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WatchFolder {
+    pub folder_id: String,
+    pub path: PathBuf,
+    /// Lowercase extensions without the dot; empty means the default media set
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Media-panel bin new files are imported into
+    #[serde(default)]
+    pub bin: Option<String>,
+    #[serde(default)]
+    pub recursive: bool,
+    #[serde(default)]
+    pub generate_proxies: bool,
+}
+
+impl WatchFolder {
+    /// Whether `path` is a file this folder should import.
+    fn matches(&self, path: &Path) -> bool {
+        let within = if self.recursive {
+            path.starts_with(&self.path)
+        } else {
+            path.parent() == Some(self.path.as_path())
+        };
+        if !within {
+            return false;
+        }
+
+        !is_hidden(path) && has_extension(path, &self.extensions)
+    }
+
+    fn recursive_mode(&self) -> RecursiveMode {
+        if self.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+}
+
+/// Hidden files and editor/OS temp files
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_none_or(|name| name.starts_with('.') || name.starts_with("~$"))
+}
+
+/// Whether `path` has one of `extensions` (the default media set when empty).
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+        return false;
+    };
+    let ext = ext.to_ascii_lowercase();
+    if extensions.is_empty() {
+        DEFAULT_EXTENSIONS.contains(&ext.as_str())
+    } else {
+        extensions.contains(&ext)
+    }
+}
+
+/// A media file found by `scan_media_folder`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ScannedFile {
+    pub path: PathBuf,
+    /// Bin path: the scanned folder's name followed by its subfolders
+    pub bin: Vec<String>,
+    pub size: u64,
+}
+
+/// Result of `scan_media_folder`
+#[derive(Debug, Default, Serialize)]
+pub struct FolderScan {
+    pub files: Vec<ScannedFile>,
+    /// Files that are not media (or not in the extension filter)
+    pub skipped: Vec<PathBuf>,
+    /// The scan stopped at `MAX_SCAN_FILES`
+    pub truncated: bool,
+}
+
+/// Walk `root` (and its subfolders when `recursive`) for media files.
+/// Hidden entries are ignored and symlinked folders are not followed.
+pub fn scan_folder(root: &Path, recursive: bool, extensions: &[String]) -> std::io::Result<FolderScan> {
+    let root_name = root
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| root.display().to_string());
+    let mut scan = FolderScan::default();
+    let mut pending = vec![(root.to_path_buf(), vec![root_name])];
+
+    while let Some((dir, bin)) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // Only the root folder has to be readable
+            Err(e) if dir == root => return Err(e),
+            Err(e) => {
+                warn!("Skipping unreadable folder {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let mut entries: Vec<_> = entries.flatten().collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        // Visit subfolders in name order after this folder's files
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if is_hidden(&path) {
+                continue;
+            }
+            if file_type.is_dir() {
+                if recursive {
+                    let mut sub_bin = bin.clone();
+                    sub_bin.push(entry.file_name().to_string_lossy().to_string());
+                    subdirs.push((path, sub_bin));
+                }
+            } else if !has_extension(&path, extensions) {
+                scan.skipped.push(path);
+            } else if scan.files.len() >= MAX_SCAN_FILES {
+                scan.truncated = true;
+                return Ok(scan);
+            } else {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                scan.files.push(ScannedFile {
+                    path,
+                    bin: bin.clone(),
+                    size,
+                });
+            }
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    Ok(scan)
+}
+
+/// Normalize a user-supplied extension filter (`".MP4"` -> `"mp4"`).
+pub fn normalize_extensions(extensions: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = extensions
+        .iter()
+        .map(|e| e.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+/// Return the path of the watch folder config file.
+pub fn get_config_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("watch-folders.json")
+}
+
+#[derive(Default)]
+pub struct WatchFolders {
+    folders: Mutex<Vec<WatchFolder>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl WatchFolders {
+    /// Load the saved folders; watching starts with [`start`].
+    pub fn load() -> Self {
+        let folders = std::fs::read_to_string(get_config_path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self {
+            folders: Mutex::new(folders),
+            watcher: Mutex::new(None),
+        }
+    }
+
+    pub fn list(&self) -> Vec<WatchFolder> {
+        self.folders.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn save(&self, folders: &[WatchFolder]) {
+        let path = get_config_path();
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        match serde_json::to_string_pretty(folders) {
+            Ok(json) => {
+                if let Err(e) = std::fs::write(&path, json) {
+                    warn!("Cannot save watch folders to {}: {}", path.display(), e);
+                }
+            }
+            Err(e) => warn!("Cannot serialize watch folders: {}", e),
+        }
+    }
+
+    fn watch(&self, folder: &WatchFolder) -> Result<(), String> {
+        let mut watcher = self.watcher.lock().unwrap_or_else(|e| e.into_inner());
+        match watcher.as_mut() {
+            Some(watcher) => watcher
+                .watch(&folder.path, folder.recursive_mode())
+                .map_err(|e| format!("Cannot watch {}: {}", folder.path.display(), e)),
+            // Not started yet; `start` picks the folder up
+            None => Ok(()),
+        }
+    }
+
+    /// Add a folder (replacing one with the same path) and start watching it.
+    pub fn add(&self, folder: WatchFolder) -> Result<WatchFolder, String> {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(existing) = folders.iter().position(|f| f.path == folder.path) {
+            let old = folders.remove(existing);
+            self.unwatch(&old);
+        }
+        self.watch(&folder)?;
+        info!("Watching folder {}", folder.path.display());
+        folders.push(folder.clone());
+        self.save(&folders);
+        Ok(folder)
+    }
+
+    pub fn remove(&self, folder_id: &str) -> Option<WatchFolder> {
+        let mut folders = self.folders.lock().unwrap_or_else(|e| e.into_inner());
+        let index = folders.iter().position(|f| f.folder_id == folder_id)?;
+        let folder = folders.remove(index);
+        self.unwatch(&folder);
+        info!("Stopped watching folder {}", folder.path.display());
+        self.save(&folders);
+        Some(folder)
+    }
+
+    fn unwatch(&self, folder: &WatchFolder) {
+        if let Some(watcher) = self.watcher.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            let _ = watcher.unwatch(&folder.path);
+        }
+    }
+
+    /// The folder a new file belongs to, if any.
+    fn folder_for(&self, path: &Path) -> Option<WatchFolder> {
+        self.folders
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|f| f.matches(path))
+            .cloned()
+    }
+}
+
+/// Start the file watcher and the task that announces settled files.
+pub fn start(state: Arc<AppState>) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<PathBuf>();
+
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+            for path in event.paths {
+                let _ = tx.send(path);
+            }
+        }
+    });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Watch folders disabled: {}", e);
+            return;
+        }
+    };
+    *state
+        .watch_folders
+        .watcher
+        .lock()
+        .unwrap_or_else(|e| e.into_inner()) = Some(watcher);
+
+    for folder in state.watch_folders.list() {
+        match state.watch_folders.watch(&folder) {
+            Ok(()) => info!("Watching folder {}", folder.path.display()),
+            Err(e) => warn!("{}", e),
+        }
+    }
+
+    tokio::spawn(async move {
+        let mut pending = Pending::default();
+        let mut tick = tokio::time::interval(SETTLE_CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                path = rx.recv() => match path {
+                    Some(path) => pending.touch(path),
+                    None => break,
+                },
+                _ = tick.tick() => {
+                    for (path, size) in pending.settled() {
+                        announce(&state, &path, size).await;
+                    }
+                }
+            }
+        }
+    });
+}
+
+async fn announce(state: &AppState, path: &Path, size: u64) {
+    let Some(folder) = state.watch_folders.folder_for(path) else {
+        return;
+    };
+    info!("Watch folder picked up {}", path.display());
+    let message = serde_json::json!({
+        "type": "watch_folder_file",
+        "folder_id": folder.folder_id,
+        "path": path,
+        "size": size,
+        "bin": folder.bin,
+        "generate_proxy": folder.generate_proxies,
+    });
+    state.broadcast(&message.to_string(), None).await;
+}
+
+/// Files that changed recently, announced once their size stops changing
+#[derive(Default)]
+struct Pending {
+    files: HashMap<PathBuf, (u64, Instant)>,
+    announced: HashSet<PathBuf>,
+}
+
+impl Pending {
+    fn touch(&mut self, path: PathBuf) {
+        if self.announced.contains(&path) {
+            return;
+        }
+        let size = self.files.get(&path).map(|(size, _)| *size).unwrap_or(u64::MAX);
+        self.files.insert(path, (size, Instant::now()));
+    }
+
+    /// Remove and return files whose size has been stable for `SETTLE_TIME`.
+    fn settled(&mut self) -> Vec<(PathBuf, u64)> {
+        let mut settled = Vec::new();
+        self.files.retain(|path, (size, since)| {
+            let current = match std::fs::metadata(path) {
+                Ok(meta) if meta.is_file() => meta.len(),
+                // Deleted, renamed away, or a directory
+                _ => return false,
+            };
+            if current != *size {
+                *size = current;
+                *since = Instant::now();
+                true
+            } else if since.elapsed() >= SETTLE_TIME && current > 0 {
+                settled.push((path.clone(), current));
+                false
+            } else {
+                true
+            }
+        });
+        self.announced.extend(settled.iter().map(|(path, _)| path.clone()));
+        settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(recursive: bool, extensions: &[&str]) -> WatchFolder {
+        WatchFolder {
+            folder_id: "f".to_string(),
+            path: PathBuf::from("/media/ingest"),
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            bin: None,
+            recursive,
+            generate_proxies: false,
+        }
+    }
+
+    #[test]
+    fn test_folder_matches() {
+        let flat = folder(false, &[]);
+        assert!(flat.matches(Path::new("/media/ingest/clip.MOV")));
+        assert!(!flat.matches(Path::new("/media/ingest/day1/clip.mov")));
+        assert!(!flat.matches(Path::new("/media/ingest/.clip.mov")));
+        assert!(!flat.matches(Path::new("/media/ingest/clip.mov.part")));
+        assert!(!flat.matches(Path::new("/media/other/clip.mov")));
+
+        let filtered = folder(true, &["wav"]);
+        assert!(filtered.matches(Path::new("/media/ingest/day1/take.wav")));
+        assert!(!filtered.matches(Path::new("/media/ingest/day1/clip.mov")));
+    }
+
+    #[test]
+    fn test_scan_folder() {
+        let root = std::env::temp_dir().join(format!("ms-scan-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(root.join("CamA/day1")).unwrap();
+        std::fs::create_dir_all(root.join(".cache")).unwrap();
+        for file in ["a.mov", "notes.txt", "CamA/b.MP4", "CamA/day1/c.wav", ".cache/d.mov"] {
+            std::fs::write(root.join(file), b"x").unwrap();
+        }
+        let name = root.file_name().unwrap().to_string_lossy().to_string();
+
+        let scan = scan_folder(&root, true, &[]).unwrap();
+        let found: Vec<_> = scan.files.iter().map(|f| (f.path.clone(), f.bin.join("/"))).collect();
+        assert_eq!(
+            found,
+            vec![
+                (root.join("a.mov"), name.clone()),
+                (root.join("CamA/b.MP4"), format!("{}/CamA", name)),
+                (root.join("CamA/day1/c.wav"), format!("{}/CamA/day1", name)),
+            ]
+        );
+        assert_eq!(scan.skipped, vec![root.join("notes.txt")]);
+        assert!(!scan.truncated);
+
+        let flat = scan_folder(&root, false, &["wav".to_string()]).unwrap();
+        assert!(flat.files.is_empty());
+        assert_eq!(flat.skipped.len(), 2);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_normalize_extensions() {
+        let input = vec![".MP4".to_string(), " mov ".to_string(), "mp4".to_string(), "".to_string()];
+        assert_eq!(normalize_extensions(&input), vec!["mov", "mp4"]);
+    }
+}
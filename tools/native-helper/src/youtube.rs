@@ -0,0 +1,659 @@
+//! Publishing rendered videos to YouTube
+//!
+//! Sign-in uses the OAuth 2.0 device flow, so no browser redirect back to the
+//! helper is needed: the web app shows the user code, the user confirms it at
+//! google.com/device, and the helper polls for the token. The OAuth client
+//! ("TVs and Limited Input devices" type) is configured through
+//! `MASTERSELECTS_YOUTUBE_CLIENT_ID` / `MASTERSELECTS_YOUTUBE_CLIENT_SECRET`.
+//!
+//! Uploads use the resumable upload protocol in 8 MiB chunks; a failed chunk
+//! is retried from the offset the server reports.
+//!
+//! Storage layout:
+//! ```text
+//! {data_local_dir}/MasterSelects/youtube/
+//! └── token.json   (refresh token, owner-only permissions)
+//! ```
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::jobs::JobReporter;
+use crate::protocol::{error_codes, job_stages, JobProgress, Response};
+
+const CLIENT_ID_ENV: &str = "MASTERSELECTS_YOUTUBE_CLIENT_ID";
+const CLIENT_SECRET_ENV: &str = "MASTERSELECTS_YOUTUBE_CLIENT_SECRET";
+
+const DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const REVOKE_URL: &str = "https://oauth2.googleapis.com/revoke";
+const UPLOAD_URL: &str =
+    "https://www.googleapis.com/upload/youtube/v3/videos?uploadType=resumable&part=snippet,status";
+const SCOPE: &str = "https://www.googleapis.com/auth/youtube.upload";
+const USER_AGENT: &str = "MasterSelects-Helper";
+
+/// Upload chunk size; the API requires a multiple of 256 KiB
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_CHUNK_RETRIES: u32 = 5;
+/// Refresh the access token this long before it expires
+const EXPIRY_MARGIN_SECS: u64 = 60;
+
+struct ClientCredentials {
+    id: String,
+    secret: String,
+}
+
+fn credentials() -> Option<ClientCredentials> {
+    let id = std::env::var(CLIENT_ID_ENV).ok().filter(|v| !v.is_empty())?;
+    let secret = std::env::var(CLIENT_SECRET_ENV).ok().filter(|v| !v.is_empty())?;
+    Some(ClientCredentials { id, secret })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredToken {
+    refresh_token: String,
+    access_token: String,
+    /// Unix seconds
+    expires_at: u64,
+}
+
+/// Return the directory holding the YouTube token.
+pub fn get_youtube_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("MasterSelects")
+        .join("youtube")
+}
+
+fn token_path() -> PathBuf {
+    get_youtube_dir().join("token.json")
+}
+
+fn load_token() -> Option<StoredToken> {
+    let json = std::fs::read_to_string(token_path()).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+fn save_token(token: &StoredToken) -> Result<(), String> {
+    let dir = get_youtube_dir();
+    std::fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    let path = token_path();
+    let json = serde_json::to_string_pretty(token).map_err(|e| e.to_string())?;
+    write_private(&path, &json).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Write `contents` to a new temp file only the user can read, then move it
+/// to `path`, so the refresh token is never readable by others.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    // A leftover temp file may have other permissions; start from a new one
+    let _ = std::fs::remove_file(&tmp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn agent() -> ureq::Agent {
+    // Resumable uploads answer 308 without a Location header; never follow it
+    ureq::AgentBuilder::new()
+        .redirects(0)
+        .timeout_connect(Duration::from_secs(15))
+        .timeout_read(Duration::from_secs(120))
+        .user_agent(USER_AGENT)
+        .build()
+}
+
+/// Status code and JSON body of a response, including 4xx/5xx answers.
+fn read_json(result: Result<ureq::Response, ureq::Error>) -> Result<(u16, serde_json::Value), String> {
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(ureq::Error::Status(_, resp)) => resp,
+        Err(e) => return Err(e.to_string()),
+    };
+    let status = resp.status();
+    let body = resp.into_string().map_err(|e| e.to_string())?;
+    Ok((status, serde_json::from_str(&body).unwrap_or_default()))
+}
+
+fn oauth_error(body: &serde_json::Value) -> String {
+    let error = body["error"].as_str().unwrap_or("unknown_error");
+    match body["error_description"].as_str() {
+        Some(description) => format!("{}: {}", error, description),
+        None => error.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_url: String,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+fn request_device_code(creds: &ClientCredentials) -> Result<DeviceCode, String> {
+    let (status, body) =
+        read_json(agent().post(DEVICE_CODE_URL).send_form(&[("client_id", &creds.id), ("scope", SCOPE)]))?;
+    if status != 200 {
+        return Err(format!("Device code request failed: {}", oauth_error(&body)));
+    }
+    serde_json::from_value(body).map_err(|e| format!("Invalid device code response: {}", e))
+}
+
+enum PollResult {
+    Pending,
+    SlowDown,
+    Authorized(StoredToken),
+}
+
+fn token_from_response(body: &serde_json::Value, refresh_token: Option<&str>) -> Result<StoredToken, String> {
+    let access_token = body["access_token"]
+        .as_str()
+        .ok_or("Token response has no access_token")?;
+    let refresh_token = body["refresh_token"]
+        .as_str()
+        .or(refresh_token)
+        .ok_or("Token response has no refresh_token")?;
+    Ok(StoredToken {
+        refresh_token: refresh_token.to_string(),
+        access_token: access_token.to_string(),
+        expires_at: now_secs() + body["expires_in"].as_u64().unwrap_or(3600),
+    })
+}
+
+fn poll_token(creds: &ClientCredentials, device_code: &str) -> Result<PollResult, String> {
+    let (status, body) = read_json(agent().post(TOKEN_URL).send_form(&[
+        ("client_id", &creds.id),
+        ("client_secret", &creds.secret),
+        ("device_code", device_code),
+        ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+    ]))?;
+    if status == 200 {
+        return token_from_response(&body, None).map(PollResult::Authorized);
+    }
+    match body["error"].as_str() {
+        Some("authorization_pending") => Ok(PollResult::Pending),
+        Some("slow_down") => Ok(PollResult::SlowDown),
+        _ => Err(format!("Sign-in failed: {}", oauth_error(&body))),
+    }
+}
+
+fn refresh_token(creds: &ClientCredentials, token: &StoredToken) -> Result<StoredToken, String> {
+    let (status, body) = read_json(agent().post(TOKEN_URL).send_form(&[
+        ("client_id", &creds.id),
+        ("client_secret", &creds.secret),
+        ("refresh_token", &token.refresh_token),
+        ("grant_type", "refresh_token"),
+    ]))?;
+    if status != 200 {
+        return Err(format!("Token refresh failed: {}", oauth_error(&body)));
+    }
+    token_from_response(&body, Some(&token.refresh_token))
+}
+
+/// A valid access token, refreshed when close to expiry.
+fn access_token(creds: &ClientCredentials) -> Result<String, String> {
+    let token = load_token().ok_or("Not signed in to YouTube")?;
+    if token.expires_at > now_secs() + EXPIRY_MARGIN_SECS {
+        return Ok(token.access_token);
+    }
+    let refreshed = refresh_token(creds, &token)?;
+    save_token(&refreshed)?;
+    Ok(refreshed.access_token)
+}
+
+// ── Metadata ──
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Privacy {
+    #[default]
+    Private,
+    Unlisted,
+    Public,
+}
+
+/// A timeline marker published as a YouTube chapter
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chapter {
+    /// Seconds from the start of the video
+    pub time: f64,
+    pub title: String,
+}
+
+/// `H:MM:SS` or `M:SS`, as YouTube expects in chapter lists
+fn chapter_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (h, m, s) = (total / 3600, total / 60 % 60, total % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+/// Append chapters to the description. YouTube only shows chapters when the
+/// list starts at 0:00, so an "Intro" chapter is added when needed.
+fn description_with_chapters(description: &str, chapters: &[Chapter]) -> String {
+    if chapters.is_empty() {
+        return description.to_string();
+    }
+    let mut chapters: Vec<&Chapter> = chapters.iter().collect();
+    chapters.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut lines = Vec::new();
+    if chapters[0].time >= 1.0 {
+        lines.push("0:00 Intro".to_string());
+    }
+    for chapter in chapters {
+        lines.push(format!("{} {}", chapter_timestamp(chapter.time), chapter.title.trim()));
+    }
+
+    let description = description.trim_end();
+    if description.is_empty() {
+        lines.join("\n")
+    } else {
+        format!("{}\n\n{}", description, lines.join("\n"))
+    }
+}
+
+fn video_resource(
+    title: &str,
+    description: &str,
+    tags: &[String],
+    privacy: Privacy,
+    chapters: &[Chapter],
+) -> serde_json::Value {
+    serde_json::json!({
+        "snippet": {
+            "title": title,
+            "description": description_with_chapters(description, chapters),
+            "tags": tags,
+        },
+        "status": {
+            "privacyStatus": privacy,
+            "selfDeclaredMadeForKids": false,
+        },
+    })
+}
+
+// ── Upload ──
+
+enum ChunkResult {
+    /// Server has bytes up to (excluding) this offset
+    Incomplete(u64),
+    Done(String),
+}
+
+fn mime_for(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("mov") => "video/quicktime",
+        Some("webm") => "video/webm",
+        Some("mkv") => "video/x-matroska",
+        _ => "video/mp4",
+    }
+}
+
+fn start_upload(token: &str, resource: &serde_json::Value, total: u64, mime: &str) -> Result<String, String> {
+    let resp = agent()
+        .post(UPLOAD_URL)
+        .set("Authorization", &format!("Bearer {}", token))
+        .set("X-Upload-Content-Length", &total.to_string())
+        .set("X-Upload-Content-Type", mime)
+        .set("Content-Type", "application/json; charset=UTF-8")
+        .send_string(&resource.to_string());
+    match resp {
+        Ok(resp) => resp
+            .header("location")
+            .map(str::to_string)
+            .ok_or_else(|| "Upload session has no location".to_string()),
+        Err(ureq::Error::Status(code, resp)) => {
+            let body = resp.into_string().unwrap_or_default();
+            Err(format!("Upload rejected ({}): {}", code, api_error(&body)))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn api_error(body: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.chars().take(200).collect())
+}
+
+/// Offset following the last byte in a `Range: bytes=0-N` header
+fn next_offset(range: Option<&str>) -> u64 {
+    range
+        .and_then(|r| r.rsplit('-').next())
+        .and_then(|end| end.trim().parse::<u64>().ok())
+        .map(|end| end + 1)
+        .unwrap_or(0)
+}
+
+fn chunk_result(result: Result<ureq::Response, ureq::Error>) -> Result<ChunkResult, String> {
+    match result {
+        Ok(resp) if resp.status() == 308 => Ok(ChunkResult::Incomplete(next_offset(resp.header("range")))),
+        Ok(resp) => {
+            let body: serde_json::Value =
+                serde_json::from_str(&resp.into_string().map_err(|e| e.to_string())?).unwrap_or_default();
+            body["id"]
+                .as_str()
+                .map(|id| ChunkResult::Done(id.to_string()))
+                .ok_or_else(|| "Upload finished without a video id".to_string())
+        }
+        Err(ureq::Error::Status(code, resp)) => {
+            let body = resp.into_string().unwrap_or_default();
+            Err(format!("Upload failed ({}): {}", code, api_error(&body)))
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn put_chunk(session_url: &str, token: &str, path: &Path, offset: u64, total: u64) -> Result<ChunkResult, String> {
+    let len = CHUNK_SIZE.min(total - offset);
+    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+    let mut buf = vec![0u8; len as usize];
+    file.read_exact(&mut buf).map_err(|e| e.to_string())?;
+
+    chunk_result(
+        agent()
+            .put(session_url)
+            .set("Authorization", &format!("Bearer {}", token))
+            .set(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", offset, offset + len - 1, total),
+            )
+            .send_bytes(&buf),
+    )
+}
+
+/// Ask the server how much of an interrupted upload it has.
+fn query_offset(session_url: &str, token: &str, total: u64) -> Result<ChunkResult, String> {
+    chunk_result(
+        agent()
+            .put(session_url)
+            .set("Authorization", &format!("Bearer {}", token))
+            .set("Content-Range", &format!("bytes */{}", total))
+            .send_bytes(&[]),
+    )
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Task failed: {}", e))?
+}
+
+fn not_configured() -> String {
+    format!(
+        "YouTube publishing is not configured. Set {} and {} for the helper.",
+        CLIENT_ID_ENV, CLIENT_SECRET_ENV
+    )
+}
+
+// ── Handlers ──
+
+/// `youtube_status`: whether publishing is configured and signed in.
+pub fn handle_status(id: &str) -> Response {
+    Response::ok(
+        id,
+        serde_json::json!({
+            "configured": credentials().is_some(),
+            "signed_in": load_token().is_some(),
+        }),
+    )
+}
+
+/// `youtube_logout`: revoke and forget the stored token.
+pub async fn handle_logout(id: &str) -> Response {
+    if let Some(token) = load_token() {
+        let revoke = blocking(move || {
+            read_json(agent().post(REVOKE_URL).send_form(&[("token", &token.refresh_token)])).map(|_| ())
+        })
+        .await;
+        if let Err(e) = revoke {
+            warn!("Token revocation failed: {}", e);
+        }
+    }
+    match std::fs::remove_file(token_path()) {
+        Ok(()) => info!("Signed out of YouTube"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Response::error(id, error_codes::WRITE_FAILED, format!("Cannot remove token: {}", e))
+        }
+    }
+    Response::ok(id, serde_json::json!({ "signed_in": false }))
+}
+
+/// `youtube_login` job: device-flow sign-in. The `awaiting_authorization`
+/// progress event carries the code the user enters at `verification_url`.
+pub async fn handle_login(reporter: &JobReporter, cancel: &CancellationToken) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+
+    if credentials().is_none() {
+        return fail(error_codes::YOUTUBE_NOT_CONFIGURED, not_configured());
+    }
+    let device = match blocking(|| request_device_code(&credentials().ok_or_else(not_configured)?)).await {
+        Ok(device) => device,
+        Err(e) => return fail(error_codes::YOUTUBE_AUTH_FAILED, e),
+    };
+
+    let mut prompt = serde_json::to_value(JobProgress::stage(job_stages::AWAITING_AUTHORIZATION, 0.0))
+        .unwrap_or_default();
+    prompt["type"] = serde_json::json!("progress");
+    prompt["user_code"] = serde_json::json!(device.user_code);
+    prompt["verification_url"] = serde_json::json!(device.verification_url);
+    prompt["expires_in"] = serde_json::json!(device.expires_in);
+    reporter.send(&Response::ok(id, prompt).with_job_id(job_id)).await;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(device.expires_in);
+    let mut interval = device.interval.max(1);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            _ = cancel.cancelled() => {
+                return fail(error_codes::CANCELLED, "Sign-in cancelled".to_string());
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return fail(error_codes::YOUTUBE_AUTH_FAILED, "Sign-in code expired".to_string());
+        }
+
+        let device_code = device.device_code.clone();
+        let poll = blocking(move || poll_token(&credentials().ok_or_else(not_configured)?, &device_code)).await;
+        match poll {
+            Ok(PollResult::Pending) => {}
+            Ok(PollResult::SlowDown) => interval += 5,
+            Ok(PollResult::Authorized(token)) => {
+                if let Err(e) = save_token(&token) {
+                    return fail(error_codes::WRITE_FAILED, e);
+                }
+                info!("Signed in to YouTube");
+                return Response::job_complete(id, job_id, serde_json::json!({ "signed_in": true }));
+            }
+            Err(e) => return fail(error_codes::YOUTUBE_AUTH_FAILED, e),
+        }
+    }
+}
+
+/// `youtube_upload` job: resumable upload of a rendered file.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_upload(
+    path: &Path,
+    title: &str,
+    description: &str,
+    tags: &[String],
+    privacy: Privacy,
+    chapters: &[Chapter],
+    reporter: &JobReporter,
+    cancel: &CancellationToken,
+) -> Response {
+    let id = reporter.id.as_str();
+    let job_id = reporter.job_id.as_str();
+    let fail = |code: &str, message: String| Response::error(id, code, message).with_job_id(job_id);
+
+    if credentials().is_none() {
+        return fail(error_codes::YOUTUBE_NOT_CONFIGURED, not_configured());
+    }
+    if load_token().is_none() {
+        return fail(error_codes::YOUTUBE_AUTH_REQUIRED, "Not signed in to YouTube".to_string());
+    }
+    let total = match std::fs::metadata(path) {
+        Ok(meta) if meta.len() > 0 => meta.len(),
+        _ => return fail(error_codes::INVALID_PATH, format!("Cannot upload {}", path.display())),
+    };
+    let token = || access_token(&credentials().ok_or_else(not_configured)?);
+
+    let resource = video_resource(title, description, tags, privacy, chapters);
+    let mime = mime_for(path);
+    let session_url = match blocking(move || start_upload(&token()?, &resource, total, mime)).await {
+        Ok(url) => url,
+        Err(e) => return fail(error_codes::UPLOAD_FAILED, e),
+    };
+    info!("Uploading {} ({} bytes) to YouTube", path.display(), total);
+
+    let mut offset = 0u64;
+    let mut retries = 0u32;
+    loop {
+        let mut progress =
+            JobProgress::stage(job_stages::UPLOADING, (offset as f64 / total as f64 * 100.0) as f32);
+        progress.bytes_done = Some(offset);
+        progress.bytes_total = Some(total);
+        reporter.progress(&progress).await;
+
+        if cancel.is_cancelled() {
+            return fail(error_codes::CANCELLED, "Upload cancelled".to_string());
+        }
+
+        let (url, file) = (session_url.clone(), path.to_path_buf());
+        let result = if retries == 0 {
+            blocking(move || put_chunk(&url, &token()?, &file, offset, total)).await
+        } else {
+            blocking(move || query_offset(&url, &token()?, total)).await
+        };
+
+        match result {
+            Ok(ChunkResult::Incomplete(next)) => {
+                offset = next;
+                retries = 0;
+            }
+            Ok(ChunkResult::Done(video_id)) => {
+                info!("YouTube upload complete: {}", video_id);
+                return Response::job_complete(
+                    id,
+                    job_id,
+                    serde_json::json!({
+                        "video_id": video_id,
+                        "url": format!("https://youtu.be/{}", video_id),
+                    }),
+                );
+            }
+            Err(e) if retries < MAX_CHUNK_RETRIES => {
+                retries += 1;
+                warn!("Upload chunk failed (attempt {}): {}", retries, e);
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(1 << retries)) => {}
+                    _ = cancel.cancelled() => {}
+                }
+            }
+            Err(e) => return fail(error_codes::UPLOAD_FAILED, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(time: f64, title: &str) -> Chapter {
+        Chapter {
+            time,
+            title: title.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_description_with_chapters() {
+        assert_eq!(description_with_chapters("Hello", &[]), "Hello");
+
+        let chapters = [chapter(3725.0, "Outro"), chapter(75.5, "Main part")];
+        assert_eq!(
+            description_with_chapters("Hello\n", &chapters),
+            "Hello\n\n0:00 Intro\n1:15 Main part\n1:02:05 Outro"
+        );
+
+        let chapters = [chapter(0.0, "Start"), chapter(30.0, "Next")];
+        assert_eq!(description_with_chapters("", &chapters), "0:00 Start\n0:30 Next");
+    }
+
+    #[test]
+    fn test_next_offset() {
+        assert_eq!(next_offset(Some("bytes=0-8388607")), 8388608);
+        assert_eq!(next_offset(None), 0);
+    }
+
+    #[test]
+    fn test_video_resource() {
+        let resource = video_resource("Title", "", &["tag".to_string()], Privacy::Unlisted, &[]);
+        assert_eq!(resource["status"]["privacyStatus"], "unlisted");
+        assert_eq!(resource["snippet"]["tags"][0], "tag");
+    }
+
+    #[test]
+    fn test_write_private() {
+        let dir = std::env::temp_dir().join(format!("ms-yt-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("token.json");
+        write_private(&path, "{}").unwrap();
+        write_private(&path, "{\"refresh_token\":\"x\"}").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"refresh_token\":\"x\"}");
+        assert!(!path.with_extension("tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}